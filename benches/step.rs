@@ -0,0 +1,102 @@
+//! Compares running instructions through `State::step`, which consumes the
+//! state and returns a new one, with `State::execute`, which updates it in place,
+//! with `State::execute` decoding every instruction again without the cache,
+//! and with the basic blocks of `BlockEngine` run a frame of instructions at a time.
+//!
+//! Run with `cargo bench --bench step`.
+use std::time::{Duration, Instant};
+use lib_chip::engine::BlockEngine;
+use lib_chip::keypad::Keypad;
+use lib_chip::memory::Memory;
+use lib_chip::state::State;
+
+const INSTRUCTIONS: u32 = 2_000_000;
+/// The instructions the block engine is given at once, a frame at 600 a second
+const RUN: u64 = 10;
+
+/// A loop of register, memory, call and draw instructions that never exits.
+///
+/// ```text
+/// 0x200: LD V0, 0x01
+/// 0x202: ADD V1, V0
+/// 0x204: SUB V2, V1
+/// 0x206: LD I, 0x300
+/// 0x208: LD [I], V2
+/// 0x20A: CALL 0x210
+/// 0x20C: DRW V1, V2, 1
+/// 0x20E: JP 0x200
+/// 0x210: SE V0, 0x01
+/// 0x212: LD V3, 0xFF
+/// 0x214: RET
+/// ```
+const PROGRAM: [u8; 22] = [
+    0x60, 0x01, 0x81, 0x04, 0x82, 0x15, 0xA3, 0x00, 0xF2, 0x55, 0x22, 0x10,
+    0xD1, 0x21, 0x12, 0x00, 0x30, 0x01, 0x63, 0xFF, 0x00, 0xEE,
+];
+
+fn setup(decode_cache: bool) -> (State, Memory) {
+    let mut memory = Memory::new();
+    memory.set_range(0x200, &PROGRAM);
+    memory.set_decode_cache(decode_cache);
+    (Default::default(), memory)
+}
+
+fn by_value() -> Duration {
+    let (mut state, mut memory) = setup(true);
+    let mut screen = state.create_buffer();
+    let mut keypad = Keypad::new();
+
+    let start = Instant::now();
+    for _ in 0..INSTRUCTIONS {
+        state = state.step(&mut memory, &mut keypad, &mut screen).unwrap();
+    }
+    start.elapsed()
+}
+
+fn in_place(decode_cache: bool) -> Duration {
+    let (mut state, mut memory) = setup(decode_cache);
+    let mut screen = state.create_buffer();
+    let mut keypad = Keypad::new();
+
+    let start = Instant::now();
+    for _ in 0..INSTRUCTIONS {
+        state.execute(&mut memory, &mut keypad, &mut screen).unwrap();
+    }
+    start.elapsed()
+}
+
+fn blocks() -> Duration {
+    let (mut state, mut memory) = setup(true);
+    let mut screen = state.create_buffer();
+    let mut keypad = Keypad::new();
+    let mut engine = BlockEngine::new();
+
+    let start = Instant::now();
+    let mut ran = 0;
+    while ran < u64::from(INSTRUCTIONS) {
+        ran += engine.run(&mut state, &mut memory, &mut keypad, &mut screen, RUN).unwrap();
+    }
+    start.elapsed()
+}
+
+fn report(name: &str, elapsed: Duration) -> f64 {
+    let per_second = f64::from(INSTRUCTIONS) / elapsed.as_secs_f64();
+    println!("{:<10} {:>10.2?} {:>14.0} instructions/s", name, elapsed, per_second);
+    per_second
+}
+
+fn main() {
+    // warm up the caches before any are timed
+    by_value();
+    in_place(false);
+    in_place(true);
+    blocks();
+
+    let by_value = report("step", by_value());
+    let uncached = report("uncached", in_place(false));
+    let in_place = report("execute", in_place(true));
+    let blocks = report("blocks", blocks());
+    println!("execute runs {:.2}x as many instructions a second as step", in_place / by_value);
+    println!("the decode cache runs {:.2}x as many instructions a second", in_place / uncached);
+    println!("the block engine runs {:.2}x as many instructions a second as execute", blocks / in_place);
+}
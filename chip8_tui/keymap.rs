@@ -0,0 +1,82 @@
+//! Maps the keys of a QWERTY keyboard onto the hex keypad.
+//!
+//! A layout is 16 characters read row by row across the 4x4 keypad of the
+//! COSMAC VIP:
+//!
+//! ```text
+//! 1 2 3 C        1 2 3 4
+//! 4 5 6 D   <-   q w e r
+//! 7 8 9 E        a s d f
+//! A 0 B F        z x c v
+//! ```
+
+/// The keypad keys in the order a layout lists them
+const KEYPAD_ORDER: [u8; 16] = [
+    0x1, 0x2, 0x3, 0xC,
+    0x4, 0x5, 0x6, 0xD,
+    0x7, 0x8, 0x9, 0xE,
+    0xA, 0x0, 0xB, 0xF,
+];
+
+/// The layout used unless another is given
+pub const DEFAULT_LAYOUT: &str = "1234qwerasdfzxcv";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyMap {
+    /// The keyboard character for each keypad key, indexed by key
+    keys: [char; 16],
+}
+
+impl Default for KeyMap {
+    fn default() -> Self {
+        KeyMap::parse(DEFAULT_LAYOUT).unwrap_or(KeyMap { keys: ['\0'; 16] })
+    }
+}
+
+impl KeyMap {
+    /// Reads a layout of 16 distinct characters, ignoring case.
+    pub fn parse(layout: &str) -> Result<KeyMap, String> {
+        let chars: Vec<char> = layout.chars().map(|c| c.to_ascii_lowercase()).collect();
+        if chars.len() != 16 {
+            return Err(format!("a layout needs 16 keys, {} were given", chars.len()));
+        }
+
+        let mut keys = ['\0'; 16];
+        for (position, c) in chars.iter().enumerate() {
+            if chars[..position].contains(c) {
+                return Err(format!("'{}' is used for more than one key", c));
+            }
+            keys[usize::from(KEYPAD_ORDER[position])] = *c;
+        }
+
+        Ok(KeyMap { keys })
+    }
+
+    /// Returns the keypad key a keyboard character is mapped to
+    pub fn key(&self, c: char) -> Option<u8> {
+        let c = c.to_ascii_lowercase();
+        self.keys.iter().position(|k| *k == c).map(|key| key as u8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_will_map_the_default_layout() {
+        let map = KeyMap::default();
+
+        assert_eq!(Some(0x1), map.key('1'));
+        assert_eq!(Some(0xC), map.key('4'));
+        assert_eq!(Some(0x0), map.key('X'));
+        assert_eq!(Some(0xF), map.key('v'));
+        assert_eq!(None, map.key('p'));
+    }
+
+    #[test]
+    fn it_will_reject_bad_layouts() {
+        assert!(KeyMap::parse("1234").is_err());
+        assert!(KeyMap::parse("1134qwerasdfzxcv").is_err());
+    }
+}
@@ -0,0 +1,317 @@
+//! Runs a chip8 rom in the terminal.
+//!
+//! ```text
+//! chip8-tui [--keys LAYOUT] [--ips N] [--lit COLOUR] [--unlit COLOUR] [--record MOVIE] [--engine ENGINE] [--quirks PROFILE] ROM
+//! ```
+//!
+//! Tab shows and hides the register panel, Ctrl-C quits.  Terminals only
+//! report key presses, so a key is held for a few frames after it is typed
+//! and kept down by the keyboard's auto repeat.
+//!
+//! `--record` writes the session to a movie when the frontend exits, even if
+//! the program failed, so it can be replayed with `lib_chip::movie`.
+//!
+//! `--engine` picks how instructions are run, `interpreter` or `blocks`.
+//!
+//! `--quirks` picks the platform the rom was written for, `default`,
+//! `cosmac-vip`, `schip` or `xo-chip`, which sets the quirks and, for
+//! XO-CHIP, the larger memory.
+mod keymap;
+mod render;
+mod terminal;
+
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::io::{self, Write};
+use std::process;
+use std::sync::mpsc::Receiver;
+use std::thread;
+use std::time::{Duration, Instant};
+use lib_chip::engine::Engine;
+use lib_chip::machine::{Config, Machine};
+use lib_chip::memory::{MEMORY_SIZE, XO_CHIP_MEMORY_SIZE};
+use lib_chip::movie::Recorder;
+use lib_chip::rom::Rom;
+use lib_chip::state::Quirks;
+use keymap::KeyMap;
+use render::Colours;
+use terminal::RawTerminal;
+
+/// The frames a key stays down after it was last typed
+const HOLD_FRAMES: u8 = 8;
+const FRAME: Duration = Duration::from_nanos(1_000_000_000 / 60);
+
+const CTRL_C: u8 = 0x03;
+const TAB: u8 = 0x09;
+
+const USAGE: &str = "usage: chip8-tui [--keys LAYOUT] [--ips N] [--lit COLOUR] [--unlit COLOUR] [--record MOVIE] [--engine ENGINE] [--quirks PROFILE] ROM";
+
+#[derive(Debug, PartialEq)]
+struct Options {
+    rom: String,
+    keymap: KeyMap,
+    instructions_per_second: Option<u32>,
+    colours: Colours,
+    record: Option<String>,
+    engine: Engine,
+    quirks: Quirks,
+    memory_size: usize,
+}
+
+fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<Options, String> {
+    let mut rom = None;
+    let mut keymap = KeyMap::default();
+    let mut instructions_per_second = None;
+    let mut colours = Colours::default();
+    let mut record = None;
+    let mut engine = Engine::default();
+    let mut profile = (Quirks::default(), MEMORY_SIZE);
+
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or(format!("{} needs a value", name));
+        match arg.as_str() {
+            "--keys" => keymap = KeyMap::parse(&value("--keys")?)?,
+            "--ips" => instructions_per_second = Some(number(&value("--ips")?)?),
+            "--lit" => colours.lit = number(&value("--lit")?)?,
+            "--unlit" => colours.unlit = number(&value("--unlit")?)?,
+            "--record" => record = Some(value("--record")?),
+            "--engine" => engine = parse_engine(&value("--engine")?)?,
+            "--quirks" => profile = parse_profile(&value("--quirks")?)?,
+            _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+            _ => rom = Some(arg)
+        }
+    }
+
+    let rom = rom.ok_or_else(|| "no rom was given".to_string())?;
+    let (quirks, memory_size) = profile;
+    Ok(Options { rom, keymap, instructions_per_second, colours, record, engine, quirks, memory_size })
+}
+
+/// Returns the quirks and memory size of a platform
+fn parse_profile(name: &str) -> Result<(Quirks, usize), String> {
+    match name {
+        "default" => Ok((Quirks::default(), MEMORY_SIZE)),
+        "cosmac-vip" => Ok((Quirks::cosmac_vip(), MEMORY_SIZE)),
+        "schip" => Ok((Quirks::schip(), MEMORY_SIZE)),
+        "xo-chip" => Ok((Quirks::xo_chip(), XO_CHIP_MEMORY_SIZE)),
+        _ => Err(format!("{} is not a quirks profile, use default, cosmac-vip, schip or xo-chip", name))
+    }
+}
+
+fn parse_engine(name: &str) -> Result<Engine, String> {
+    match name {
+        "interpreter" => Ok(Engine::Interpreter),
+        "blocks" => Ok(Engine::Blocks),
+        _ => Err(format!("{} is not an engine, use interpreter or blocks", name))
+    }
+}
+
+fn number<T: std::str::FromStr>(value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("{} is not a valid number", value))
+}
+
+/// The keys typed in the terminal, each held for a few frames
+struct Input {
+    keymap: KeyMap,
+    held: [u8; 16],
+    show_panel: bool,
+    quit: bool,
+}
+
+impl Input {
+    fn poll(&mut self, receiver: &Receiver<u8>) {
+        for held in self.held.iter_mut() {
+            *held = held.saturating_sub(1);
+        }
+
+        for byte in receiver.try_iter() {
+            match byte {
+                CTRL_C => self.quit = true,
+                TAB => self.show_panel = !self.show_panel,
+                _ => if let Some(key) = self.keymap.key(char::from(byte)) {
+                    self.held[usize::from(key)] = HOLD_FRAMES;
+                }
+            }
+        }
+    }
+
+    /// Returns the keys held down, one bit per key
+    fn pressed(&self) -> u16 {
+        self.held.iter().enumerate()
+            .filter(|(_, held)| **held > 0)
+            .fold(0, |set, (key, _)| set | 1 << key)
+    }
+}
+
+/// The layout last drawn on the terminal, a frame with a different layout is redrawn in full
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Layout {
+    width: u32,
+    height: u32,
+    show_panel: bool,
+}
+
+/// Draws the lines of the screen that changed, or all of them when `full` is set.
+fn draw(machine: &mut Machine, options: &Options, full: bool, show_panel: bool, bell: bool) -> io::Result<()> {
+    let screen = machine.screen();
+    let lines = if full { (0..render::line_count(screen)).collect() } else { render::dirty_lines(screen) };
+
+    let mut frame = String::new();
+    for line in lines {
+        frame.push_str(&format!("\x1b[{};1H", line + 1));
+        frame.push_str(&render::render_line(screen, line, options.colours));
+    }
+
+    frame.push_str(&format!("\x1b[{};1H", render::line_count(screen) + 1));
+    if show_panel {
+        for line in machine.state().to_string().lines() {
+            frame.push_str(line);
+            frame.push_str("\x1b[K\r\n");
+        }
+    }
+    if full {
+        frame.push_str("\x1b[J");
+    }
+    if bell {
+        frame.push('\x07');
+    }
+
+    let mut stdout = io::stdout();
+    stdout.write_all(frame.as_bytes())?;
+    stdout.flush()?;
+    machine.screen_mut().present();
+    Ok(())
+}
+
+fn run(options: Options) -> Result<(), Box<dyn Error>> {
+    let rom = Rom::load(&options.rom)?;
+    let mut config = Config {
+        quirks: options.quirks,
+        memory_size: options.memory_size,
+        engine: options.engine,
+        ..Default::default()
+    };
+    if let Some(ips) = options.instructions_per_second {
+        config.instructions_per_second = ips;
+    }
+    let mut machine = Machine::new(config);
+    machine.load_rom(&rom)?;
+
+    let mut recorder = options.record.as_ref().map(|_| Recorder::new(&mut machine));
+    let result = play(&mut machine, recorder.as_mut(), &options);
+
+    if let (Some(path), Some(recorder)) = (&options.record, recorder) {
+        fs::write(path, recorder.finish().to_bytes())?;
+    }
+    result
+}
+
+fn play(machine: &mut Machine, mut recorder: Option<&mut Recorder>, options: &Options) -> Result<(), Box<dyn Error>> {
+    let _terminal = RawTerminal::enter()?;
+    let receiver = terminal::spawn_reader();
+    let mut input = Input { keymap: options.keymap.clone(), held: [0; 16], show_panel: false, quit: false };
+    let mut next_frame = Instant::now();
+    let mut drawn = None;
+
+    while !input.quit && machine.state().run_flag {
+        input.poll(&receiver);
+        machine.keypad_mut().set_pressed(input.pressed());
+
+        let was_beeping = machine.state().sound_timer > 0;
+        match recorder.as_mut() {
+            Some(recorder) => recorder.run_frame(machine)?,
+            None => machine.run_frame()?
+        }
+        let bell = !was_beeping && machine.state().sound_timer > 0;
+
+        let layout = Layout { width: machine.width(), height: machine.height(), show_panel: input.show_panel };
+        draw(machine, options, drawn != Some(layout), input.show_panel, bell)?;
+        drawn = Some(layout);
+
+        next_frame += FRAME;
+        let now = Instant::now();
+        if next_frame > now {
+            thread::sleep(next_frame - now);
+        } else {
+            // running behind, so skip ahead rather than rushing to catch up
+            next_frame = now;
+        }
+    }
+
+    Ok(())
+}
+
+fn main() {
+    let options = match parse_args(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}\n{}", message, USAGE);
+            process::exit(2);
+        }
+    };
+
+    if let Err(error) = run(options) {
+        eprintln!("chip8-tui: {}", error);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    fn args(line: &str) -> impl Iterator<Item = String> + '_ {
+        line.split_whitespace().map(String::from)
+    }
+
+    #[test]
+    fn it_will_parse_options() {
+        let options = parse_args(args("--ips 1000 --lit 10 --record bug.c8mv --engine blocks --quirks xo-chip game.ch8")).unwrap();
+
+        assert_eq!("game.ch8", options.rom);
+        assert_eq!(Some(1000), options.instructions_per_second);
+        assert_eq!(Colours { lit: 10, unlit: 0 }, options.colours);
+        assert_eq!(Some("bug.c8mv".to_string()), options.record);
+        assert_eq!(Engine::Blocks, options.engine);
+        assert_eq!(Quirks::xo_chip(), options.quirks);
+        assert_eq!(XO_CHIP_MEMORY_SIZE, options.memory_size);
+    }
+
+    #[test]
+    fn it_will_default_to_the_chip8_profile() {
+        let options = parse_args(args("game.ch8")).unwrap();
+
+        assert_eq!(Quirks::default(), options.quirks);
+        assert_eq!(MEMORY_SIZE, options.memory_size);
+        assert_eq!(None, options.record);
+    }
+
+    #[test]
+    fn it_will_reject_bad_options() {
+        assert!(parse_args(args("--ips")).is_err());
+        assert!(parse_args(args("--ips fast game.ch8")).is_err());
+        assert!(parse_args(args("--keys")).is_err());
+        assert!(parse_args(args("--ips 10")).is_err());
+        assert!(parse_args(args("--engine jit game.ch8")).is_err());
+        assert!(parse_args(args("--quirks chip48 game.ch8")).is_err());
+    }
+
+    #[test]
+    fn it_will_hold_typed_keys_for_a_few_frames() {
+        let (sender, receiver) = mpsc::channel();
+        let mut input = Input { keymap: KeyMap::default(), held: [0; 16], show_panel: false, quit: false };
+        sender.send(b'w').unwrap();
+        sender.send(TAB).unwrap();
+
+        input.poll(&receiver);
+        assert_eq!(1 << 0x5, input.pressed());
+        assert!(input.show_panel);
+
+        for _ in 0..HOLD_FRAMES {
+            input.poll(&receiver);
+        }
+        assert_eq!(0, input.pressed());
+    }
+}
@@ -0,0 +1,81 @@
+//! Draws the screen with Unicode half blocks, two pixels to a character.
+//!
+//! Each character is an upper half block with the foreground colour set to
+//! the top pixel and the background colour to the bottom pixel.
+use lib_chip::framebuffer::FrameBuffer;
+
+/// The ANSI 256 colour codes pixels are drawn in
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Colours {
+    pub lit: u8,
+    pub unlit: u8,
+}
+
+impl Default for Colours {
+    fn default() -> Self {
+        Colours { lit: 15, unlit: 0 }
+    }
+}
+
+fn lit(screen: &FrameBuffer, x: u32, y: u32) -> bool {
+    y < screen.height() && screen.pixel(x, y) != 0
+}
+
+/// Returns the number of lines the screen is drawn as
+pub fn line_count(screen: &FrameBuffer) -> u32 {
+    screen.height().div_ceil(2)
+}
+
+/// Returns the lines holding a row that changed since the screen was last presented
+pub fn dirty_lines(screen: &FrameBuffer) -> Vec<u32> {
+    let mut lines: Vec<u32> = screen.dirty_rows().map(|y| y / 2).collect();
+    lines.dedup();
+    lines
+}
+
+/// Draws a line of the screen as text, ending with the colours reset.
+pub fn render_line(screen: &FrameBuffer, line: u32, colours: Colours) -> String {
+    let colour = |on: bool| if on { colours.lit } else { colours.unlit };
+    let y = line * 2;
+
+    let mut text = String::new();
+    let mut last = None;
+    for x in 0..screen.width() {
+        let cell = (colour(lit(screen, x, y)), colour(lit(screen, x, y + 1)));
+        // only change colour when it differs from the character before
+        if last != Some(cell) {
+            text.push_str(&format!("\x1b[38;5;{};48;5;{}m", cell.0, cell.1));
+            last = Some(cell);
+        }
+        text.push('▀');
+    }
+    text.push_str("\x1b[0m");
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_will_draw_two_rows_to_a_line() {
+        // a 2x4 screen, the top left and bottom right pixels lit
+        let screen = FrameBuffer::from_pixels(2, 4, &[0x1, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x1]);
+        let colours = Colours { lit: 7, unlit: 1 };
+
+        assert_eq!(2, line_count(&screen));
+        assert_eq!("\x1b[38;5;7;48;5;1m▀\x1b[38;5;1;48;5;1m▀\x1b[0m", render_line(&screen, 0, colours));
+        assert_eq!("\x1b[38;5;1;48;5;1m▀\x1b[38;5;1;48;5;7m▀\x1b[0m", render_line(&screen, 1, colours));
+    }
+
+    #[test]
+    fn it_will_find_the_lines_that_changed() {
+        let mut screen = FrameBuffer::new(8, 8);
+        screen.present();
+        screen.toggle(0, 2, 0x1);
+        screen.toggle(0, 3, 0x1);
+        screen.toggle(0, 6, 0x1);
+
+        assert_eq!(vec![1, 3], dirty_lines(&screen));
+    }
+}
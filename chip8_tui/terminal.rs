@@ -0,0 +1,59 @@
+//! Puts the terminal into raw mode and reads keys without blocking.
+//!
+//! Raw mode is set with `stty`, so no terminal library is needed on the
+//! machines the frontend runs on over SSH.
+use std::io::{self, Read, Write};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+/// Restores the terminal settings it saved when dropped.
+pub struct RawTerminal {
+    saved: String,
+}
+
+fn stty(args: &[&str]) -> io::Result<String> {
+    // stty acts on its stdin, which must be the terminal
+    let output = Command::new("stty").args(args).stdin(Stdio::inherit()).output()?;
+    if !output.status.success() {
+        return Err(io::Error::other("stty failed, is stdin a terminal?"));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+impl RawTerminal {
+    /// Turns off line buffering and echo, hides the cursor and clears the screen.
+    pub fn enter() -> io::Result<RawTerminal> {
+        let saved = stty(&["-g"])?;
+        stty(&["raw", "-echo"])?;
+
+        let mut stdout = io::stdout();
+        write!(stdout, "\x1b[?25l\x1b[2J")?;
+        stdout.flush()?;
+        Ok(RawTerminal { saved })
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        let _ = stty(&[&self.saved]);
+        let mut stdout = io::stdout();
+        let _ = write!(stdout, "\x1b[0m\x1b[?25h\r\n");
+        let _ = stdout.flush();
+    }
+}
+
+/// Reads bytes from stdin on another thread, so the frontend can poll them
+pub fn spawn_reader() -> Receiver<u8> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let stdin = io::stdin();
+        for byte in stdin.lock().bytes() {
+            match byte {
+                Ok(byte) if sender.send(byte).is_ok() => (),
+                _ => break
+            }
+        }
+    });
+    receiver
+}
@@ -0,0 +1,73 @@
+//! Errors raised while assembling a program.
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+
+/// Describes what was wrong with a line of source.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AsmErrorKind {
+    /// A character that cannot start any token.
+    UnexpectedCharacter(char),
+    /// A token that looked like a number but could not be read as one.
+    InvalidNumber(String),
+    /// An instruction or directive that is not recognised.
+    UnknownMnemonic(String),
+    /// The operands do not match any form of the instruction.
+    InvalidOperands(String),
+    /// An operand that should be a value was missing or malformed.
+    ExpectedExpression,
+    /// A label or constant that is never defined.
+    UndefinedSymbol(String),
+    /// A label or constant that is defined more than once.
+    DuplicateSymbol(String),
+    /// A constant that is defined in terms of itself.
+    CircularDefinition(String),
+    /// A value that does not fit in its operand.
+    ValueOutOfRange(i64),
+    /// An address below the start of the program or beyond the end of memory.
+    AddressOutOfRange(i64),
+    /// A line that does not start with an instruction, directive or label.
+    ExpectedInstruction,
+    /// Output written over an address that already holds output.
+    Overlap(usize),
+}
+
+/// An error in the source, with the 1-based line and column it was found at.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AsmError {
+    pub line: usize,
+    pub column: usize,
+    pub kind: AsmErrorKind,
+}
+
+impl AsmError {
+    pub(crate) fn new(line: usize, column: usize, kind: AsmErrorKind) -> AsmError {
+        AsmError { line, column, kind }
+    }
+}
+
+impl Display for AsmErrorKind {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            AsmErrorKind::UnexpectedCharacter(c) => write!(f, "unexpected character '{}'", c),
+            AsmErrorKind::InvalidNumber(n) => write!(f, "invalid number '{}'", n),
+            AsmErrorKind::UnknownMnemonic(m) => write!(f, "unknown instruction '{}'", m),
+            AsmErrorKind::InvalidOperands(m) => write!(f, "invalid operands for '{}'", m),
+            AsmErrorKind::ExpectedExpression => write!(f, "expected a value"),
+            AsmErrorKind::UndefinedSymbol(s) => write!(f, "undefined symbol '{}'", s),
+            AsmErrorKind::DuplicateSymbol(s) => write!(f, "symbol '{}' is already defined", s),
+            AsmErrorKind::CircularDefinition(s) => write!(f, "constant '{}' is defined in terms of itself", s),
+            AsmErrorKind::ValueOutOfRange(v) => write!(f, "value {} is out of range", v),
+            AsmErrorKind::AddressOutOfRange(v) => write!(f, "address 0x{:X} is outside of program memory", v),
+            AsmErrorKind::ExpectedInstruction => write!(f, "expected an instruction"),
+            AsmErrorKind::Overlap(address) => write!(f, "output overlaps existing output at 0x{:04X}", address)
+        }
+    }
+}
+
+impl Display for AsmError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.kind)
+    }
+}
+
+impl Error for AsmError {}
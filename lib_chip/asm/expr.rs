@@ -0,0 +1,195 @@
+//! Values written in source, and the symbols they can refer to.
+use std::collections::HashMap;
+use super::error::{AsmError, AsmErrorKind};
+use super::lexer::{Token, TokenKind};
+
+/// Deepest chain of constants that refer to other constants
+const MAX_DEPTH: usize = 32;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Term {
+    Number(i64),
+    Symbol(String),
+}
+
+/// A sum of numbers and symbols, such as `sprites + 5 - 1`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expr {
+    /// Each term with the column it starts at and whether it is subtracted
+    pub terms: Vec<(bool, Term, usize)>,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Expr {
+    /// Reads an expression from tokens, which must all be part of it.
+    pub fn parse(tokens: &[Token], line: usize, column: usize) -> Result<Expr, AsmError> {
+        let expected = |column| AsmError::new(line, column, AsmErrorKind::ExpectedExpression);
+        let mut terms = Vec::new();
+        let mut negative = false;
+        let mut want_term = true;
+
+        for token in tokens {
+            match (&token.kind, want_term) {
+                (TokenKind::Minus, true) => negative = !negative,
+                (TokenKind::Plus, true) => (),
+                (TokenKind::Number(n), true) => {
+                    terms.push((negative, Term::Number(*n), token.column));
+                    want_term = false;
+                },
+                (TokenKind::Ident(name), true) => {
+                    terms.push((negative, Term::Symbol(name.clone()), token.column));
+                    want_term = false;
+                },
+                (TokenKind::Plus, false) => {
+                    negative = false;
+                    want_term = true;
+                },
+                (TokenKind::Minus, false) => {
+                    negative = true;
+                    want_term = true;
+                },
+                _ => return Err(expected(token.column))
+            }
+        }
+
+        if want_term {
+            let end = tokens.last().map(|t| t.column + 1).unwrap_or(column);
+            return Err(expected(end));
+        }
+
+        Ok(Expr { terms, line, column })
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Symbol {
+    /// A label, holding the address it was defined at
+    Label(i64),
+    /// A constant, evaluated when it is used
+    Constant(Expr),
+}
+
+/// The labels and constants defined by a program.
+#[derive(Debug, Default)]
+pub struct Symbols {
+    symbols: HashMap<String, Symbol>,
+}
+
+impl Symbols {
+    fn insert(&mut self, name: &str, symbol: Symbol, line: usize, column: usize) -> Result<(), AsmError> {
+        if self.symbols.contains_key(name) {
+            return Err(AsmError::new(line, column, AsmErrorKind::DuplicateSymbol(name.to_string())));
+        }
+
+        self.symbols.insert(name.to_string(), symbol);
+        Ok(())
+    }
+
+    pub fn define_label(&mut self, name: &str, address: i64, line: usize, column: usize) -> Result<(), AsmError> {
+        self.insert(name, Symbol::Label(address), line, column)
+    }
+
+    pub fn define_constant(&mut self, name: &str, expr: Expr, line: usize, column: usize) -> Result<(), AsmError> {
+        self.insert(name, Symbol::Constant(expr), line, column)
+    }
+
+    /// Evaluates an expression, all symbols it uses must be defined.
+    pub fn evaluate(&self, expr: &Expr) -> Result<i64, AsmError> {
+        self.evaluate_at_depth(expr, 0)
+    }
+
+    fn evaluate_at_depth(&self, expr: &Expr, depth: usize) -> Result<i64, AsmError> {
+        let mut total: i64 = 0;
+        for (negative, term, column) in expr.terms.iter() {
+            let value = match term {
+                Term::Number(n) => *n,
+                Term::Symbol(name) => match self.symbols.get(name) {
+                    Some(Symbol::Label(address)) => *address,
+                    Some(Symbol::Constant(_)) if depth >= MAX_DEPTH => {
+                        let kind = AsmErrorKind::CircularDefinition(name.clone());
+                        return Err(AsmError::new(expr.line, *column, kind));
+                    },
+                    Some(Symbol::Constant(inner)) => self.evaluate_at_depth(inner, depth + 1)?,
+                    None => {
+                        let kind = AsmErrorKind::UndefinedSymbol(name.clone());
+                        return Err(AsmError::new(expr.line, *column, kind));
+                    }
+                }
+            };
+
+            total = if *negative { total.wrapping_sub(value) } else { total.wrapping_add(value) };
+        }
+
+        Ok(total)
+    }
+
+    /// Evaluates an expression and checks it lies within min and max inclusive.
+    pub fn evaluate_in_range(&self, expr: &Expr, min: i64, max: i64) -> Result<i64, AsmError> {
+        let value = self.evaluate(expr)?;
+        if value < min || value > max {
+            return Err(AsmError::new(expr.line, expr.column, AsmErrorKind::ValueOutOfRange(value)));
+        }
+
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::lexer::tokenize;
+
+    fn expr(source: &str) -> Expr {
+        let tokens = tokenize(source, 1).unwrap();
+        Expr::parse(&tokens, 1, 1).unwrap()
+    }
+
+    #[test]
+    fn it_will_sum_terms() {
+        let mut symbols: Symbols = Default::default();
+        symbols.define_label("sprite", 0x300, 1, 1).unwrap();
+
+        assert_eq!(0x304, symbols.evaluate(&expr("sprite + 5 - 1")).unwrap());
+        assert_eq!(-2, symbols.evaluate(&expr("-2")).unwrap());
+    }
+
+    #[test]
+    fn it_will_evaluate_constants_when_used() {
+        let mut symbols: Symbols = Default::default();
+        symbols.define_constant("END", expr("start + 2"), 1, 1).unwrap();
+        symbols.define_label("start", 0x200, 2, 1).unwrap();
+
+        assert_eq!(0x202, symbols.evaluate(&expr("END")).unwrap());
+    }
+
+    #[test]
+    fn it_will_report_undefined_symbols() {
+        let symbols: Symbols = Default::default();
+
+        let err = symbols.evaluate(&expr("1 + missing")).unwrap_err();
+
+        assert_eq!(AsmError::new(1, 5, AsmErrorKind::UndefinedSymbol("missing".to_string())), err);
+    }
+
+    #[test]
+    fn it_will_report_circular_constants() {
+        let mut symbols: Symbols = Default::default();
+        symbols.define_constant("A", expr("B"), 1, 1).unwrap();
+        symbols.define_constant("B", expr("A"), 2, 1).unwrap();
+
+        let err = symbols.evaluate(&expr("A")).unwrap_err();
+
+        assert_eq!(AsmErrorKind::CircularDefinition("A".to_string()), err.kind);
+    }
+
+    #[test]
+    fn it_will_report_duplicate_symbols() {
+        let mut symbols: Symbols = Default::default();
+        symbols.define_label("loop", 0x200, 1, 1).unwrap();
+
+        let err = symbols.define_constant("loop", expr("1"), 4, 2).unwrap_err();
+
+        assert_eq!(AsmError::new(4, 2, AsmErrorKind::DuplicateSymbol("loop".to_string())), err);
+    }
+}
@@ -0,0 +1,264 @@
+//! Turns an instruction mnemonic and its operands into an opcode.
+use super::error::{AsmError, AsmErrorKind};
+use super::expr::{Expr, Symbols};
+use super::lexer::{Token, TokenKind};
+use crate::opcode::{AddOp, OpCode, ShiftOp, SkipOp, LoadOp, JumpOp, ScrollOp, Register};
+
+/// Operands that name a part of the machine rather than a value
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Keyword {
+    I,
+    DT,
+    ST,
+    K,
+    F,
+    HF,
+    B,
+    R,
+    Pitch,
+}
+
+impl Keyword {
+    fn from_name(name: &str) -> Option<Keyword> {
+        match name.to_ascii_uppercase().as_str() {
+            "I" => Some(Keyword::I),
+            "DT" => Some(Keyword::DT),
+            "ST" => Some(Keyword::ST),
+            "K" => Some(Keyword::K),
+            "F" => Some(Keyword::F),
+            "HF" => Some(Keyword::HF),
+            "B" => Some(Keyword::B),
+            "R" => Some(Keyword::R),
+            "PITCH" => Some(Keyword::Pitch),
+            _ => None
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operand {
+    /// A register, V0 to VF
+    Register(Register),
+    /// The memory pointed to by I, written `[I]`
+    IndirectI,
+    Keyword(Keyword),
+    /// A 16-bit address for the XO-CHIP long I load, written `LONG addr`
+    Long(Expr),
+    Value(Expr),
+}
+
+/// Returns the register named by an identifier such as `VA`
+fn register(name: &str) -> Option<Register> {
+    let upper = name.to_ascii_uppercase();
+    if upper.len() == 2 && upper.starts_with('V') {
+        u8::from_str_radix(&upper[1..], 16).ok()
+    } else {
+        None
+    }
+}
+
+impl Operand {
+    /// Reads an operand from the tokens between two commas.
+    pub fn parse(tokens: &[Token], line: usize, column: usize) -> Result<Operand, AsmError> {
+        match tokens {
+            [Token { kind: TokenKind::Ident(name), .. }] => {
+                if let Some(v) = register(name) {
+                    return Ok(Operand::Register(v));
+                }
+
+                if let Some(keyword) = Keyword::from_name(name) {
+                    return Ok(Operand::Keyword(keyword));
+                }
+            },
+            [Token { kind: TokenKind::LBracket, .. },
+             Token { kind: TokenKind::Ident(name), .. },
+             Token { kind: TokenKind::RBracket, .. }] if name.eq_ignore_ascii_case("I") => {
+                return Ok(Operand::IndirectI);
+            },
+            [Token { kind: TokenKind::Ident(name), .. }, rest @ ..]
+                if name.eq_ignore_ascii_case("LONG") && !rest.is_empty() => {
+                return Ok(Operand::Long(Expr::parse(rest, line, rest[0].column)?));
+            },
+            _ => ()
+        }
+
+        Ok(Operand::Value(Expr::parse(tokens, line, column)?))
+    }
+}
+
+/// Returns the number of bytes an instruction assembles to
+pub fn instruction_size(operands: &[Operand]) -> usize {
+    if operands.iter().any(|o| matches!(o, Operand::Long(_))) {
+        4
+    } else {
+        2
+    }
+}
+
+const ADDRESS: (i64, i64) = (0x0, 0xFFF);
+const LONG_ADDRESS: (i64, i64) = (0x0, 0xFFFF);
+const BYTE: (i64, i64) = (-0x80, 0xFF);
+const NIBBLE: (i64, i64) = (0x0, 0xF);
+
+fn value(symbols: &Symbols, expr: &Expr, range: (i64, i64)) -> Result<i64, AsmError> {
+    symbols.evaluate_in_range(expr, range.0, range.1)
+}
+
+/// Encodes an instruction, evaluating any values against the symbols.
+pub fn encode_instruction(mnemonic: &str, operands: &[Operand], symbols: &Symbols,
+    line: usize, column: usize) -> Result<OpCode, AsmError> {
+    use self::Operand::{Register as V, IndirectI, Keyword as Kw, Long, Value};
+
+    let upper = mnemonic.to_ascii_uppercase();
+    let address = |e| value(symbols, e, ADDRESS).map(|v| v as u16);
+    let byte = |e| value(symbols, e, BYTE).map(|v| v as u8);
+    let nibble = |e| value(symbols, e, NIBBLE).map(|v| v as u8);
+
+    let opcode = match (upper.as_str(), operands) {
+        ("CLS", []) => OpCode::CLS,
+        ("RET", []) => OpCode::RET,
+        ("EXIT", []) => OpCode::EXIT,
+        ("LOW", []) => OpCode::LOW,
+        ("HIGH", []) => OpCode::HIGH,
+        ("SCR", []) => OpCode::SCROLL(ScrollOp::SCR),
+        ("SCL", []) => OpCode::SCROLL(ScrollOp::SCL),
+        ("SCD", [Value(n)]) => OpCode::SCROLL(ScrollOp::SCD(nibble(n)?)),
+        ("AUDIO", []) => OpCode::AUDIO,
+        ("PLANE", [Value(n)]) => OpCode::PLANE(nibble(n)?),
+        ("JP", [Value(nnn)]) => OpCode::JP(JumpOp::JP(address(nnn)?)),
+        ("JP", [V(0), Value(nnn)]) => OpCode::JP(JumpOp::JPV0(address(nnn)?)),
+        ("CALL", [Value(nnn)]) => OpCode::CALL(address(nnn)?),
+        ("SE", [V(x), V(y)]) => OpCode::SKIP(SkipOp::SEXY(*x, *y)),
+        ("SE", [V(x), Value(kk)]) => OpCode::SKIP(SkipOp::SE(*x, byte(kk)?)),
+        ("SNE", [V(x), V(y)]) => OpCode::SKIP(SkipOp::SNEXY(*x, *y)),
+        ("SNE", [V(x), Value(kk)]) => OpCode::SKIP(SkipOp::SNE(*x, byte(kk)?)),
+        ("SKP", [V(x)]) => OpCode::SKIP(SkipOp::SKP(*x)),
+        ("SKNP", [V(x)]) => OpCode::SKIP(SkipOp::SKNP(*x)),
+        ("LD", [V(x), V(y)]) => OpCode::LD(LoadOp::LDXY(*x, *y)),
+        ("LD", [V(x), Value(kk)]) => OpCode::LD(LoadOp::LD(*x, byte(kk)?)),
+        ("LD", [Kw(Keyword::I), Value(nnn)]) => OpCode::LD(LoadOp::LDI(address(nnn)?)),
+        ("LD", [Kw(Keyword::I), Long(nnnn)]) => {
+            OpCode::LD(LoadOp::LDIL(value(symbols, nnnn, LONG_ADDRESS)? as u16))
+        },
+        ("LD", [V(x), Kw(Keyword::DT)]) => OpCode::LD(LoadOp::LDVXDT(*x)),
+        ("LD", [V(x), Kw(Keyword::K)]) => OpCode::LD(LoadOp::LDKEY(*x)),
+        ("LD", [Kw(Keyword::DT), V(x)]) => OpCode::LD(LoadOp::LDDTVX(*x)),
+        ("LD", [Kw(Keyword::ST), V(x)]) => OpCode::LD(LoadOp::LDSTVX(*x)),
+        ("LD", [Kw(Keyword::F), V(x)]) => OpCode::LD(LoadOp::LDF(*x)),
+        ("LD", [Kw(Keyword::HF), V(x)]) => OpCode::LD(LoadOp::LDHF(*x)),
+        ("LD", [Kw(Keyword::B), V(x)]) => OpCode::LD(LoadOp::LDB(*x)),
+        ("LD", [IndirectI, V(x)]) => OpCode::LD(LoadOp::LDIV0X(*x)),
+        ("LD", [V(x), IndirectI]) => OpCode::LD(LoadOp::LDV0XI(*x)),
+        ("LD", [Kw(Keyword::R), V(x)]) => OpCode::LD(LoadOp::LDRVX(*x)),
+        ("LD", [V(x), Kw(Keyword::R)]) => OpCode::LD(LoadOp::LDVXR(*x)),
+        ("LD", [Kw(Keyword::Pitch), V(x)]) => OpCode::LD(LoadOp::LDPITCH(*x)),
+        ("SAVE", [V(x), V(y)]) => OpCode::LD(LoadOp::LDIVXY(*x, *y)),
+        ("LOAD", [V(x), V(y)]) => OpCode::LD(LoadOp::LDVXYI(*x, *y)),
+        ("ADD", [V(x), V(y)]) => OpCode::ADD(AddOp::ADDREG(*x, *y)),
+        ("ADD", [V(x), Value(kk)]) => OpCode::ADD(AddOp::ADD(*x, byte(kk)?)),
+        ("ADD", [Kw(Keyword::I), V(x)]) => OpCode::ADD(AddOp::ADDI(*x)),
+        ("OR", [V(x), V(y)]) => OpCode::OR(*x, *y),
+        ("AND", [V(x), V(y)]) => OpCode::AND(*x, *y),
+        ("XOR", [V(x), V(y)]) => OpCode::XOR(*x, *y),
+        ("SUB", [V(x), V(y)]) => OpCode::SUB(*x, *y),
+        ("SUBN", [V(x), V(y)]) => OpCode::SUBN(*x, *y),
+        ("SHR", [V(x)]) => OpCode::SHIFT(ShiftOp::SHR(*x, *x)),
+        ("SHR", [V(x), V(y)]) => OpCode::SHIFT(ShiftOp::SHR(*x, *y)),
+        ("SHL", [V(x)]) => OpCode::SHIFT(ShiftOp::SHL(*x, *x)),
+        ("SHL", [V(x), V(y)]) => OpCode::SHIFT(ShiftOp::SHL(*x, *y)),
+        ("RND", [V(x), Value(kk)]) => OpCode::RND(*x, byte(kk)?),
+        ("DRW", [V(x), V(y), Value(n)]) => OpCode::DRW(*x, *y, nibble(n)?),
+        (known, _) if is_mnemonic(known) => {
+            let kind = AsmErrorKind::InvalidOperands(mnemonic.to_string());
+            return Err(AsmError::new(line, column, kind));
+        },
+        _ => {
+            let kind = AsmErrorKind::UnknownMnemonic(mnemonic.to_string());
+            return Err(AsmError::new(line, column, kind));
+        }
+    };
+
+    Ok(opcode)
+}
+
+/// Returns true if the upper case name is an instruction mnemonic
+pub fn is_mnemonic(name: &str) -> bool {
+    const MNEMONICS: [&str; 29] = [
+        "CLS", "RET", "EXIT", "LOW", "HIGH", "SCR", "SCL", "SCD", "AUDIO", "PLANE",
+        "JP", "CALL", "SE", "SNE", "SKP", "SKNP", "LD", "SAVE", "LOAD", "ADD", "OR",
+        "AND", "XOR", "SUB", "SUBN", "SHR", "SHL", "RND", "DRW"
+    ];
+
+    MNEMONICS.contains(&name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::lexer::tokenize;
+
+    fn encode(mnemonic: &str, operands: &str) -> Result<OpCode, AsmError> {
+        let tokens = tokenize(operands, 1).unwrap();
+        let operands: Vec<Operand> = tokens
+            .split(|t| t.kind == TokenKind::Comma)
+            .filter(|o| !o.is_empty())
+            .map(|o| Operand::parse(o, 1, o[0].column).unwrap())
+            .collect();
+        let mut symbols: Symbols = Default::default();
+        symbols.define_label("target", 0x234, 1, 1).unwrap();
+
+        encode_instruction(mnemonic, &operands, &symbols, 1, 1)
+    }
+
+    #[test]
+    fn it_will_encode_load_forms() {
+        assert_eq!(OpCode::LD(LoadOp::LD(0x1, 0x20)), encode("LD", "V1, 0x20").unwrap());
+        assert_eq!(OpCode::LD(LoadOp::LDXY(0xA, 0xB)), encode("ld", "va, VB").unwrap());
+        assert_eq!(OpCode::LD(LoadOp::LDI(0x234)), encode("LD", "I, target").unwrap());
+        assert_eq!(OpCode::LD(LoadOp::LDIL(0x1234)), encode("LD", "I, LONG 0x1234").unwrap());
+        assert_eq!(OpCode::LD(LoadOp::LDIV0X(0x5)), encode("LD", "[I], V5").unwrap());
+        assert_eq!(OpCode::LD(LoadOp::LDV0XI(0x5)), encode("LD", "V5, [I]").unwrap());
+        assert_eq!(OpCode::LD(LoadOp::LDKEY(0x2)), encode("LD", "V2, K").unwrap());
+        assert_eq!(OpCode::LD(LoadOp::LDB(0x2)), encode("LD", "B, V2").unwrap());
+        assert_eq!(OpCode::LD(LoadOp::LDPITCH(0x2)), encode("LD", "PITCH, V2").unwrap());
+    }
+
+    #[test]
+    fn it_will_encode_jumps() {
+        assert_eq!(OpCode::JP(JumpOp::JP(0x234)), encode("JP", "target").unwrap());
+        assert_eq!(OpCode::JP(JumpOp::JPV0(0x236)), encode("JP", "V0, target + 2").unwrap());
+        assert_eq!(OpCode::CALL(0x234), encode("CALL", "target").unwrap());
+    }
+
+    #[test]
+    fn it_will_default_shifts_to_the_same_register() {
+        assert_eq!(OpCode::SHIFT(ShiftOp::SHR(0x3, 0x3)), encode("SHR", "V3").unwrap());
+        assert_eq!(OpCode::SHIFT(ShiftOp::SHL(0x3, 0x4)), encode("SHL", "V3, V4").unwrap());
+    }
+
+    #[test]
+    fn it_will_wrap_negative_bytes() {
+        assert_eq!(OpCode::ADD(AddOp::ADD(0x0, 0xFF)), encode("ADD", "V0, -1").unwrap());
+    }
+
+    #[test]
+    fn it_will_report_values_out_of_range() {
+        let err = encode("DRW", "V0, V1, 16").unwrap_err();
+
+        assert_eq!(AsmError::new(1, 9, AsmErrorKind::ValueOutOfRange(16)), err);
+    }
+
+    #[test]
+    fn it_will_report_invalid_operands() {
+        let err = encode("LD", "DT, 5").unwrap_err();
+
+        assert_eq!(AsmErrorKind::InvalidOperands("LD".to_string()), err.kind);
+    }
+
+    #[test]
+    fn it_will_report_unknown_mnemonics() {
+        let err = encode("MOV", "V0, V1").unwrap_err();
+
+        assert_eq!(AsmErrorKind::UnknownMnemonic("MOV".to_string()), err.kind);
+    }
+}
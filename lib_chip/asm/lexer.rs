@@ -0,0 +1,165 @@
+//! Splits a line of source into tokens.
+use super::error::{AsmError, AsmErrorKind};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenKind {
+    Ident(String),
+    Number(i64),
+    Comma,
+    Colon,
+    LBracket,
+    RBracket,
+    Plus,
+    Minus,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    /// The 1-based column the token starts at
+    pub column: usize,
+}
+
+fn is_ident_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_' || c == '.'
+}
+
+fn is_ident(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '.'
+}
+
+/// Reads a number in decimal, hex (`0x`, `$` or `#`) or binary (`0b` or `%`).
+fn parse_number(text: &str) -> Option<i64> {
+    let lower = text.to_ascii_lowercase();
+    let prefixes: [(&str, u32); 5] = [("0x", 16), ("0b", 2), ("$", 16), ("#", 16), ("%", 2)];
+    let (digits, radix) = prefixes
+        .iter()
+        .find_map(|(prefix, radix)| lower.strip_prefix(prefix).map(|digits| (digits, *radix)))
+        .unwrap_or((&lower[..], 10));
+
+    let digits = digits.replace('_', "");
+    if digits.is_empty() {
+        return None;
+    }
+
+    i64::from_str_radix(&digits, radix).ok()
+}
+
+/// Tokenizes a single line, everything after a `;` is a comment.
+pub fn tokenize(line: &str, line_number: usize) -> Result<Vec<Token>, AsmError> {
+    let chars: Vec<char> = line.chars().collect();
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+
+    while pos < chars.len() {
+        let c = chars[pos];
+        let column = pos + 1;
+
+        if c == ';' {
+            break;
+        }
+
+        if c.is_whitespace() {
+            pos += 1;
+            continue;
+        }
+
+        let single = match c {
+            ',' => Some(TokenKind::Comma),
+            ':' => Some(TokenKind::Colon),
+            '[' => Some(TokenKind::LBracket),
+            ']' => Some(TokenKind::RBracket),
+            '+' => Some(TokenKind::Plus),
+            '-' => Some(TokenKind::Minus),
+            _ => None
+        };
+
+        if let Some(kind) = single {
+            tokens.push(Token { kind, column });
+            pos += 1;
+            continue;
+        }
+
+        if c.is_ascii_digit() || c == '$' || c == '#' || c == '%' {
+            let start = pos;
+            pos += 1;
+            while pos < chars.len() && is_ident(chars[pos]) {
+                pos += 1;
+            }
+
+            let text: String = chars[start..pos].iter().collect();
+            let value = parse_number(&text)
+                .ok_or_else(|| AsmError::new(line_number, column, AsmErrorKind::InvalidNumber(text.clone())))?;
+            tokens.push(Token { kind: TokenKind::Number(value), column });
+            continue;
+        }
+
+        if is_ident_start(c) {
+            let start = pos;
+            while pos < chars.len() && is_ident(chars[pos]) {
+                pos += 1;
+            }
+
+            let text: String = chars[start..pos].iter().collect();
+            tokens.push(Token { kind: TokenKind::Ident(text), column });
+            continue;
+        }
+
+        return Err(AsmError::new(line_number, column, AsmErrorKind::UnexpectedCharacter(c)));
+    }
+
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(line: &str) -> Vec<TokenKind> {
+        tokenize(line, 1).unwrap().into_iter().map(|t| t.kind).collect()
+    }
+
+    #[test]
+    fn it_will_tokenize_an_instruction() {
+        let actual = kinds("  LD V1, 0x20 ; load");
+
+        assert_eq!(vec![
+            TokenKind::Ident("LD".to_string()),
+            TokenKind::Ident("V1".to_string()),
+            TokenKind::Comma,
+            TokenKind::Number(0x20)
+        ], actual);
+    }
+
+    #[test]
+    fn it_will_read_numbers_in_each_radix() {
+        assert_eq!(vec![TokenKind::Number(255)], kinds("255"));
+        assert_eq!(vec![TokenKind::Number(255)], kinds("0xFF"));
+        assert_eq!(vec![TokenKind::Number(255)], kinds("$ff"));
+        assert_eq!(vec![TokenKind::Number(255)], kinds("#FF"));
+        assert_eq!(vec![TokenKind::Number(5)], kinds("0b101"));
+        assert_eq!(vec![TokenKind::Number(0xF0)], kinds("%1111_0000"));
+    }
+
+    #[test]
+    fn it_will_record_columns() {
+        let tokens = tokenize("loop: JP loop", 1).unwrap();
+        let columns: Vec<usize> = tokens.iter().map(|t| t.column).collect();
+
+        assert_eq!(vec![1, 5, 7, 10], columns);
+    }
+
+    #[test]
+    fn it_will_report_invalid_numbers() {
+        let err = tokenize("db 0xZZ", 3).unwrap_err();
+
+        assert_eq!(AsmError::new(3, 4, AsmErrorKind::InvalidNumber("0xZZ".to_string())), err);
+    }
+
+    #[test]
+    fn it_will_report_unexpected_characters() {
+        let err = tokenize("LD V0, @", 2).unwrap_err();
+
+        assert_eq!(AsmError::new(2, 8, AsmErrorKind::UnexpectedCharacter('@')), err);
+    }
+}
@@ -0,0 +1,342 @@
+//! An assembler that turns Cowgod style mnemonic source into a rom.
+//!
+//! Each line holds an optional label, followed by an instruction or directive
+//! and a `;` comment.  Mnemonics, registers and directives are case insensitive,
+//! labels and constants are not.
+//!
+//! - Instructions use the syntax of Cowgod's technical reference, such as
+//!   `LD V1, 0x20`, `DRW V0, V1, 5` and `CALL draw_ship`.  SUPER-CHIP adds
+//!   `SCD n`, `SCR`, `SCL`, `EXIT`, `LOW`, `HIGH`, `LD HF, Vx`, `LD R, Vx` and
+//!   `LD Vx, R`.  XO-CHIP adds `LD I, LONG addr`, `SAVE Vx, Vy`, `LOAD Vx, Vy`,
+//!   `PLANE n`, `AUDIO` and `LD PITCH, Vx`.
+//! - `name:` defines a label at the current address.
+//! - `name EQU value` defines a constant.
+//! - `DB value, ...` and `DW value, ...` emit bytes and big endian words.
+//! - `ORG address` moves the current address, which starts at 0x200.
+//!
+//! Values are sums of numbers, labels and constants such as `sprites + 5`.
+//! Numbers may be decimal, hex written `0x`, `$` or `#`, or binary written `0b` or `%`.
+//!
+//! # Example:
+//!
+//! ```
+//! # use lib_chip::asm::assemble;
+//! let source = "
+//!     LD I, ship
+//! loop:
+//!     DRW V0, V1, 2
+//!     JP loop
+//! ship:
+//!     db 0b0110_0000, 0b1111_0000
+//! ";
+//!
+//! let rom = assemble(source)?;
+//! # assert_eq!([0xA2, 0x06, 0xD0, 0x12, 0x12, 0x02, 0x60, 0xF0], rom.read_all());
+//! # Ok::<(), lib_chip::asm::AsmError>(())
+//! ```
+
+mod error;
+mod expr;
+mod instruction;
+mod lexer;
+
+pub use self::error::{AsmError, AsmErrorKind};
+
+use self::expr::{Expr, Symbols};
+use self::instruction::{Operand, encode_instruction, instruction_size};
+use self::lexer::{Token, TokenKind, tokenize};
+use crate::memory::XO_CHIP_MEMORY_SIZE;
+use crate::opcode::encoder::encode_bytes;
+use crate::rom::{Rom, PROGRAM_START};
+
+/// The range of values accepted by `DW`, negative values are written as two's complement
+const WORD: (i64, i64) = (-0x8000, 0xFFFF);
+/// The range of values accepted by `DB`, negative values are written as two's complement
+const BYTE: (i64, i64) = (-0x80, 0xFF);
+
+enum Item {
+    Instruction { mnemonic: String, operands: Vec<Operand>, column: usize },
+    Bytes(Vec<Expr>),
+    Words(Vec<Expr>),
+}
+
+/// A line that produces output, with the address the output starts at
+struct Statement {
+    line: usize,
+    column: usize,
+    address: usize,
+    item: Item,
+}
+
+/// Splits operand tokens at each comma, keeping the column each operand starts at.
+fn split_operands(tokens: &[Token], column: usize) -> Vec<(usize, &[Token])> {
+    if tokens.is_empty() {
+        return Vec::new();
+    }
+
+    let mut operands = Vec::new();
+    let mut start = 0;
+    let mut start_column = column;
+    for (index, token) in tokens.iter().enumerate() {
+        if token.kind == TokenKind::Comma {
+            operands.push((start_column, &tokens[start..index]));
+            start = index + 1;
+            start_column = token.column + 1;
+        }
+    }
+
+    operands.push((start_column, &tokens[start..]));
+    operands
+        .into_iter()
+        .map(|(column, tokens)| (tokens.first().map(|t| t.column).unwrap_or(column), tokens))
+        .collect()
+}
+
+fn parse_values(tokens: &[Token], line: usize, column: usize) -> Result<Vec<Expr>, AsmError> {
+    split_operands(tokens, column)
+        .into_iter()
+        .map(|(column, tokens)| Expr::parse(tokens, line, column))
+        .collect()
+}
+
+/// Reads every line, defining labels and constants and laying out the output.
+fn first_pass(source: &str, symbols: &mut Symbols) -> Result<Vec<Statement>, AsmError> {
+    let mut statements = Vec::new();
+    let mut address = usize::from(PROGRAM_START);
+
+    for (index, text) in source.lines().enumerate() {
+        let line = index + 1;
+        let tokens = tokenize(text, line)?;
+        let mut rest = &tokens[..];
+
+        if let [Token { kind: TokenKind::Ident(name), column }, Token { kind: TokenKind::Colon, .. }, tail @ ..] = rest {
+            symbols.define_label(name, address as i64, line, *column)?;
+            rest = tail;
+        }
+
+        if let [Token { kind: TokenKind::Ident(name), column }, Token { kind: TokenKind::Ident(equ), column: equ_column }, tail @ ..] = rest {
+            if equ.eq_ignore_ascii_case("EQU") {
+                let expr = Expr::parse(tail, line, equ_column + equ.len() + 1)?;
+                symbols.define_constant(name, expr, line, *column)?;
+                continue;
+            }
+        }
+
+        let (mnemonic, column, tail) = match rest {
+            [] => continue,
+            [Token { kind: TokenKind::Ident(mnemonic), column }, tail @ ..] => (mnemonic, *column, tail),
+            [token, ..] => return Err(AsmError::new(line, token.column, AsmErrorKind::ExpectedInstruction))
+        };
+
+        let operand_column = column + mnemonic.len() + 1;
+        let item = match mnemonic.to_ascii_uppercase().as_str() {
+            "ORG" => {
+                let origin = match parse_values(tail, line, operand_column)?.as_slice() {
+                    [origin] => origin.clone(),
+                    _ => return Err(AsmError::new(line, column, AsmErrorKind::InvalidOperands(mnemonic.clone())))
+                };
+
+                let value = symbols.evaluate(&origin)?;
+                if value < i64::from(PROGRAM_START) || value > XO_CHIP_MEMORY_SIZE as i64 {
+                    return Err(AsmError::new(line, origin.column, AsmErrorKind::AddressOutOfRange(value)));
+                }
+
+                address = value as usize;
+                continue;
+            },
+            "DB" => Item::Bytes(parse_values(tail, line, operand_column)?),
+            "DW" => Item::Words(parse_values(tail, line, operand_column)?),
+            _ => {
+                let operands = split_operands(tail, operand_column)
+                    .into_iter()
+                    .map(|(column, tokens)| Operand::parse(tokens, line, column))
+                    .collect::<Result<Vec<Operand>, AsmError>>()?;
+                Item::Instruction { mnemonic: mnemonic.clone(), operands, column }
+            }
+        };
+
+        let size = match &item {
+            Item::Instruction { operands, .. } => instruction_size(operands),
+            Item::Bytes(values) => values.len(),
+            Item::Words(values) => values.len() * 2
+        };
+
+        statements.push(Statement { line, column, address, item });
+        address += size;
+    }
+
+    Ok(statements)
+}
+
+/// Evaluates each statement into the bytes it produces.
+fn second_pass(statements: &[Statement], symbols: &Symbols) -> Result<Vec<u8>, AsmError> {
+    let start = usize::from(PROGRAM_START);
+    let mut output: Vec<u8> = Vec::new();
+    let mut written: Vec<bool> = Vec::new();
+
+    for statement in statements {
+        let bytes = match &statement.item {
+            Item::Instruction { mnemonic, operands, column } => {
+                let opcode = encode_instruction(mnemonic, operands, symbols, statement.line, *column)?;
+                encode_bytes(opcode)
+            },
+            Item::Bytes(values) => values
+                .iter()
+                .map(|v| symbols.evaluate_in_range(v, BYTE.0, BYTE.1).map(|b| b as u8))
+                .collect::<Result<Vec<u8>, AsmError>>()?,
+            Item::Words(values) => {
+                let mut bytes = Vec::new();
+                for value in values {
+                    let word = symbols.evaluate_in_range(value, WORD.0, WORD.1)? as u16;
+                    bytes.push((word >> 8) as u8);
+                    bytes.push(word as u8);
+                }
+                bytes
+            }
+        };
+
+        let end = statement.address + bytes.len();
+        if end > XO_CHIP_MEMORY_SIZE {
+            let kind = AsmErrorKind::AddressOutOfRange(end as i64);
+            return Err(AsmError::new(statement.line, statement.column, kind));
+        }
+
+        if output.len() < end - start {
+            output.resize(end - start, 0x0);
+            written.resize(end - start, false);
+        }
+
+        for (offset, byte) in bytes.into_iter().enumerate() {
+            let index = statement.address - start + offset;
+            if written[index] {
+                let kind = AsmErrorKind::Overlap(statement.address + offset);
+                return Err(AsmError::new(statement.line, statement.column, kind));
+            }
+
+            output[index] = byte;
+            written[index] = true;
+        }
+    }
+
+    Ok(output)
+}
+
+/// Assembles source into a rom that loads at 0x200.
+///
+/// Fails with the line and column of the first error found.
+pub fn assemble(source: &str) -> Result<Rom, AsmError> {
+    let mut symbols: Symbols = Default::default();
+    let statements = first_pass(source, &mut symbols)?;
+    let data = second_pass(&statements, &symbols)?;
+
+    Ok(Rom::from_memory(data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opcode::{OpCode, LoadOp};
+    use crate::opcode::parser::parse_opcode;
+
+    fn bytes(source: &str) -> Vec<u8> {
+        assemble(source).unwrap().read_all().to_vec()
+    }
+
+    #[test]
+    fn it_will_assemble_instructions() {
+        let actual = bytes("CLS\nLD V1, 0x20\nDRW V0, V1, 5\nRET");
+
+        assert_eq!(vec![0x00, 0xE0, 0x61, 0x20, 0xD0, 0x15, 0x00, 0xEE], actual);
+    }
+
+    #[test]
+    fn it_will_resolve_forward_and_backward_labels() {
+        let actual = bytes("start: CALL draw_ship\n JP start\ndraw_ship:\n RET");
+
+        assert_eq!(vec![0x22, 0x04, 0x12, 0x00, 0x00, 0xEE], actual);
+    }
+
+    #[test]
+    fn it_will_use_constants() {
+        let actual = bytes("SPEED equ 3\nOFFSET EQU SPEED + 1\n ADD V2, OFFSET");
+
+        assert_eq!(vec![0x72, 0x04], actual);
+    }
+
+    #[test]
+    fn it_will_emit_data() {
+        let actual = bytes("db 1, 0x2, -1\ndw 0x1234, end\nend:");
+
+        assert_eq!(vec![0x01, 0x02, 0xFF, 0x12, 0x34, 0x02, 0x07], actual);
+    }
+
+    #[test]
+    fn it_will_move_the_origin() {
+        let actual = bytes("JP main\norg 0x206\nmain: JP main");
+
+        assert_eq!(vec![0x12, 0x06, 0x00, 0x00, 0x00, 0x00, 0x12, 0x06], actual);
+    }
+
+    #[test]
+    fn it_will_assemble_long_loads() {
+        let actual = bytes("LD I, LONG data\nSAVE V0, V3\norg 0x1000\ndata: db 0xAA");
+
+        assert_eq!(vec![0xF0, 0x00, 0x10, 0x00, 0x50, 0x32], actual[..6].to_vec());
+        assert_eq!(0xAA, actual[0x1000 - 0x200]);
+    }
+
+    #[test]
+    fn it_will_ignore_comments_and_blank_lines() {
+        let actual = bytes("; a program\n\n  CLS ; clear\n");
+
+        assert_eq!(vec![0x00, 0xE0], actual);
+    }
+
+    #[test]
+    fn it_will_decode_to_the_same_instructions() {
+        let actual = bytes("CALL 0x300\nLD V3, DT\nLD I, 0x123\nDRW V1, V2, 0xF");
+        let decoded: Vec<OpCode> = actual.chunks(2).map(|c| parse_opcode(c[0], c[1])).collect();
+
+        assert_eq!(vec![
+            OpCode::CALL(0x300),
+            OpCode::LD(LoadOp::LDVXDT(3)),
+            OpCode::LD(LoadOp::LDI(0x123)),
+            OpCode::DRW(1, 2, 0xF)
+        ], decoded);
+    }
+
+    #[test]
+    fn it_will_report_the_line_and_column_of_errors() {
+        let err = assemble("CLS\n  LD V0, missing").unwrap_err();
+
+        assert_eq!(AsmError::new(2, 10, AsmErrorKind::UndefinedSymbol("missing".to_string())), err);
+    }
+
+    #[test]
+    fn it_will_report_unknown_mnemonics() {
+        let err = assemble("  MOV V0, V1").unwrap_err();
+
+        assert_eq!(AsmError::new(1, 3, AsmErrorKind::UnknownMnemonic("MOV".to_string())), err);
+    }
+
+    #[test]
+    fn it_will_report_missing_operands() {
+        let err = assemble("LD V0,").unwrap_err();
+
+        assert_eq!(AsmError::new(1, 7, AsmErrorKind::ExpectedExpression), err);
+    }
+
+    #[test]
+    fn it_will_report_origins_below_the_program() {
+        let err = assemble("org 0x100").unwrap_err();
+
+        assert_eq!(AsmError::new(1, 5, AsmErrorKind::AddressOutOfRange(0x100)), err);
+    }
+
+    #[test]
+    fn it_will_report_overlapping_output() {
+        let err = assemble("CLS\norg 0x200\nRET").unwrap_err();
+
+        assert_eq!(AsmError::new(3, 1, AsmErrorKind::Overlap(0x200)), err);
+    }
+}
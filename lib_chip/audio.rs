@@ -0,0 +1,221 @@
+//! Synthesises the beep played while the sound timer is above zero.
+//!
+//! The beeper produces 16-bit mono PCM samples of a square wave.  Samples are
+//! counted from the emulated time the clock reports rather than from whole
+//! frames, so a second of emulation always makes exactly `sample_rate`
+//! samples and the beep starts and stops on the sample the timer changes at.
+//!
+//! # Example:
+//!
+//! ```
+//! # use std::time::Duration;
+//! # use lib_chip::audio::{AudioConfig, Beeper};
+//! let mut beeper = Beeper::new(AudioConfig { sample_rate: 8000, ..Default::default() });
+//! beeper.advance(Duration::from_millis(100), true);
+//! beeper.advance(Duration::from_millis(250), false);
+//!
+//! let samples = beeper.take_samples();
+//! # assert_eq!(2000, samples.len());
+//! # assert!(samples[..800].iter().all(|s| *s != 0));
+//! # assert!(samples[800..].iter().all(|s| *s == 0));
+//! let mut wav = Vec::new();
+//! lib_chip::audio::write_wav(&mut wav, 8000, &samples)?;
+//! # assert_eq!(44 + 4000, wav.len());
+//! # Ok::<(), std::io::Error>(())
+//! ```
+use std::io::{self, Write};
+use std::time::Duration;
+
+/// The sample rate used by `AudioConfig::default`
+pub const DEFAULT_SAMPLE_RATE: u32 = 44_100;
+/// The pitch of the beep used by `AudioConfig::default`, in Hz
+pub const DEFAULT_FREQUENCY: f32 = 440.0;
+/// The loudness used by `AudioConfig::default`, from 0 to 1
+pub const DEFAULT_VOLUME: f32 = 0.25;
+
+const NANOS_PER_SECOND: u128 = 1_000_000_000;
+
+/// Describes the sound to make.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AudioConfig {
+    /// Samples each second
+    pub sample_rate: u32,
+    /// The pitch of the square wave, in Hz
+    pub frequency: f32,
+    /// The loudness of the square wave, from 0 to 1
+    pub volume: f32,
+}
+
+impl Default for AudioConfig {
+    fn default() -> Self {
+        AudioConfig {
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            frequency: DEFAULT_FREQUENCY,
+            volume: DEFAULT_VOLUME,
+        }
+    }
+}
+
+/// Generates samples of the beep as emulated time passes.
+#[derive(Debug, Clone)]
+pub struct Beeper {
+    config: AudioConfig,
+    /// The samples generated since the beeper started
+    generated: u64,
+    /// How far through a cycle of the wave the next sample is, from 0 to 1
+    phase: f64,
+    samples: Vec<i16>,
+}
+
+impl Beeper {
+    pub fn new(config: AudioConfig) -> Beeper {
+        Beeper {
+            config,
+            generated: 0,
+            phase: 0.0,
+            samples: Vec::new(),
+        }
+    }
+
+    pub fn config(&self) -> &AudioConfig {
+        &self.config
+    }
+
+    /// Generates the samples up to `elapsed` emulated time since the beeper
+    /// started, beeping if `playing`.
+    ///
+    /// Times are rounded to the nearest sample and times before the last
+    /// sample generated are ignored.
+    pub fn advance(&mut self, elapsed: Duration, playing: bool) {
+        let scaled = elapsed.as_nanos() * u128::from(self.config.sample_rate);
+        let due = ((scaled + NANOS_PER_SECOND / 2) / NANOS_PER_SECOND) as u64;
+        if due <= self.generated {
+            return;
+        }
+
+        let count = (due - self.generated) as usize;
+        self.generated = due;
+        if !playing {
+            // the wave restarts with each beep so every beep sounds the same
+            self.phase = 0.0;
+            self.samples.resize(self.samples.len() + count, 0);
+            return;
+        }
+
+        let amplitude = f64::from(self.config.volume.clamp(0.0, 1.0)) * f64::from(i16::MAX);
+        let step = f64::from(self.config.frequency) / f64::from(self.config.sample_rate.max(1));
+        for _ in 0..count {
+            let sample = if self.phase < 0.5 { amplitude } else { -amplitude };
+            self.samples.push(sample as i16);
+            self.phase = (self.phase + step).fract();
+        }
+    }
+
+    /// Returns the samples generated since they were last taken
+    pub fn take_samples(&mut self) -> Vec<i16> {
+        std::mem::take(&mut self.samples)
+    }
+
+    /// Returns the samples generated since the beeper started
+    pub fn generated(&self) -> u64 {
+        self.generated
+    }
+
+    /// Starts again from no time, dropping samples not yet taken
+    pub fn reset(&mut self) {
+        *self = Beeper::new(self.config);
+    }
+}
+
+/// Writes samples as a 16-bit mono WAV file.
+pub fn write_wav<W: Write>(writer: &mut W, sample_rate: u32, samples: &[i16]) -> io::Result<()> {
+    let data_size = (samples.len() * 2) as u32;
+    let block_align: u16 = 2;
+    let bits_per_sample: u16 = 16;
+
+    writer.write_all(b"RIFF")?;
+    writer.write_all(&(36 + data_size).to_le_bytes())?;
+    writer.write_all(b"WAVE")?;
+
+    writer.write_all(b"fmt ")?;
+    writer.write_all(&16u32.to_le_bytes())?;
+    // PCM with one channel
+    writer.write_all(&1u16.to_le_bytes())?;
+    writer.write_all(&1u16.to_le_bytes())?;
+    writer.write_all(&sample_rate.to_le_bytes())?;
+    writer.write_all(&(sample_rate * u32::from(block_align)).to_le_bytes())?;
+    writer.write_all(&block_align.to_le_bytes())?;
+    writer.write_all(&bits_per_sample.to_le_bytes())?;
+
+    writer.write_all(b"data")?;
+    writer.write_all(&data_size.to_le_bytes())?;
+    for sample in samples {
+        writer.write_all(&sample.to_le_bytes())?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn beeper(frequency: f32) -> Beeper {
+        Beeper::new(AudioConfig { sample_rate: 1000, frequency, volume: 1.0 })
+    }
+
+    #[test]
+    fn it_will_generate_a_square_wave() {
+        let mut beeper = beeper(250.0);
+
+        beeper.advance(Duration::from_millis(8), true);
+
+        let high = i16::MAX;
+        assert_eq!(vec![high, high, -high, -high, high, high, -high, -high], beeper.take_samples());
+    }
+
+    #[test]
+    fn it_will_be_silent_when_not_playing() {
+        let mut beeper = beeper(250.0);
+
+        beeper.advance(Duration::from_millis(5), false);
+
+        assert_eq!(vec![0; 5], beeper.take_samples());
+    }
+
+    #[test]
+    fn it_will_not_drift_however_time_is_split() {
+        let mut beeper = Beeper::new(Default::default());
+
+        for frame in 1..=600u64 {
+            beeper.advance(Duration::from_nanos(frame * 1_000_000_000 / 60), frame % 2 == 0);
+        }
+
+        assert_eq!(10 * 44_100, beeper.generated());
+        assert_eq!(10 * 44_100, beeper.take_samples().len());
+    }
+
+    #[test]
+    fn it_will_ignore_times_already_generated() {
+        let mut beeper = beeper(250.0);
+
+        beeper.advance(Duration::from_millis(4), true);
+        beeper.advance(Duration::from_millis(2), true);
+
+        assert_eq!(4, beeper.generated());
+    }
+
+    #[test]
+    fn it_will_write_a_wav_header() {
+        let mut wav = Vec::new();
+
+        write_wav(&mut wav, 22_050, &[0x1234, -1]).unwrap();
+
+        assert_eq!(b"RIFF", &wav[0..4]);
+        assert_eq!(40u32.to_le_bytes(), wav[4..8]);
+        assert_eq!(b"WAVEfmt ", &wav[8..16]);
+        assert_eq!(22_050u32.to_le_bytes(), wav[24..28]);
+        assert_eq!(b"data", &wav[36..40]);
+        assert_eq!(vec![0x34, 0x12, 0xFF, 0xFF], wav[44..].to_vec());
+    }
+}
@@ -0,0 +1,354 @@
+//! Runs instructions at a fixed rate and the timers at 60Hz in emulated time.
+//!
+//! The clock counts emulated time in exact fractions of a second, so the
+//! timers tick 60 times for every second of instructions however the time is
+//! split between calls.  Each timer tick also sets `State::vblank`, marking
+//! the start of a new frame.
+//!
+//! # Example:
+//!
+//! ```
+//! # use std::time::Duration;
+//! # use lib_chip::state::State;
+//! # use lib_chip::memory::Memory;
+//! # use lib_chip::clock::Clock;
+//! # use lib_chip::keypad::Keypad;
+//! let mut memory = Memory::new();
+//! memory.set_range(0x200, &[0x12, 0x00]);
+//! let mut state = State { delay_timer: 60, ..Default::default() };
+//! let mut screen = state.create_buffer();
+//!
+//! let mut clock = Clock::new(600);
+//! state = clock.run_for(Duration::from_millis(500), state, &mut memory, &mut Keypad::new(), &mut screen)?;
+//! # assert_eq!(30, state.delay_timer);
+//! # assert_eq!(300, clock.instructions());
+//! # Ok::<(), lib_chip::state::ExecError>(())
+//! ```
+use std::time::Duration;
+use crate::framebuffer::FrameBuffer;
+use crate::keypad::Keypad;
+use crate::memory::Memory;
+use crate::state::{self, ExecError, State};
+
+/// The rate the delay and sound timers count down at
+pub const TIMER_HZ: u32 = 60;
+/// The instruction rate used by `Clock::default`, 10 instructions a frame
+pub const DEFAULT_INSTRUCTIONS_PER_SECOND: u32 = 600;
+
+const NANOS_PER_SECOND: u128 = 1_000_000_000;
+
+/// Schedules instructions and timer ticks.
+///
+/// Time is counted in units of 1 / (60 * instructions per second) seconds, so
+/// instruction n runs at time 60n and timer tick k at time k times the
+/// instruction rate.
+#[derive(Debug, Clone)]
+pub struct Clock {
+    instructions_per_second: u32,
+    time: u64,
+    /// Nanoseconds left over from `run_for` too short to make a unit of time,
+    /// scaled by the units in a second
+    carry: u128,
+    instructions: u64,
+    frames: u64,
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Clock::new(DEFAULT_INSTRUCTIONS_PER_SECOND)
+    }
+}
+
+/// Something the clock schedules
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Event {
+    /// Run the next instruction
+    Instruction,
+    /// Count down the timers and start a new frame
+    Tick,
+}
+
+/// Counts down the timers and signals the start of a frame
+pub fn tick(state: State) -> State {
+    let mut state = state;
+    count_down(&mut state);
+    state
+}
+
+/// Counts down the timers and signals the start of a frame, in place
+pub fn count_down(state: &mut State) {
+    state.delay_timer = state::delay_timer(state);
+    state.sound_timer = state::sound_timer(state);
+    state.vblank = true;
+}
+
+impl Clock {
+    /// Creates a clock that runs the given number of instructions each second, at least one.
+    pub fn new(instructions_per_second: u32) -> Clock {
+        Clock {
+            instructions_per_second: instructions_per_second.max(1),
+            time: 0,
+            carry: 0,
+            instructions: 0,
+            frames: 0,
+        }
+    }
+
+    pub fn instructions_per_second(&self) -> u32 {
+        self.instructions_per_second
+    }
+
+    /// Returns the number of instructions run so far
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    /// Returns the number of times the timers have ticked so far
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Returns the emulated time that has passed
+    pub fn elapsed(&self) -> Duration {
+        self.to_duration(self.time)
+    }
+
+    /// Converts units of time to the nearest nanosecond
+    fn to_duration(&self, time: u64) -> Duration {
+        let units = self.units_per_second();
+        let nanos = (u128::from(time) * NANOS_PER_SECOND + units / 2) / units;
+        Duration::from_nanos(nanos as u64)
+    }
+
+    fn units_per_second(&self) -> u128 {
+        u128::from(TIMER_HZ) * u128::from(self.instructions_per_second)
+    }
+
+    /// Passes every instruction before `target` and every timer tick up to
+    /// and including it to the handler, in the order they fall, along with
+    /// the emulated time each falls at.
+    fn run_until<F>(&mut self, target: u64, mut handle: F) -> Result<(), ExecError>
+        where F: FnMut(Event, Duration) -> Result<(), ExecError> {
+        self.run_until_batched(target, |event, at, _| handle(event, at).map(|_| 1))
+    }
+
+    /// Like `run_until`, but passes the handler the most instructions it may
+    /// run before the next event, and the handler returns how many it ran.
+    fn run_until_batched<F>(&mut self, target: u64, mut handle: F) -> Result<(), ExecError>
+        where F: FnMut(Event, Duration, u64) -> Result<u64, ExecError> {
+        let ips = u64::from(self.instructions_per_second);
+        let per_instruction = u64::from(TIMER_HZ);
+
+        loop {
+            let next_instruction = self.instructions * per_instruction;
+            let next_tick = (self.frames + 1) * ips;
+
+            if next_tick <= target && next_tick <= next_instruction {
+                handle(Event::Tick, self.to_duration(next_tick), 0)?;
+                self.frames += 1;
+            } else if next_instruction < target {
+                // instructions run before a tick that falls at the same time
+                let end = if next_tick <= target { next_tick } else { target };
+                let due = end.div_ceil(per_instruction) - self.instructions;
+                let ran = handle(Event::Instruction, self.to_duration(next_instruction), due)?;
+                self.instructions += ran.clamp(1, due);
+            } else {
+                break;
+            }
+        }
+
+        self.time = target;
+        Ok(())
+    }
+
+    fn target_after(&mut self, duration: Duration) -> u64 {
+        let scaled = duration.as_nanos() * self.units_per_second() + self.carry;
+        self.carry = scaled % NANOS_PER_SECOND;
+        self.time + (scaled / NANOS_PER_SECOND) as u64
+    }
+
+    fn next_frame(&self) -> u64 {
+        (self.frames + 1) * u64::from(self.instructions_per_second)
+    }
+
+    /// Passes the events that fall in the next `duration` of emulated time to
+    /// the handler, with the time since the clock started that each falls at.
+    ///
+    /// An error from the handler stops the clock, the failed event is not counted.
+    pub fn run_for_with<F>(&mut self, duration: Duration, handle: F) -> Result<(), ExecError>
+        where F: FnMut(Event, Duration) -> Result<(), ExecError> {
+        let target = self.target_after(duration);
+        self.run_until(target, handle)
+    }
+
+    /// Passes the events up to and including the next timer tick to the handler.
+    pub fn run_frame_with<F>(&mut self, handle: F) -> Result<(), ExecError>
+        where F: FnMut(Event, Duration) -> Result<(), ExecError> {
+        let target = self.next_frame();
+        self.run_until(target, handle)
+    }
+
+    /// Like `run_frame_with`, but passes runs of instructions to the handler at once.
+    ///
+    /// Along with each `Event::Instruction` the handler is given the number of
+    /// instructions due before the next event, with the time the first falls
+    /// at, and returns how many it ran.  It should run at least one, and an
+    /// error should mean none of them ran.
+    pub fn run_frame_batched<F>(&mut self, handle: F) -> Result<(), ExecError>
+        where F: FnMut(Event, Duration, u64) -> Result<u64, ExecError> {
+        let target = self.next_frame();
+        self.run_until_batched(target, handle)
+    }
+
+    /// Passes the next instruction to the handler, after any timer ticks that fall before it.
+    ///
+    /// An error from the handler stops the clock, the failed instruction is
+    /// passed again by the next call.
+    pub fn run_instruction_with<F>(&mut self, handle: F) -> Result<(), ExecError>
+        where F: FnMut(Event, Duration) -> Result<(), ExecError> {
+        let target = self.instructions * u64::from(TIMER_HZ) + 1;
+        self.run_until(target, handle)
+    }
+
+    /// Runs the instructions and timer ticks that fall in the next `duration` of emulated time.
+    pub fn run_for(&mut self, duration: Duration, state: State, memory: &mut Memory,
+        keypad: &mut Keypad, screen: &mut FrameBuffer) -> Result<State, ExecError> {
+        let target = self.target_after(duration);
+        run_state(self, target, state, memory, keypad, screen)
+    }
+
+    /// Runs instructions up to and including the next timer tick.
+    pub fn run_frame(&mut self, state: State, memory: &mut Memory,
+        keypad: &mut Keypad, screen: &mut FrameBuffer) -> Result<State, ExecError> {
+        let target = self.next_frame();
+        run_state(self, target, state, memory, keypad, screen)
+    }
+}
+
+/// Steps a state through the events up to `target`
+fn run_state(clock: &mut Clock, target: u64, state: State, memory: &mut Memory,
+    keypad: &mut Keypad, screen: &mut FrameBuffer) -> Result<State, ExecError> {
+    let mut state = state;
+    clock.run_until(target, |event, _| {
+        match event {
+            Event::Tick => count_down(&mut state),
+            Event::Instruction => state.execute(memory, keypad, screen)?
+        }
+        Ok(())
+    })?;
+
+    Ok(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A program that counts its loops in V0
+    fn counter() -> Memory {
+        let mut memory = Memory::new();
+        memory.set_range(0x200, &[0x70, 0x01, 0x12, 0x00]);
+        memory
+    }
+
+    #[test]
+    fn it_will_run_a_frame_of_instructions() {
+        let mut memory = counter();
+        let state = State { delay_timer: 5, sound_timer: 1, ..Default::default() };
+        let mut screen = state.create_buffer();
+        let mut clock = Clock::new(600);
+
+        let state = clock.run_frame(state, &mut memory, &mut Keypad::new(), &mut screen).unwrap();
+
+        assert_eq!(10, clock.instructions());
+        assert_eq!(1, clock.frames());
+        assert_eq!(5, state.registers[0]);
+        assert_eq!(4, state.delay_timer);
+        assert_eq!(0, state.sound_timer);
+        assert!(state.vblank);
+    }
+
+    #[test]
+    fn it_will_tick_at_sixty_hertz_however_time_is_split() {
+        let mut memory = counter();
+        let mut state = State { delay_timer: 200, ..Default::default() };
+        let mut screen = state.create_buffer();
+        let mut clock = Clock::new(1000);
+
+        for _ in 0..3000 {
+            state = clock.run_for(Duration::from_micros(333), state, &mut memory, &mut Keypad::new(), &mut screen).unwrap();
+        }
+
+        let mut whole = Clock::new(1000);
+        let mut memory = counter();
+        let other = State { delay_timer: 200, ..Default::default() };
+        let other = whole.run_for(Duration::from_micros(999_000), other, &mut memory, &mut Keypad::new(), &mut screen).unwrap();
+
+        assert_eq!(whole.instructions(), clock.instructions());
+        assert_eq!(59, clock.frames());
+        assert_eq!(999, clock.instructions());
+        assert_eq!(other.delay_timer, state.delay_timer);
+        assert_eq!(Duration::from_micros(999_000), clock.elapsed());
+    }
+
+    #[test]
+    fn it_will_not_run_instructions_with_no_time() {
+        let mut memory = counter();
+        let state: State = Default::default();
+        let mut screen = state.create_buffer();
+        let mut clock = Clock::new(600);
+
+        let state = clock.run_for(Duration::from_nanos(1), state, &mut memory, &mut Keypad::new(), &mut screen).unwrap();
+        clock.run_for(Duration::from_nanos(0), state, &mut memory, &mut Keypad::new(), &mut screen).unwrap();
+
+        assert_eq!(0, clock.instructions());
+        assert_eq!(0, clock.frames());
+    }
+
+    #[test]
+    fn it_will_pass_the_time_of_each_event() {
+        let mut clock = Clock::new(120);
+        let mut events = Vec::new();
+
+        clock.run_frame_with(|event, at| {
+            events.push((event, at.as_micros()));
+            Ok(())
+        }).unwrap();
+
+        assert_eq!(vec![(Event::Instruction, 0), (Event::Instruction, 8333), (Event::Tick, 16666)], events);
+    }
+
+    #[test]
+    fn it_will_pass_one_instruction_at_a_time() {
+        let mut clock = Clock::new(120);
+        let mut events = Vec::new();
+
+        for _ in 0..3 {
+            clock.run_instruction_with(|event, _| {
+                events.push(event);
+                Ok(())
+            }).unwrap();
+        }
+
+        assert_eq!(vec![Event::Instruction, Event::Instruction, Event::Tick, Event::Instruction], events);
+        assert_eq!(3, clock.instructions());
+        assert_eq!(1, clock.frames());
+    }
+
+    #[test]
+    fn it_will_batch_the_instructions_between_ticks() {
+        let mut clock = Clock::new(1000);
+        let mut batches = Vec::new();
+
+        clock.run_frame_batched(|event, _, due| {
+            batches.push((event, due));
+            Ok(due.min(7))
+        }).unwrap();
+
+        // instructions run every 60 units of time and the tick falls at 1000
+        let expected = vec![(Event::Instruction, 17), (Event::Instruction, 10), (Event::Instruction, 3), (Event::Tick, 0)];
+        assert_eq!(expected, batches);
+        assert_eq!(17, clock.instructions());
+        assert_eq!(1, clock.frames());
+    }
+}
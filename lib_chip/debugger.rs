@@ -0,0 +1,549 @@
+//! An interactive debugger that runs a program one instruction at a time.
+//!
+//! The debugger owns the state, memory and screen of a program and stops
+//! execution at breakpoints, memory watchpoints and register watchpoints.
+//! Instructions are timed by a `Clock`, which counts down the timers and
+//! starts each frame as the program runs.
+//!
+//! # Example:
+//!
+//! ```
+//! # use lib_chip::state::State;
+//! # use lib_chip::memory::Memory;
+//! # use lib_chip::debugger::{Debugger, StopReason};
+//! let mut memory = Memory::new();
+//! memory.set_range(0x200, &[0x60, 0x01, 0x61, 0x02, 0x12, 0x04]);
+//!
+//! let mut debugger = Debugger::new(Default::default(), memory);
+//! debugger.add_breakpoint(0x204);
+//!
+//! let reason = debugger.resume();
+//! # assert_eq!(StopReason::Breakpoint(0x204), reason);
+//! # assert_eq!(0x2, debugger.state().registers[1]);
+//! ```
+use std::collections::BTreeSet;
+use crate::clock::{self, Clock, Event};
+use crate::framebuffer::FrameBuffer;
+use crate::keypad::Keypad;
+use crate::memory::Memory;
+use crate::opcode::{OpCode, LoadOp};
+use crate::opcode::parser::{is_long_opcode, parse_long_opcode, parse_opcode};
+use crate::state::{ExecError, State};
+
+/// The most instructions run by a single call before giving up
+pub const DEFAULT_STEP_LIMIT: usize = 1_000_000;
+
+/// A register that can be watched for changes
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum WatchRegister {
+    /// One of the registers V0 to VF
+    V(u8),
+    I,
+}
+
+/// The kind of memory access a watchpoint stops on
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+/// Describes why the debugger stopped running the program.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum StopReason {
+    /// A single instruction, or a whole routine when stepping over a call, was executed
+    Stepped,
+    /// The routine being stepped out of returned
+    Returned,
+    /// The program counter reached a breakpoint
+    Breakpoint(u16),
+    /// The instruction at pc read a watched address
+    MemoryRead { pc: u16, address: u16 },
+    /// The instruction at pc wrote to a watched address
+    MemoryWrite { pc: u16, address: u16 },
+    /// The instruction at pc changed the value of a watched register
+    RegisterChanged { pc: u16, register: WatchRegister, old: u16, new: u16 },
+    /// The instruction at pc is waiting for a key to be pressed
+    WaitingForKey(u16),
+    /// The program has exited
+    Exited,
+    /// The step limit was reached without any other reason to stop
+    StepLimit,
+    /// The instruction could not be executed, the state is left as it was before it
+    Error(ExecError),
+}
+
+pub struct Debugger {
+    state: State,
+    memory: Memory,
+    screen: FrameBuffer,
+    keypad: Keypad,
+    /// The keypad as the last instruction left it, its edges are cleared at
+    /// the next tick if they have not changed since
+    keys_seen: Option<Keypad>,
+    clock: Clock,
+    breakpoints: BTreeSet<u16>,
+    read_watchpoints: BTreeSet<u16>,
+    write_watchpoints: BTreeSet<u16>,
+    registers: BTreeSet<WatchRegister>,
+    step_limit: usize,
+}
+
+/// Returns the register's value
+fn register_value(state: &State, register: WatchRegister) -> u16 {
+    match register {
+        WatchRegister::V(x) => u16::from(state.registers[usize::from(x & 0xF)]),
+        WatchRegister::I => state.i
+    }
+}
+
+/// Decodes the instruction the state will execute next, if it lies in memory.
+pub(crate) fn next_opcode(state: &State, memory: &Memory) -> Option<OpCode> {
+    if let Some(opcode) = state.opcode {
+        return Some(opcode);
+    }
+
+    let pc = usize::from(state.pc);
+    if pc + 1 >= memory.size() {
+        return None;
+    }
+
+    let (high, low) = (memory.read(state.pc), memory.read(state.pc + 1));
+    if !is_long_opcode(high, low) {
+        return Some(parse_opcode(high, low));
+    }
+
+    if pc + 3 >= memory.size() {
+        return None;
+    }
+
+    Some(parse_long_opcode(high, low, memory.read(state.pc + 2), memory.read(state.pc + 3)))
+}
+
+/// Returns the number of registers transferred by the XO-CHIP range loads
+fn register_count(x: u8, y: u8) -> usize {
+    usize::from(x.abs_diff(y)) + 1
+}
+
+/// Returns the number of bytes an opcode reads and writes starting at I,
+/// instruction fetches are not counted.
+pub(crate) fn memory_access(state: &State, opcode: OpCode) -> (usize, usize) {
+    match opcode {
+        // a draw waiting for the display reads nothing until it runs
+        OpCode::DRW(_, _, _) if state.quirks.display_wait && !state.vblank => (0, 0),
+        OpCode::DRW(_, _, n) => {
+            let planes = (state.plane & 0x3).count_ones() as usize;
+            let bytes = if n == 0 { 32 } else { usize::from(n) };
+            (bytes * planes, 0)
+        },
+        OpCode::AUDIO => (16, 0),
+        OpCode::LD(LoadOp::LDV0XI(x)) => (usize::from(x) + 1, 0),
+        OpCode::LD(LoadOp::LDVXYI(x, y)) => (register_count(x, y), 0),
+        OpCode::LD(LoadOp::LDB(_)) => (0, 3),
+        OpCode::LD(LoadOp::LDIV0X(x)) => (0, usize::from(x) + 1),
+        OpCode::LD(LoadOp::LDIVXY(x, y)) => (0, register_count(x, y)),
+        _ => (0, 0)
+    }
+}
+
+/// Returns the first watched address in the bytes from start
+fn first_watched(watchpoints: &BTreeSet<u16>, start: u16, len: usize) -> Option<u16> {
+    if len == 0 {
+        return None;
+    }
+
+    let end = usize::from(start) + len;
+    watchpoints
+        .range(start..)
+        .find(|address| usize::from(**address) < end)
+        .copied()
+}
+
+impl Debugger {
+    /// Creates a debugger for a program already loaded into memory.
+    pub fn new(state: State, memory: Memory) -> Debugger {
+        let screen = state.create_buffer();
+        Debugger {
+            state,
+            memory,
+            screen,
+            keypad: Keypad::new(),
+            keys_seen: None,
+            clock: Clock::default(),
+            breakpoints: BTreeSet::new(),
+            read_watchpoints: BTreeSet::new(),
+            write_watchpoints: BTreeSet::new(),
+            registers: BTreeSet::new(),
+            step_limit: DEFAULT_STEP_LIMIT,
+        }
+    }
+
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    /// Allows registers and flags to be changed between steps
+    pub fn state_mut(&mut self) -> &mut State {
+        &mut self.state
+    }
+
+    pub fn memory(&self) -> &Memory {
+        &self.memory
+    }
+
+    pub fn memory_mut(&mut self) -> &mut Memory {
+        &mut self.memory
+    }
+
+    pub fn screen(&self) -> &FrameBuffer {
+        &self.screen
+    }
+
+    /// Allows keys to be pressed and released between steps
+    pub fn keypad_mut(&mut self) -> &mut Keypad {
+        &mut self.keypad
+    }
+
+    pub fn clock(&self) -> &Clock {
+        &self.clock
+    }
+
+    /// Replaces the clock, to run the program at another instruction rate
+    pub fn set_clock(&mut self, clock: Clock) {
+        self.clock = clock;
+    }
+
+    /// Sets the most instructions a single call to `resume`, `step_over` or
+    /// `step_out` will execute.
+    pub fn set_step_limit(&mut self, limit: usize) {
+        self.step_limit = limit;
+    }
+
+    pub fn add_breakpoint(&mut self, address: u16) {
+        self.breakpoints.insert(address);
+    }
+
+    /// Removes a breakpoint, returning false if there was none at the address
+    pub fn remove_breakpoint(&mut self, address: u16) -> bool {
+        self.breakpoints.remove(&address)
+    }
+
+    pub fn watch_memory(&mut self, address: u16, access: Access) {
+        match access {
+            Access::Read => self.read_watchpoints.insert(address),
+            Access::Write => self.write_watchpoints.insert(address)
+        };
+    }
+
+    /// Removes a memory watchpoint, returning false if there was none
+    pub fn unwatch_memory(&mut self, address: u16, access: Access) -> bool {
+        match access {
+            Access::Read => self.read_watchpoints.remove(&address),
+            Access::Write => self.write_watchpoints.remove(&address)
+        }
+    }
+
+    pub fn watch_register(&mut self, register: WatchRegister) {
+        self.registers.insert(register);
+    }
+
+    /// Removes a register watchpoint, returning false if there was none
+    pub fn unwatch_register(&mut self, register: WatchRegister) -> bool {
+        self.registers.remove(&register)
+    }
+
+    /// Executes one instruction, returning a reason to stop if it triggered a
+    /// watchpoint, exited, failed or is waiting for a key.
+    fn execute(&mut self) -> Option<StopReason> {
+        if !self.state.run_flag {
+            return Some(StopReason::Exited);
+        }
+
+        let pc = self.state.pc;
+        let i = self.state.i;
+        let mut access = (0, 0);
+        let before: Vec<u16> = self.registers.iter().map(|r| register_value(&self.state, *r)).collect();
+
+        // a failed instruction leaves the state as it was
+        let Debugger { state, memory, screen, keypad, keys_seen, clock, .. } = self;
+        let result = clock.run_instruction_with(|event, _| {
+            match event {
+                Event::Tick => {
+                    clock::count_down(state);
+                    // as in a frame, keys pressed since the last instruction are still seen
+                    if *keys_seen == Some(*keypad) {
+                        keypad.clear_edges();
+                    }
+                },
+                Event::Instruction => {
+                    // taken after any tick, which may let a waiting draw run
+                    access = next_opcode(state, memory)
+                        .map(|opcode| memory_access(state, opcode))
+                        .unwrap_or((0, 0));
+                    state.execute(memory, keypad, screen)?;
+                    *keys_seen = Some(*keypad);
+                }
+            }
+            Ok(())
+        });
+        if let Err(err) = result {
+            return Some(StopReason::Error(err));
+        }
+
+        let (reads, writes) = access;
+        if let Some(OpCode::LD(LoadOp::LDKEY(_))) = self.state.opcode {
+            return Some(StopReason::WaitingForKey(pc));
+        }
+
+        if let Some(address) = first_watched(&self.read_watchpoints, i, reads) {
+            return Some(StopReason::MemoryRead { pc, address });
+        }
+
+        if let Some(address) = first_watched(&self.write_watchpoints, i, writes) {
+            return Some(StopReason::MemoryWrite { pc, address });
+        }
+
+        let changed = self.registers
+            .iter()
+            .zip(before)
+            .map(|(register, old)| (*register, old, register_value(&self.state, *register)))
+            .find(|(_, old, new)| old != new);
+        if let Some((register, old, new)) = changed {
+            return Some(StopReason::RegisterChanged { pc, register, old, new });
+        }
+
+        if !self.state.run_flag {
+            return Some(StopReason::Exited);
+        }
+
+        None
+    }
+
+    /// Executes instructions until one stops execution, a breakpoint is reached,
+    /// `done` returns true or the step limit is reached.
+    fn run_until<F>(&mut self, done: F, reason: StopReason) -> StopReason
+        where F: Fn(&State) -> bool {
+        for _ in 0..self.step_limit {
+            if let Some(stop) = self.execute() {
+                return stop;
+            }
+
+            if done(&self.state) {
+                return reason;
+            }
+
+            if self.breakpoints.contains(&self.state.pc) {
+                return StopReason::Breakpoint(self.state.pc);
+            }
+        }
+
+        StopReason::StepLimit
+    }
+
+    /// Executes a single instruction.
+    pub fn step(&mut self) -> StopReason {
+        self.execute().unwrap_or(StopReason::Stepped)
+    }
+
+    /// Executes a single instruction, running any routine it calls until it returns.
+    pub fn step_over(&mut self) -> StopReason {
+        match next_opcode(&self.state, &self.memory) {
+            Some(OpCode::CALL(_)) => {
+                let depth = self.state.stack_pointer;
+                let next = self.state.pc.wrapping_add(2);
+                self.run_until(|state| state.pc == next && state.stack_pointer == depth, StopReason::Stepped)
+            },
+            _ => self.step()
+        }
+    }
+
+    /// Executes instructions until the current routine returns.
+    pub fn step_out(&mut self) -> StopReason {
+        let depth = self.state.stack_pointer;
+        self.run_until(|state| state.stack_pointer < depth, StopReason::Returned)
+    }
+
+    /// Continues execution until there is a reason to stop.
+    pub fn resume(&mut self) -> StopReason {
+        self.run_until(|_| false, StopReason::StepLimit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::Quirks;
+
+    fn load(program: &[u8]) -> Debugger {
+        let mut memory = Memory::new();
+        memory.set_range(0x200, program);
+        Debugger::new(Default::default(), memory)
+    }
+
+    #[test]
+    fn it_will_single_step() {
+        let mut debugger = load(&[0x60, 0x05, 0x61, 0x06]);
+
+        assert_eq!(StopReason::Stepped, debugger.step());
+        assert_eq!(0x202, debugger.state().pc);
+        assert_eq!(0x5, debugger.state().registers[0]);
+    }
+
+    #[test]
+    fn it_will_stop_at_breakpoints() {
+        let mut debugger = load(&[0x70, 0x01, 0x70, 0x01, 0x12, 0x00]);
+        debugger.add_breakpoint(0x204);
+
+        assert_eq!(StopReason::Breakpoint(0x204), debugger.resume());
+        assert_eq!(StopReason::Breakpoint(0x204), debugger.resume());
+        assert_eq!(0x4, debugger.state().registers[0]);
+
+        assert!(debugger.remove_breakpoint(0x204));
+        debugger.set_step_limit(10);
+        assert_eq!(StopReason::StepLimit, debugger.resume());
+    }
+
+    #[test]
+    fn it_will_step_over_calls() {
+        let mut debugger = load(&[0x22, 0x04, 0x00, 0xFD, 0x60, 0x07, 0x00, 0xEE]);
+
+        assert_eq!(StopReason::Stepped, debugger.step_over());
+        assert_eq!(0x202, debugger.state().pc);
+        assert_eq!(0x7, debugger.state().registers[0]);
+    }
+
+    #[test]
+    fn it_will_stop_at_breakpoints_inside_stepped_over_calls() {
+        let mut debugger = load(&[0x22, 0x04, 0x00, 0xFD, 0x60, 0x07, 0x00, 0xEE]);
+        debugger.add_breakpoint(0x206);
+
+        assert_eq!(StopReason::Breakpoint(0x206), debugger.step_over());
+    }
+
+    #[test]
+    fn it_will_step_out_of_routines() {
+        let mut debugger = load(&[0x22, 0x04, 0x00, 0xFD, 0x60, 0x07, 0x61, 0x08, 0x00, 0xEE]);
+        debugger.step();
+
+        assert_eq!(StopReason::Returned, debugger.step_out());
+        assert_eq!(0x202, debugger.state().pc);
+        assert_eq!(0x8, debugger.state().registers[1]);
+    }
+
+    #[test]
+    fn it_will_stop_on_watched_memory_writes() {
+        let mut debugger = load(&[0xA3, 0x00, 0x60, 0x01, 0xF2, 0x55, 0x12, 0x06]);
+        debugger.watch_memory(0x302, Access::Write);
+
+        assert_eq!(StopReason::MemoryWrite { pc: 0x204, address: 0x302 }, debugger.resume());
+    }
+
+    #[test]
+    fn it_will_stop_on_watched_memory_reads() {
+        let mut debugger = load(&[0xA3, 0x00, 0xD0, 0x13, 0x12, 0x04]);
+        debugger.watch_memory(0x301, Access::Read);
+        debugger.watch_memory(0x301, Access::Write);
+
+        assert_eq!(StopReason::MemoryRead { pc: 0x202, address: 0x301 }, debugger.resume());
+    }
+
+    #[test]
+    fn it_will_not_report_reads_by_a_draw_waiting_for_the_display() {
+        let mut memory = Memory::new();
+        memory.set_range(0x200, &[0xA3, 0x00, 0xD0, 0x11, 0x12, 0x04]);
+        let mut debugger = Debugger::new(State { quirks: Quirks::cosmac_vip(), ..Default::default() }, memory);
+        debugger.watch_memory(0x300, Access::Read);
+
+        assert_eq!(StopReason::Stepped, debugger.step());
+        assert_eq!(StopReason::Stepped, debugger.step());
+        assert_eq!(0x202, debugger.state().pc);
+
+        assert_eq!(StopReason::MemoryRead { pc: 0x202, address: 0x300 }, debugger.resume());
+        assert_eq!(0x204, debugger.state().pc);
+    }
+
+    #[test]
+    fn it_will_stop_when_watched_registers_change() {
+        let mut debugger = load(&[0x60, 0x00, 0x61, 0x02, 0xA1, 0x23, 0x12, 0x06]);
+        debugger.watch_register(WatchRegister::V(0));
+        debugger.watch_register(WatchRegister::I);
+
+        let expected = StopReason::RegisterChanged { pc: 0x204, register: WatchRegister::I, old: 0x0, new: 0x123 };
+        assert_eq!(expected, debugger.resume());
+    }
+
+    #[test]
+    fn it_will_report_waiting_for_keys() {
+        let mut debugger = load(&[0xF0, 0x0A]);
+
+        assert_eq!(StopReason::WaitingForKey(0x200), debugger.resume());
+
+        debugger.keypad_mut().press(0x4);
+        assert_eq!(StopReason::WaitingForKey(0x200), debugger.step());
+
+        debugger.keypad_mut().release(0x4);
+        assert_eq!(StopReason::Stepped, debugger.step());
+        assert_eq!(0x4, debugger.state().registers[0]);
+    }
+
+    #[test]
+    fn it_will_count_down_the_timers_as_it_runs() {
+        let mut debugger = load(&[0x12, 0x00]);
+        debugger.state_mut().delay_timer = 30;
+        debugger.set_step_limit(120);
+
+        assert_eq!(StopReason::StepLimit, debugger.resume());
+        // the tick before the 120th instruction has not been reached
+        assert_eq!(11, debugger.clock().frames());
+        assert_eq!(19, debugger.state().delay_timer);
+    }
+
+    #[test]
+    fn it_will_draw_when_waiting_for_the_display() {
+        let mut memory = Memory::new();
+        memory.set_range(0x200, &[0xD0, 0x15, 0x70, 0x01, 0x12, 0x00]);
+        let mut debugger = Debugger::new(State { quirks: Quirks::cosmac_vip(), ..Default::default() }, memory);
+        debugger.add_breakpoint(0x202);
+
+        assert_eq!(StopReason::Breakpoint(0x202), debugger.resume());
+        assert_eq!(StopReason::Breakpoint(0x202), debugger.resume());
+        assert_eq!(0x1, debugger.state().registers[0]);
+        assert_eq!(2, debugger.clock().frames());
+    }
+
+    #[test]
+    fn it_will_keep_keys_pressed_and_released_between_steps_for_a_frame() {
+        // every instruction starts a new frame
+        let mut debugger = load(&[0x60, 0x00, 0xF0, 0x0A]);
+        debugger.set_clock(Clock::new(60));
+        debugger.step();
+
+        debugger.keypad_mut().press(0x4);
+        debugger.keypad_mut().release(0x4);
+
+        assert_eq!(StopReason::Stepped, debugger.step());
+        assert_eq!(0x4, debugger.state().registers[0]);
+
+        let mut debugger = load(&[0x60, 0x00, 0x61, 0x00, 0xF0, 0x0A]);
+        debugger.set_clock(Clock::new(60));
+        debugger.step();
+
+        debugger.keypad_mut().press(0x4);
+        debugger.keypad_mut().release(0x4);
+        debugger.step();
+
+        assert_eq!(StopReason::WaitingForKey(0x204), debugger.step());
+    }
+
+    #[test]
+    fn it_will_report_exits_and_errors() {
+        let mut debugger = load(&[0x00, 0xFD]);
+        assert_eq!(StopReason::Exited, debugger.resume());
+        assert_eq!(StopReason::Exited, debugger.step());
+
+        let mut debugger = load(&[0x00, 0xEE]);
+        let err = ExecError::StackUnderflow { pc: 0x200, opcode: 0x00EE };
+        assert_eq!(StopReason::Error(err), debugger.resume());
+        assert_eq!(0x200, debugger.state().pc);
+    }
+}
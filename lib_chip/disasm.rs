@@ -0,0 +1,356 @@
+//! A disassembler that follows control flow to separate code from data.
+//!
+//! Decoding starts at 0x200 and follows every path through JP, CALL, the skip
+//! instructions and RET.  Bytes that are never reached are treated as data,
+//! so sprites are not shown as nonsense instructions.  `JP V0` jumps to an
+//! address only known when it runs, so the path ends there and the table it
+//! indexes is labelled but left as data.  The listing uses the
+//! syntax read by `asm::assemble` and assembles back to the same rom.
+//!
+//! # Example:
+//!
+//! ```
+//! # use lib_chip::rom::Rom;
+//! # use lib_chip::disasm::disassemble;
+//! let rom = Rom::from_memory(vec![0xA2, 0x04, 0x12, 0x02, 0x60, 0xF0]);
+//! let listing = disassemble(&rom).to_string();
+//! # assert_eq!("    LD I, 0x204\nlabel_202:\n    JP label_202\n    db 0x60, 0xF0\n", listing);
+//! ```
+use std::collections::BTreeMap;
+use std::fmt::{self, Display, Formatter};
+use crate::opcode::{AddOp, OpCode, ShiftOp, SkipOp, LoadOp, JumpOp, ScrollOp};
+use crate::opcode::parser::{is_long_opcode, parse_long_opcode, parse_opcode};
+use crate::rom::{Rom, PROGRAM_START};
+
+/// The most bytes written on a single `db` line
+const BYTES_PER_LINE: usize = 8;
+
+/// A rom split into instructions and data.
+pub struct Disassembly {
+    data: Vec<u8>,
+    /// Each decoded instruction keyed by its address
+    instructions: BTreeMap<u16, OpCode>,
+    /// The name given to each jump and call target
+    labels: BTreeMap<u16, String>,
+}
+
+/// Decodes the instruction at an offset into the rom, if the whole instruction is present.
+fn decode(data: &[u8], offset: usize) -> Option<OpCode> {
+    let high = *data.get(offset)?;
+    let low = *data.get(offset + 1)?;
+
+    if is_long_opcode(high, low) {
+        let next_high = *data.get(offset + 2)?;
+        let next_low = *data.get(offset + 3)?;
+        Some(parse_long_opcode(high, low, next_high, next_low))
+    } else {
+        Some(parse_opcode(high, low))
+    }
+}
+
+/// Returns the addresses execution may continue at after an instruction,
+/// along with any jump or call target it names.
+fn successors(data: &[u8], address: u16, opcode: OpCode) -> (Vec<u16>, Option<u16>) {
+    let next = address.wrapping_add(opcode.size());
+
+    match opcode {
+        OpCode::RET | OpCode::EXIT => (vec![], None),
+        OpCode::JP(JumpOp::JP(nnn)) => (vec![nnn], Some(nnn)),
+        // the offset added to nnn is unknown, so the path cannot be followed
+        OpCode::JP(JumpOp::JPV0(nnn)) => (vec![], Some(nnn)),
+        OpCode::CALL(nnn) => (vec![nnn, next], Some(nnn)),
+        OpCode::SKIP(_) => {
+            let skip = usize::from(next.wrapping_sub(PROGRAM_START));
+            let size = decode(data, skip).map(|o| o.size()).unwrap_or(2);
+            (vec![next, next.wrapping_add(size)], None)
+        },
+        _ => (vec![next], None)
+    }
+}
+
+/// Disassembles a rom loaded at 0x200.
+///
+/// Only bytes reached from 0x200 are decoded as instructions, everything
+/// else is listed as data.
+pub fn disassemble(rom: &Rom) -> Disassembly {
+    let data = rom.read_all().to_vec();
+    let mut instructions = BTreeMap::new();
+    let mut targets: BTreeMap<u16, bool> = BTreeMap::new();
+    let mut claimed = vec![false; data.len()];
+    let mut pending = vec![PROGRAM_START];
+
+    while let Some(address) = pending.pop() {
+        if address < PROGRAM_START || instructions.contains_key(&address) {
+            continue;
+        }
+
+        let offset = usize::from(address - PROGRAM_START);
+        let opcode = match decode(&data, offset) {
+            Some(OpCode::Unknown(_)) | None => continue,
+            Some(opcode) => opcode
+        };
+
+        let range = offset..offset + usize::from(opcode.size());
+        if claimed[range.clone()].iter().any(|c| *c) {
+            continue;
+        }
+
+        claimed[range].iter_mut().for_each(|c| *c = true);
+        instructions.insert(address, opcode);
+
+        let (next, target) = successors(&data, address, opcode);
+        if let Some(target) = target {
+            let is_call = matches!(opcode, OpCode::CALL(_));
+            *targets.entry(target).or_insert(false) |= is_call;
+        }
+
+        pending.extend(next);
+    }
+
+    let end = PROGRAM_START as usize + data.len();
+    let labels = targets
+        .into_iter()
+        .filter(|(address, _)| {
+            let address = usize::from(*address);
+            if address < PROGRAM_START as usize || address >= end {
+                return false;
+            }
+
+            // A label can only be placed at the start of a line
+            let offset = address - PROGRAM_START as usize;
+            !claimed[offset] || instructions.contains_key(&(address as u16))
+        })
+        .map(|(address, is_call)| {
+            let prefix = if is_call { "sub" } else { "label" };
+            (address, format!("{}_{:03X}", prefix, address))
+        })
+        .collect();
+
+    Disassembly { data, instructions, labels }
+}
+
+impl Disassembly {
+    /// Returns the instruction decoded at an address, or None if it holds data.
+    pub fn instruction(&self, address: u16) -> Option<OpCode> {
+        self.instructions.get(&address).copied()
+    }
+
+    /// Returns the label given to an address.
+    pub fn label(&self, address: u16) -> Option<&str> {
+        self.labels.get(&address).map(|l| &l[..])
+    }
+
+    fn address(&self, nnn: u16) -> String {
+        match self.labels.get(&nnn) {
+            Some(label) => label.clone(),
+            None => format!("0x{:03X}", nnn)
+        }
+    }
+
+    /// Formats an instruction in assembler syntax, naming labelled addresses.
+    fn mnemonic(&self, opcode: OpCode) -> String {
+        format_instruction(opcode, |nnn| self.address(nnn))
+    }
+}
+
+/// Formats an instruction in assembler syntax.
+///
+/// Example:
+///
+/// ```
+/// # use lib_chip::disasm::mnemonic;
+/// # use lib_chip::opcode::{OpCode, LoadOp};
+/// let text = mnemonic(OpCode::LD(LoadOp::LD(0xA, 0x05)));
+/// # assert_eq!("LD VA, 0x05", text);
+/// ```
+pub fn mnemonic(opcode: OpCode) -> String {
+    format_instruction(opcode, |nnn| format!("0x{:03X}", nnn))
+}
+
+fn format_instruction<F: Fn(u16) -> String>(opcode: OpCode, address: F) -> String {
+    match opcode {
+        OpCode::Unknown(raw) => format!("dw 0x{:04X}", raw),
+        OpCode::CLS => "CLS".to_string(),
+        OpCode::RET => "RET".to_string(),
+        OpCode::EXIT => "EXIT".to_string(),
+        OpCode::LOW => "LOW".to_string(),
+        OpCode::HIGH => "HIGH".to_string(),
+        OpCode::AUDIO => "AUDIO".to_string(),
+        OpCode::PLANE(n) => format!("PLANE {}", n),
+        OpCode::SCROLL(ScrollOp::SCD(n)) => format!("SCD {}", n),
+        OpCode::SCROLL(ScrollOp::SCR) => "SCR".to_string(),
+        OpCode::SCROLL(ScrollOp::SCL) => "SCL".to_string(),
+        OpCode::JP(JumpOp::JP(nnn)) => format!("JP {}", address(nnn)),
+        OpCode::JP(JumpOp::JPV0(nnn)) => format!("JP V0, {}", address(nnn)),
+        OpCode::CALL(nnn) => format!("CALL {}", address(nnn)),
+        OpCode::SKIP(SkipOp::SE(x, kk)) => format!("SE V{:X}, 0x{:02X}", x, kk),
+        OpCode::SKIP(SkipOp::SNE(x, kk)) => format!("SNE V{:X}, 0x{:02X}", x, kk),
+        OpCode::SKIP(SkipOp::SEXY(x, y)) => format!("SE V{:X}, V{:X}", x, y),
+        OpCode::SKIP(SkipOp::SNEXY(x, y)) => format!("SNE V{:X}, V{:X}", x, y),
+        OpCode::SKIP(SkipOp::SKP(x)) => format!("SKP V{:X}", x),
+        OpCode::SKIP(SkipOp::SKNP(x)) => format!("SKNP V{:X}", x),
+        OpCode::LD(LoadOp::LD(x, kk)) => format!("LD V{:X}, 0x{:02X}", x, kk),
+        OpCode::LD(LoadOp::LDI(nnn)) => format!("LD I, 0x{:03X}", nnn),
+        OpCode::LD(LoadOp::LDIL(nnnn)) => format!("LD I, LONG 0x{:04X}", nnnn),
+        OpCode::LD(LoadOp::LDXY(x, y)) => format!("LD V{:X}, V{:X}", x, y),
+        OpCode::LD(LoadOp::LDVXDT(x)) => format!("LD V{:X}, DT", x),
+        OpCode::LD(LoadOp::LDDTVX(x)) => format!("LD DT, V{:X}", x),
+        OpCode::LD(LoadOp::LDKEY(x)) => format!("LD V{:X}, K", x),
+        OpCode::LD(LoadOp::LDSTVX(x)) => format!("LD ST, V{:X}", x),
+        OpCode::LD(LoadOp::LDF(x)) => format!("LD F, V{:X}", x),
+        OpCode::LD(LoadOp::LDHF(x)) => format!("LD HF, V{:X}", x),
+        OpCode::LD(LoadOp::LDB(x)) => format!("LD B, V{:X}", x),
+        OpCode::LD(LoadOp::LDIV0X(x)) => format!("LD [I], V{:X}", x),
+        OpCode::LD(LoadOp::LDV0XI(x)) => format!("LD V{:X}, [I]", x),
+        OpCode::LD(LoadOp::LDRVX(x)) => format!("LD R, V{:X}", x),
+        OpCode::LD(LoadOp::LDVXR(x)) => format!("LD V{:X}, R", x),
+        OpCode::LD(LoadOp::LDPITCH(x)) => format!("LD PITCH, V{:X}", x),
+        OpCode::LD(LoadOp::LDIVXY(x, y)) => format!("SAVE V{:X}, V{:X}", x, y),
+        OpCode::LD(LoadOp::LDVXYI(x, y)) => format!("LOAD V{:X}, V{:X}", x, y),
+        OpCode::ADD(AddOp::ADD(x, kk)) => format!("ADD V{:X}, 0x{:02X}", x, kk),
+        OpCode::ADD(AddOp::ADDREG(x, y)) => format!("ADD V{:X}, V{:X}", x, y),
+        OpCode::ADD(AddOp::ADDI(x)) => format!("ADD I, V{:X}", x),
+        OpCode::OR(x, y) => format!("OR V{:X}, V{:X}", x, y),
+        OpCode::AND(x, y) => format!("AND V{:X}, V{:X}", x, y),
+        OpCode::XOR(x, y) => format!("XOR V{:X}, V{:X}", x, y),
+        OpCode::SUB(x, y) => format!("SUB V{:X}, V{:X}", x, y),
+        OpCode::SUBN(x, y) => format!("SUBN V{:X}, V{:X}", x, y),
+        OpCode::SHIFT(ShiftOp::SHR(x, y)) => format!("SHR V{:X}, V{:X}", x, y),
+        OpCode::SHIFT(ShiftOp::SHL(x, y)) => format!("SHL V{:X}, V{:X}", x, y),
+        OpCode::RND(x, kk) => format!("RND V{:X}, 0x{:02X}", x, kk),
+        OpCode::DRW(x, y, n) => format!("DRW V{:X}, V{:X}, {}", x, y, n)
+    }
+}
+
+impl Display for Disassembly {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let mut offset = 0;
+        let mut bytes: Vec<String> = Vec::new();
+
+        let flush = |f: &mut Formatter, bytes: &mut Vec<String>| -> fmt::Result {
+            if !bytes.is_empty() {
+                writeln!(f, "    db {}", bytes.join(", "))?;
+                bytes.clear();
+            }
+            Ok(())
+        };
+
+        while offset < self.data.len() {
+            let address = PROGRAM_START + offset as u16;
+
+            if let Some(label) = self.labels.get(&address) {
+                flush(f, &mut bytes)?;
+                writeln!(f, "{}:", label)?;
+            }
+
+            match self.instructions.get(&address) {
+                Some(opcode) => {
+                    flush(f, &mut bytes)?;
+                    writeln!(f, "    {}", self.mnemonic(*opcode))?;
+                    offset += usize::from(opcode.size());
+                },
+                None => {
+                    bytes.push(format!("0x{:02X}", self.data[offset]));
+                    if bytes.len() == BYTES_PER_LINE {
+                        flush(f, &mut bytes)?;
+                    }
+                    offset += 1;
+                }
+            }
+        }
+
+        flush(f, &mut bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+
+    fn listing(data: Vec<u8>) -> String {
+        disassemble(&Rom::from_memory(data)).to_string()
+    }
+
+    #[test]
+    fn it_will_label_jump_and_call_targets() {
+        let actual = listing(vec![0x22, 0x04, 0x12, 0x00, 0x00, 0xEE]);
+
+        assert_eq!("label_200:\n    CALL sub_204\n    JP label_200\nsub_204:\n    RET\n", actual);
+    }
+
+    #[test]
+    fn it_will_not_decode_unreachable_bytes() {
+        let actual = listing(vec![0x00, 0xEE, 0xF0, 0x90, 0x90, 0x90, 0xF0]);
+
+        assert_eq!("    RET\n    db 0xF0, 0x90, 0x90, 0x90, 0xF0\n", actual);
+    }
+
+    #[test]
+    fn it_will_follow_both_sides_of_a_skip() {
+        let actual = listing(vec![0x30, 0x01, 0x00, 0xEE, 0x00, 0xE0, 0x00, 0xEE]);
+
+        assert_eq!("    SE V0, 0x01\n    RET\n    CLS\n    RET\n", actual);
+    }
+
+    #[test]
+    fn it_will_skip_over_long_instructions() {
+        let disassembly = disassemble(&Rom::from_memory(vec![0x40, 0x00, 0xF0, 0x00, 0x12, 0x34, 0x00, 0xEE]));
+
+        assert_eq!(Some(OpCode::LD(LoadOp::LDIL(0x1234))), disassembly.instruction(0x202));
+        assert_eq!(Some(OpCode::RET), disassembly.instruction(0x206));
+        assert_eq!(None, disassembly.instruction(0x204));
+    }
+
+    #[test]
+    fn it_will_not_follow_indirect_jumps() {
+        let disassembly = disassemble(&Rom::from_memory(vec![0xB2, 0x04, 0x12, 0x00, 0x12, 0x00]));
+
+        assert_eq!(None, disassembly.instruction(0x202));
+        assert_eq!(None, disassembly.instruction(0x204));
+        assert_eq!("    JP V0, label_204\n    db 0x12, 0x00\nlabel_204:\n    db 0x12, 0x00\n", disassembly.to_string());
+    }
+
+    #[test]
+    fn it_will_leave_targets_outside_the_rom_as_addresses() {
+        let actual = listing(vec![0x13, 0x00]);
+
+        assert_eq!("    JP 0x300\n", actual);
+    }
+
+    #[test]
+    fn it_will_split_data_at_labels() {
+        let disassembly = disassemble(&Rom::from_memory(vec![0x12, 0x04, 0xAA, 0xBB, 0x00, 0xEE]));
+
+        assert_eq!(Some("label_204"), disassembly.label(0x204));
+        assert_eq!("    JP label_204\n    db 0xAA, 0xBB\nlabel_204:\n    RET\n", disassembly.to_string());
+    }
+
+    #[test]
+    fn it_will_reassemble_to_the_same_rom() {
+        let source = "
+            CLS
+            LD I, sprite
+            LD V0, 0
+        loop:
+            DRW V0, V1, 5
+            ADD V0, 8
+            SE V0, 0x40
+            JP loop
+            CALL wait
+            LD I, LONG 0x1234
+            EXIT
+        wait:
+            LD V2, K
+            SHR V2, V3
+            RET
+        sprite:
+            db 0xF0, 0x90, 0x90, 0x90, 0xF0, 0x20, 0x60, 0x20, 0x20, 0x70
+        ";
+        let rom = assemble(source).unwrap();
+
+        let listing = disassemble(&rom).to_string();
+
+        assert_eq!(rom, assemble(&listing).unwrap());
+    }
+}
@@ -0,0 +1,418 @@
+//! Runs code as basic blocks of pre-decoded micro-ops.
+//!
+//! The interpreter fetches, decodes and dispatches every instruction it runs.
+//! `BlockEngine` instead splits code into basic blocks, ending each at a jump,
+//! skip, CALL, RET or any other instruction that may not carry on to the next.
+//! A block is compiled once into micro-ops, with the common register and I
+//! instructions bound to their operands, and runs until it exits.  Every other
+//! instruction is handed to the interpreter already decoded, so the state
+//! changes exactly as it would running one instruction at a time.
+//!
+//! A block is forgotten when memory inside it is written, by the program or
+//! the frontend, and compiled again the next time it runs.
+//!
+//! # Example:
+//!
+//! ```
+//! # use lib_chip::engine::BlockEngine;
+//! # use lib_chip::keypad::Keypad;
+//! # use lib_chip::memory::Memory;
+//! # use lib_chip::state::State;
+//! let mut memory = Memory::new();
+//! // ADD V0, 1; ADD V1, 2; JP 0x200
+//! memory.set_range(0x200, &[0x70, 0x01, 0x71, 0x02, 0x12, 0x00]);
+//! let mut state: State = Default::default();
+//! let mut screen = state.create_buffer();
+//!
+//! let mut engine = BlockEngine::new();
+//! let ran = engine.run(&mut state, &mut memory, &mut Keypad::new(), &mut screen, 30)?;
+//! # assert_eq!(30, ran);
+//! # assert_eq!((10, 20), (state.registers[0], state.registers[1]));
+//! # assert_eq!(1, engine.blocks());
+//! # Ok::<(), lib_chip::state::ExecError>(())
+//! ```
+use crate::framebuffer::FrameBuffer;
+use crate::keypad::Keypad;
+use crate::memory::Memory;
+use crate::opcode::{AddOp, LoadOp, OpCode};
+use crate::state::{self, ExecError, State};
+
+/// The most instructions compiled into one block
+const MAX_BLOCK_STEPS: usize = 64;
+/// The most bytes a block can be compiled from, every instruction a long I load
+const MAX_BLOCK_BYTES: usize = MAX_BLOCK_STEPS * 4;
+
+/// How a machine runs instructions
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub enum Engine {
+    /// Decodes and dispatches one instruction at a time, the only engine that
+    /// can be traced and profiled
+    #[default]
+    Interpreter,
+    /// Runs basic blocks compiled by `BlockEngine`
+    Blocks,
+}
+
+/// An instruction bound to its operands
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum MicroOp {
+    SetByte(usize, u8),
+    AddByte(usize, u8),
+    Copy(usize, usize),
+    AddReg(usize, usize),
+    Sub(usize, usize),
+    SubN(usize, usize),
+    SetI(u16),
+    AddI(usize),
+    /// Any other instruction, run by the interpreter
+    Interpret,
+}
+
+impl MicroOp {
+    fn compile(opcode: OpCode) -> MicroOp {
+        match opcode {
+            OpCode::LD(LoadOp::LD(vx, kk)) => MicroOp::SetByte(usize::from(vx), kk),
+            OpCode::LD(LoadOp::LDXY(vx, vy)) => MicroOp::Copy(usize::from(vx), usize::from(vy)),
+            OpCode::LD(LoadOp::LDI(nnn)) => MicroOp::SetI(nnn),
+            OpCode::LD(LoadOp::LDIL(nnnn)) => MicroOp::SetI(nnnn),
+            OpCode::ADD(AddOp::ADD(vx, kk)) => MicroOp::AddByte(usize::from(vx), kk),
+            OpCode::ADD(AddOp::ADDREG(vx, vy)) => MicroOp::AddReg(usize::from(vx), usize::from(vy)),
+            OpCode::ADD(AddOp::ADDI(vx)) => MicroOp::AddI(usize::from(vx)),
+            OpCode::SUB(vx, vy) => MicroOp::Sub(usize::from(vx), usize::from(vy)),
+            OpCode::SUBN(vx, vy) => MicroOp::SubN(usize::from(vx), usize::from(vy)),
+            _ => MicroOp::Interpret
+        }
+    }
+
+    /// Applies the micro-op to the registers, leaving the pc to the caller
+    fn apply(self, state: &mut State) {
+        let registers = &mut state.registers;
+        match self {
+            MicroOp::SetByte(x, kk) => registers[x] = kk,
+            MicroOp::AddByte(x, kk) => registers[x] = registers[x].wrapping_add(kk),
+            MicroOp::Copy(x, y) => registers[x] = registers[y],
+            MicroOp::AddReg(x, y) => {
+                let (result, carry) = registers[x].overflowing_add(registers[y]);
+                registers[x] = result;
+                registers[0xF] = u8::from(carry);
+            },
+            MicroOp::Sub(x, y) => {
+                let (vx, vy) = (registers[x], registers[y]);
+                registers[x] = vx.wrapping_sub(vy);
+                registers[0xF] = u8::from(vx >= vy);
+            },
+            MicroOp::SubN(x, y) => {
+                let (vx, vy) = (registers[x], registers[y]);
+                registers[x] = vy.wrapping_sub(vx);
+                registers[0xF] = u8::from(vy >= vx);
+            },
+            MicroOp::SetI(nnn) => state.i = nnn,
+            MicroOp::AddI(x) => state.i = state.i.wrapping_add(u16::from(registers[x])),
+            MicroOp::Interpret => ()
+        }
+    }
+}
+
+/// An instruction of a block
+#[derive(Debug)]
+struct Step {
+    pc: u16,
+    next: u16,
+    raw: u16,
+    opcode: OpCode,
+    op: MicroOp,
+}
+
+/// A run of instructions entered at the first and left after the last
+#[derive(Debug)]
+struct Block {
+    /// The address after the last byte the instructions were compiled from
+    end: usize,
+    steps: Vec<Step>,
+}
+
+/// Returns true if the instruction may not carry on to the one after it.
+///
+/// Draws and key loads can leave the pc where it is to wait.
+fn ends_block(opcode: OpCode) -> bool {
+    matches!(opcode,
+        OpCode::JP(_) | OpCode::SKIP(_) | OpCode::CALL(_) | OpCode::RET |
+        OpCode::DRW(..) | OpCode::LD(LoadOp::LDKEY(_)) | OpCode::EXIT | OpCode::Unknown(_))
+}
+
+/// Returns true if the instruction sets the sound timer, which a run ends
+/// before so a frontend sees the sound start on the instruction it did.
+fn sets_sound(opcode: OpCode) -> bool {
+    matches!(opcode, OpCode::LD(LoadOp::LDSTVX(_)))
+}
+
+/// Compiles the block starting at `pc`, none if no instruction can be fetched there
+fn compile(pc: u16, memory: &mut Memory) -> Option<Block> {
+    let mut steps: Vec<Step> = Vec::new();
+    let mut address = pc;
+
+    while let Ok((raw, opcode)) = state::fetch_decoded(address, memory) {
+        if sets_sound(opcode) && !steps.is_empty() {
+            break;
+        }
+
+        let next = address.wrapping_add(opcode.size());
+        steps.push(Step { pc: address, next, raw, opcode, op: MicroOp::compile(opcode) });
+        if ends_block(opcode) || steps.len() == MAX_BLOCK_STEPS || next < address {
+            break;
+        }
+        address = next;
+    }
+
+    let last = steps.last()?;
+    let start = usize::from(pc);
+    let end = usize::from(last.pc) + usize::from(last.opcode.size());
+    memory.watch(start, end - start);
+    Some(Block { end, steps })
+}
+
+/// Runs the steps of a block, at most `limit` of them.
+///
+/// Returns the steps run, and the error of the step that failed if one did.
+fn run_block(block: &Block, state: &mut State, memory: &mut Memory, keypad: &mut Keypad,
+    screen: &mut FrameBuffer, limit: u64) -> (u64, Result<(), ExecError>) {
+    let mut ran = 0;
+    for step in block.steps.iter().take(limit.min(MAX_BLOCK_STEPS as u64) as usize) {
+        if step.op == MicroOp::Interpret {
+            if let Err(err) = state.execute_decoded(memory, keypad, screen, step.raw, step.opcode) {
+                return (ran, Err(err));
+            }
+            ran += 1;
+
+            // the rest of the block may have been written over
+            if memory.has_written_code() {
+                break;
+            }
+        } else {
+            step.op.apply(state);
+            state.pc = step.next;
+            state.last_opcode = step.opcode;
+            ran += 1;
+        }
+    }
+
+    (ran, Ok(()))
+}
+
+/// Reports a failed instruction only if it was the first of a run, otherwise
+/// it is left to fail again at the start of the next run.
+fn stopped(ran: u64, err: ExecError) -> Result<u64, ExecError> {
+    if ran == 0 {
+        Err(err)
+    } else {
+        Ok(ran)
+    }
+}
+
+/// Compiles and runs basic blocks, keeping them until their code is written.
+///
+/// An engine should only be used with the memory it compiled its blocks from,
+/// `clear` it before running a different memory.
+#[derive(Debug, Default)]
+pub struct BlockEngine {
+    /// The block starting at each address, allocated to the size of memory on first use
+    blocks: Vec<Option<Box<Block>>>,
+    compiled: usize,
+}
+
+impl BlockEngine {
+    pub fn new() -> BlockEngine {
+        Default::default()
+    }
+
+    /// Runs up to `limit` instructions, returning the number run.
+    ///
+    /// The state changes exactly as it would calling `State::execute` the same
+    /// number of times.  A run can end early, after the program writes over
+    /// its own code or before it sets the sound timer, and an instruction that
+    /// fails ends the run before it.  The error is returned once the failed
+    /// instruction is the first of a run, with the state as it was before it.
+    ///
+    /// Once the program has exited every instruction counts as run.
+    pub fn run(&mut self, state: &mut State, memory: &mut Memory, keypad: &mut Keypad,
+        screen: &mut FrameBuffer, limit: u64) -> Result<u64, ExecError> {
+        let mut ran = 0;
+        while ran < limit {
+            if !state.run_flag {
+                return Ok(limit);
+            }
+
+            if self.blocks.len() != memory.size() {
+                self.clear();
+                self.blocks.resize_with(memory.size(), || None);
+            }
+            self.forget_written(memory);
+
+            let pc = usize::from(state.pc);
+            if state.opcode.is_none() && pc < self.blocks.len() && self.blocks[pc].is_none() {
+                if let Some(block) = compile(state.pc, memory) {
+                    self.blocks[pc] = Some(Box::new(block));
+                    self.compiled += 1;
+                }
+            }
+
+            let block = match self.blocks.get(pc) {
+                Some(Some(block)) if state.opcode.is_none() => block,
+                // a pending instruction, or one that cannot be fetched, is left to the interpreter
+                _ => {
+                    if let Err(err) = state.execute(memory, keypad, screen) {
+                        return stopped(ran, err);
+                    }
+                    ran += 1;
+                    continue;
+                }
+            };
+
+            if ran > 0 && sets_sound(block.steps[0].opcode) {
+                break;
+            }
+
+            let (count, result) = run_block(block, state, memory, keypad, screen, limit - ran);
+            ran += count;
+            if let Err(err) = result {
+                return stopped(ran, err);
+            }
+        }
+
+        Ok(ran)
+    }
+
+    /// Returns the number of blocks compiled and not yet forgotten
+    pub fn blocks(&self) -> usize {
+        self.compiled
+    }
+
+    /// Forgets every compiled block
+    pub fn clear(&mut self) {
+        self.blocks = Vec::new();
+        self.compiled = 0;
+    }
+
+    /// Forgets the blocks whose code has been written since they were compiled
+    fn forget_written(&mut self, memory: &mut Memory) {
+        let written = match memory.take_written_code() {
+            Some(written) => written,
+            None => return
+        };
+
+        // only blocks starting up to a block's length before the write can overlap it
+        let from = written.start.saturating_sub(MAX_BLOCK_BYTES);
+        for entry in &mut self.blocks[from..written.end] {
+            if entry.as_ref().is_some_and(|block| block.end > written.start) {
+                *entry = None;
+                self.compiled -= 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn setup(program: &[u8]) -> (State, Memory, FrameBuffer) {
+        let mut memory = Memory::new();
+        memory.set_range(0x200, program);
+        let state: State = Default::default();
+        let screen = state.create_buffer();
+        (state, memory, screen)
+    }
+
+    #[test]
+    fn it_will_stop_at_the_limit() {
+        // ADD V0, 1; ADD V0, 1; ADD V0, 1; JP 0x200
+        let (mut state, mut memory, mut screen) = setup(&[0x70, 0x01, 0x70, 0x01, 0x70, 0x01, 0x12, 0x00]);
+        let mut engine = BlockEngine::new();
+
+        let ran = engine.run(&mut state, &mut memory, &mut Keypad::new(), &mut screen, 6).unwrap();
+
+        assert_eq!(6, ran);
+        assert_eq!(5, state.registers[0]);
+        assert_eq!(0x204, state.pc);
+        assert_eq!(OpCode::ADD(AddOp::ADD(0x0, 0x1)), state.last_opcode);
+    }
+
+    #[test]
+    fn it_will_end_blocks_at_control_flow() {
+        // LD V0, 1; SE V0, 1; LD V1, 1; CALL 0x20A; JP 0x200; RET
+        let (mut state, mut memory, mut screen) = setup(&[0x60, 0x01, 0x30, 0x01, 0x61, 0x01, 0x22, 0x0A, 0x12, 0x00, 0x00, 0xEE]);
+        let mut engine = BlockEngine::new();
+
+        engine.run(&mut state, &mut memory, &mut Keypad::new(), &mut screen, 5).unwrap();
+
+        // 0x200 to the skip, 0x206 to the call, the routine, and 0x208 after it
+        assert_eq!(4, engine.blocks());
+        assert_eq!(0x200, state.pc);
+        assert_eq!(0, state.registers[1]);
+    }
+
+    #[test]
+    fn it_will_recompile_code_the_program_writes_over() {
+        // LD V0, 0x71; LD I, 0x208; LD [I], V0; ADD V0, 1
+        let (mut state, mut memory, mut screen) = setup(&[0x60, 0x71, 0xA2, 0x08, 0xF0, 0x55, 0x00, 0xE0, 0x70, 0x01, 0x00, 0xFD]);
+        let mut engine = BlockEngine::new();
+
+        engine.run(&mut state, &mut memory, &mut Keypad::new(), &mut screen, 10).unwrap();
+
+        // the ADD V0, 1 was written over with ADD V1, 1 before it ran
+        assert_eq!(0x71, state.registers[0]);
+        assert_eq!(1, state.registers[1]);
+    }
+
+    #[test]
+    fn it_will_recompile_code_the_frontend_writes_over() {
+        // ADD V0, 1; JP 0x200
+        let (mut state, mut memory, mut screen) = setup(&[0x70, 0x01, 0x12, 0x00]);
+        let mut engine = BlockEngine::new();
+
+        engine.run(&mut state, &mut memory, &mut Keypad::new(), &mut screen, 2).unwrap();
+        memory.set(0x201, 0x05);
+        engine.run(&mut state, &mut memory, &mut Keypad::new(), &mut screen, 2).unwrap();
+
+        assert_eq!(6, state.registers[0]);
+    }
+
+    #[test]
+    fn it_will_report_a_fault_at_the_start_of_the_next_run() {
+        // LD V0, 7; RET
+        let (mut state, mut memory, mut screen) = setup(&[0x60, 0x07, 0x00, 0xEE]);
+        let mut engine = BlockEngine::new();
+
+        let ran = engine.run(&mut state, &mut memory, &mut Keypad::new(), &mut screen, 10).unwrap();
+        assert_eq!(1, ran);
+
+        let err = engine.run(&mut state, &mut memory, &mut Keypad::new(), &mut screen, 10).unwrap_err();
+        assert_eq!(ExecError::StackUnderflow { pc: 0x202, opcode: 0x00EE }, err);
+        assert_eq!(0x202, state.pc);
+        assert_eq!(0x7, state.registers[0]);
+    }
+
+    #[test]
+    fn it_will_stop_before_the_sound_timer_is_set() {
+        // LD V0, 9; LD ST, V0; JP 0x204
+        let (mut state, mut memory, mut screen) = setup(&[0x60, 0x09, 0xF0, 0x18, 0x12, 0x04]);
+        let mut engine = BlockEngine::new();
+
+        assert_eq!(1, engine.run(&mut state, &mut memory, &mut Keypad::new(), &mut screen, 10).unwrap());
+        assert_eq!(0, state.sound_timer);
+
+        assert_eq!(10, engine.run(&mut state, &mut memory, &mut Keypad::new(), &mut screen, 10).unwrap());
+        assert_eq!(9, state.sound_timer);
+    }
+
+    #[test]
+    fn it_will_count_every_instruction_once_exited() {
+        let (mut state, mut memory, mut screen) = setup(&[0x00, 0xFD]);
+        let mut engine = BlockEngine::new();
+
+        let ran = engine.run(&mut state, &mut memory, &mut Keypad::new(), &mut screen, 10).unwrap();
+
+        assert_eq!(10, ran);
+        assert!(!state.run_flag);
+    }
+}
@@ -0,0 +1,285 @@
+//! Exports the screen buffer as images and text, without a window.
+//!
+//! Any non-zero pixel of the screen is drawn in the foreground colour.
+//!
+//! # Example:
+//!
+//! ```
+//! # use lib_chip::export::{self, ExportOptions};
+//! let mut screen = vec![0x0; 64 * 32];
+//! screen[0] = 0x1;
+//!
+//! let options = ExportOptions { scale: 4, ..Default::default() };
+//! let image = export::render(&screen, 64, 32, &options);
+//! let mut png = Vec::new();
+//! export::write_png(&image, &mut png)?;
+//!
+//! # assert_eq!(256, image.width);
+//! assert!(export::to_ascii(&screen, 64, 32).starts_with("#..."));
+//! # Ok::<(), std::io::Error>(())
+//! ```
+use std::io::{self, Write};
+
+pub type Colour = [u8; 3];
+
+/// The colours pixels are drawn in
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Palette {
+    pub background: Colour,
+    pub foreground: Colour,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette {
+            background: [0x00, 0x00, 0x00],
+            foreground: [0xFF, 0xFF, 0xFF],
+        }
+    }
+}
+
+/// Describes how the screen is drawn.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ExportOptions {
+    /// The width and height each screen pixel is drawn as, at least one
+    pub scale: u32,
+    pub palette: Palette,
+    /// The colour of lines drawn around every pixel, only drawn when the
+    /// scale is at least two.  The image gains a column and row for the lines
+    /// closing the right and bottom edges.
+    pub grid: Option<Colour>,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        ExportOptions {
+            scale: 1,
+            palette: Default::default(),
+            grid: None,
+        }
+    }
+}
+
+/// An image as rows of RGB bytes
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl Image {
+    pub fn pixel(&self, x: u32, y: u32) -> Colour {
+        let offset = ((y * self.width + x) * 3) as usize;
+        [self.pixels[offset], self.pixels[offset + 1], self.pixels[offset + 2]]
+    }
+}
+
+fn lit(screen: &[u8], width: u32, x: u32, y: u32) -> bool {
+    screen.get((y * width + x) as usize).map(|p| *p != 0).unwrap_or(false)
+}
+
+/// Draws a screen of `width` by `height` pixels as an image.
+pub fn render(screen: &[u8], width: u32, height: u32, options: &ExportOptions) -> Image {
+    let scale = options.scale.max(1);
+    let grid = options.grid.filter(|_| scale > 1);
+    // the extra column and row fall on multiples of the scale, so are all grid
+    let border = u32::from(grid.is_some());
+    let (image_width, image_height) = (width * scale + border, height * scale + border);
+    let mut pixels = Vec::with_capacity((image_width * image_height * 3) as usize);
+
+    for y in 0..image_height {
+        for x in 0..image_width {
+            let colour = match grid {
+                Some(colour) if x % scale == 0 || y % scale == 0 => colour,
+                _ if lit(screen, width, x / scale, y / scale) => options.palette.foreground,
+                _ => options.palette.background
+            };
+            pixels.extend_from_slice(&colour);
+        }
+    }
+
+    Image { width: image_width, height: image_height, pixels }
+}
+
+/// Writes an image as a binary PPM.
+pub fn write_ppm<W: Write>(image: &Image, writer: &mut W) -> io::Result<()> {
+    write!(writer, "P6\n{} {}\n255\n", image.width, image.height)?;
+    writer.write_all(&image.pixels)
+}
+
+/// Writes an image as a PNG.
+///
+/// The image data is stored without compression, which keeps the encoder
+/// small at the cost of larger files.
+pub fn write_png<W: Write>(image: &Image, writer: &mut W) -> io::Result<()> {
+    writer.write_all(b"\x89PNG\r\n\x1a\n")?;
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&image.width.to_be_bytes());
+    header.extend_from_slice(&image.height.to_be_bytes());
+    // 8 bit RGB, default compression, filtering and no interlacing
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+    write_chunk(writer, b"IHDR", &header)?;
+
+    // each row starts with the filter type, 0 for none
+    let row = (image.width * 3) as usize;
+    let mut raw = Vec::with_capacity((row + 1) * image.height as usize);
+    for line in image.pixels.chunks(row.max(1)) {
+        raw.push(0x0);
+        raw.extend_from_slice(line);
+    }
+    write_chunk(writer, b"IDAT", &zlib_stored(&raw))?;
+
+    write_chunk(writer, b"IEND", &[])
+}
+
+fn write_chunk<W: Write>(writer: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    writer.write_all(&(data.len() as u32).to_be_bytes())?;
+    writer.write_all(kind)?;
+    writer.write_all(data)?;
+
+    let crc = crc32(&[&kind[..], data].concat());
+    writer.write_all(&crc.to_be_bytes())
+}
+
+/// Wraps data in a zlib stream of uncompressed deflate blocks
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    const MAX_BLOCK: usize = 0xFFFF;
+
+    let mut stream = vec![0x78, 0x01];
+    let mut blocks = data.chunks(MAX_BLOCK).peekable();
+    if blocks.peek().is_none() {
+        stream.extend_from_slice(&[0x1, 0x0, 0x0, 0xFF, 0xFF]);
+    }
+
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+        stream.push(u8::from(last));
+        stream.extend_from_slice(&len.to_le_bytes());
+        stream.extend_from_slice(&(!len).to_le_bytes());
+        stream.extend_from_slice(block);
+    }
+
+    stream.extend_from_slice(&adler32(data).to_be_bytes());
+    stream
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for byte in data {
+        crc ^= u32::from(*byte);
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in data {
+        a = (a + u32::from(*byte)) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+/// Draws a screen as text, `#` for each lit pixel and `.` for each unlit one.
+pub fn to_ascii(screen: &[u8], width: u32, height: u32) -> String {
+    let mut text = String::with_capacity(((width + 1) * height) as usize);
+    for y in 0..height {
+        for x in 0..width {
+            text.push(if lit(screen, width, x, y) { '#' } else { '.' });
+        }
+        text.push('\n');
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 2x2 screen with the top left and bottom right pixels lit
+    fn diagonal() -> Vec<u8> {
+        vec![0x1, 0x0, 0x0, 0x3]
+    }
+
+    #[test]
+    fn it_will_scale_the_screen() {
+        let image = render(&diagonal(), 2, 2, &ExportOptions { scale: 3, ..Default::default() });
+
+        assert_eq!((6, 6), (image.width, image.height));
+        assert_eq!([0xFF; 3], image.pixel(2, 2));
+        assert_eq!([0x00; 3], image.pixel(3, 2));
+        assert_eq!([0xFF; 3], image.pixel(5, 5));
+    }
+
+    #[test]
+    fn it_will_draw_grid_lines() {
+        let palette = Palette { background: [0x10; 3], foreground: [0x20; 3] };
+        let options = ExportOptions { scale: 4, palette, grid: Some([0x30; 3]) };
+
+        let image = render(&diagonal(), 2, 2, &options);
+
+        assert_eq!([0x30; 3], image.pixel(4, 1));
+        assert_eq!([0x30; 3], image.pixel(1, 0));
+        assert_eq!([0x20; 3], image.pixel(1, 1));
+        assert_eq!([0x10; 3], image.pixel(5, 1));
+    }
+
+    #[test]
+    fn it_will_close_the_grid_along_the_edges() {
+        let palette = Palette { background: [0x10; 3], foreground: [0x20; 3] };
+        let options = ExportOptions { scale: 4, palette, grid: Some([0x30; 3]) };
+
+        let image = render(&diagonal(), 2, 2, &options);
+
+        assert_eq!((9, 9), (image.width, image.height));
+        for edge in 0..9 {
+            assert_eq!([0x30; 3], image.pixel(edge, 0));
+            assert_eq!([0x30; 3], image.pixel(0, edge));
+            assert_eq!([0x30; 3], image.pixel(edge, 8));
+            assert_eq!([0x30; 3], image.pixel(8, edge));
+        }
+        assert_eq!([0x20; 3], image.pixel(7, 7));
+    }
+
+    #[test]
+    fn it_will_write_a_ppm() {
+        let image = render(&diagonal(), 2, 2, &Default::default());
+        let mut ppm = Vec::new();
+
+        write_ppm(&image, &mut ppm).unwrap();
+
+        assert!(ppm.starts_with(b"P6\n2 2\n255\n"));
+        assert_eq!(11 + 12, ppm.len());
+    }
+
+    #[test]
+    fn it_will_write_a_png() {
+        let image = render(&diagonal(), 2, 2, &Default::default());
+        let mut png = Vec::new();
+
+        write_png(&image, &mut png).unwrap();
+
+        assert_eq!(b"\x89PNG\r\n\x1a\n", &png[..8]);
+        assert_eq!(b"IHDR", &png[12..16]);
+        // the CRC of an empty IEND chunk
+        assert_eq!([0xAE, 0x42, 0x60, 0x82], png[png.len() - 4..]);
+    }
+
+    #[test]
+    fn it_will_check_data_like_zlib() {
+        assert_eq!(0xCBF4_3926, crc32(b"123456789"));
+        assert_eq!(0x091E_01DE, adler32(b"123456789"));
+    }
+
+    #[test]
+    fn it_will_draw_ascii_art() {
+        assert_eq!("#.\n.#\n", to_ascii(&diagonal(), 2, 2));
+    }
+}
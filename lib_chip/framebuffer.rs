@@ -0,0 +1,408 @@
+//! The screen a program draws on, tracking what changed since it was last shown.
+//!
+//! Each XO-CHIP plane is stored as one bitmask per row, so a sprite row is
+//! drawn with a shift and an XOR, and a collision is found with an AND.  The
+//! pixels can be expanded to bytes, holding one bit per plane, or to RGBA
+//! whenever the frontend needs them.
+//!
+//! Every change marks the columns it touched in its row as dirty, and the
+//! frontend calls `present` once it has shown them, so it only needs to
+//! redraw what changed.
+//!
+//! # Example:
+//!
+//! ```
+//! # use lib_chip::framebuffer::{FrameBuffer, Rect};
+//! let mut screen = FrameBuffer::new(64, 32);
+//! screen.present();
+//!
+//! screen.draw_row(0x1, 3, 2, 0b1010_0000 << 8, false);
+//! screen.toggle(5, 4, 0x1);
+//! assert_eq!(vec![2, 4], screen.dirty_rows().collect::<Vec<_>>());
+//! assert_eq!(Some(Rect { x: 3, y: 2, width: 3, height: 3 }), screen.dirty_rect());
+//! assert_eq!(0x1, screen.to_bytes()[2 * 64 + 5]);
+//!
+//! screen.present();
+//! assert!(!screen.is_dirty());
+//! ```
+
+/// The widest screen a row can hold
+pub const MAX_WIDTH: u32 = 128;
+/// The XO-CHIP planes, as the bit each sets in a pixel
+const PLANES: [u8; 2] = [0x1, 0x2];
+
+/// An area of the screen in pixels.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrameBuffer {
+    width: u32,
+    height: u32,
+    /// The rows of each plane, column 0 is the highest of the `width` low bits
+    planes: [Vec<u128>; 2],
+    /// The first and last columns changed in each row since the last present
+    dirty: Vec<Option<(u32, u32)>>,
+}
+
+/// Returns the index of each plane set in `planes`
+fn plane_indexes(planes: u8) -> impl Iterator<Item = usize> {
+    PLANES.iter().enumerate()
+        .filter(move |(_, plane)| planes & **plane != 0)
+        .map(|(index, _)| index)
+}
+
+impl FrameBuffer {
+    /// Creates a blank screen, which is dirty so the frontend draws it once.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the screen is wider than `MAX_WIDTH`.
+    pub fn new(width: u32, height: u32) -> FrameBuffer {
+        assert!(width <= MAX_WIDTH, "a screen can be at most {} pixels wide", MAX_WIDTH);
+        let rows = vec![0; height as usize];
+        let mut screen = FrameBuffer { width, height, planes: [rows.clone(), rows], dirty: Vec::new() };
+        screen.mark_all();
+        screen
+    }
+
+    /// Creates a dirty screen from its pixels, row by row, one bit per plane.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is not exactly one pixel for each position, or the
+    /// screen is wider than `MAX_WIDTH`.
+    pub fn from_pixels(width: u32, height: u32, pixels: &[u8]) -> FrameBuffer {
+        assert_eq!((width * height) as usize, pixels.len(), "a {}x{} screen needs a pixel for each position", width, height);
+        let mut screen = FrameBuffer::new(width, height);
+        for (index, planes) in screen.planes.iter_mut().enumerate() {
+            for (row, pixels) in planes.iter_mut().zip(pixels.chunks(width.max(1) as usize)) {
+                *row = pixels.iter().fold(0, |row, pixel| (row << 1) | u128::from((pixel >> index) & 1));
+            }
+        }
+        screen
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Returns a row of a plane as a bitmask, with column 0 in bit `width - 1`
+    pub fn row(&self, plane: u8, y: u32) -> u128 {
+        plane_indexes(plane).next().map(|index| self.planes[index][y as usize]).unwrap_or(0)
+    }
+
+    /// Returns the planes lit at a position
+    pub fn pixel(&self, x: u32, y: u32) -> u8 {
+        let bit = self.bit(x);
+        plane_indexes(0xFF)
+            .filter(|index| self.planes[*index][y as usize] & bit != 0)
+            .fold(0, |pixel, index| pixel | PLANES[index])
+    }
+
+    /// Sets the planes lit at a position.
+    pub fn set(&mut self, x: u32, y: u32, value: u8) {
+        let bit = self.bit(x);
+        for index in plane_indexes(0xFF) {
+            let row = self.planes[index][y as usize];
+            let lit = if value & PLANES[index] != 0 { row | bit } else { row & !bit };
+            self.replace_row(index, y, lit);
+        }
+    }
+
+    /// Flips the given planes of a pixel, returning true if any of them were lit.
+    pub fn toggle(&mut self, x: u32, y: u32, planes: u8) -> bool {
+        let bit = self.bit(x);
+        plane_indexes(planes).fold(false, |erased, index| {
+            let row = self.planes[index][y as usize];
+            self.replace_row(index, y, row ^ bit);
+            erased || row & bit != 0
+        })
+    }
+
+    /// XORs a sprite row onto the given planes, returning true if any lit pixel was erased.
+    ///
+    /// The sprite is 16 pixels wide with its leftmost pixel in the highest
+    /// bit, an 8 pixel sprite fills the high byte.  It starts at column `x`,
+    /// pixels past the right edge are clipped when `clip` is set and wrap
+    /// onto the left edge otherwise.
+    pub fn draw_row(&mut self, planes: u8, x: u32, y: u32, sprite: u16, clip: bool) -> bool {
+        let bits = self.place(x, sprite, clip);
+        plane_indexes(planes).fold(false, |erased, index| {
+            let row = self.planes[index][y as usize];
+            self.replace_row(index, y, row ^ bits);
+            erased || row & bits != 0
+        })
+    }
+
+    /// Turns off the given planes of every pixel, only rows that were lit become dirty.
+    pub fn clear(&mut self, planes: u8) {
+        for index in plane_indexes(planes) {
+            for y in 0..self.height {
+                self.replace_row(index, y, 0);
+            }
+        }
+    }
+
+    /// Moves the given planes right by `dx` and down by `dy`, pixels moved in
+    /// from outside the screen are cleared.
+    pub fn scroll(&mut self, planes: u8, dx: i32, dy: i32) {
+        let mask = self.mask();
+        for index in plane_indexes(planes) {
+            let source = self.planes[index].clone();
+            for y in 0..self.height {
+                let from = i64::from(y) - i64::from(dy);
+                let row = if from >= 0 && from < i64::from(self.height) { source[from as usize] } else { 0 };
+                let moved = match dx {
+                    dx if dx.unsigned_abs() >= self.width => 0,
+                    dx if dx >= 0 => row >> dx,
+                    dx => (row << dx.unsigned_abs()) & mask
+                };
+                self.replace_row(index, y, moved);
+            }
+        }
+    }
+
+    /// Changes the size of the screen, leaving it blank and dirty.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the screen is wider than `MAX_WIDTH`.
+    pub fn resize(&mut self, width: u32, height: u32) {
+        *self = FrameBuffer::new(width, height);
+    }
+
+    /// Expands the screen to one byte per pixel, row by row, holding one bit per plane
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut pixels = Vec::with_capacity((self.width * self.height) as usize);
+        for y in 0..self.height {
+            pixels.extend((0..self.width).map(|x| self.pixel(x, y)));
+        }
+        pixels
+    }
+
+    /// Expands the screen to four bytes per pixel, row by row.
+    ///
+    /// Each pixel takes the colour at the index of the planes it has lit, so
+    /// a chip8 screen only uses the first two.
+    pub fn to_rgba(&self, colours: &[[u8; 4]; 4]) -> Vec<u8> {
+        self.to_bytes().iter().flat_map(|pixel| colours[usize::from(pixel & 0x3)].iter().copied()).collect()
+    }
+
+    /// Returns true if anything changed since the last present
+    pub fn is_dirty(&self) -> bool {
+        self.dirty.iter().any(Option::is_some)
+    }
+
+    /// Returns the rows changed since the last present, from top to bottom
+    pub fn dirty_rows(&self) -> impl Iterator<Item = u32> + '_ {
+        self.dirty.iter().enumerate()
+            .filter(|(_, span)| span.is_some())
+            .map(|(y, _)| y as u32)
+    }
+
+    /// Returns the smallest area holding every change since the last present
+    pub fn dirty_rect(&self) -> Option<Rect> {
+        let mut rows = self.dirty.iter().enumerate()
+            .filter_map(|(y, span)| span.map(|(left, right)| (y as u32, left, right)));
+        let (top, left, right) = rows.next()?;
+
+        let (bottom, left, right) = rows.fold((top, left, right), |(_, left, right), (y, l, r)| {
+            (y, left.min(l), right.max(r))
+        });
+        Some(Rect { x: left, y: top, width: right - left + 1, height: bottom - top + 1 })
+    }
+
+    /// Forgets the changes, once the frontend has shown them.
+    pub fn present(&mut self) {
+        self.dirty.iter_mut().for_each(|span| *span = None);
+    }
+
+    /// The low `width` bits, which hold a row
+    fn mask(&self) -> u128 {
+        if self.width == MAX_WIDTH { u128::MAX } else { (1 << self.width) - 1 }
+    }
+
+    fn bit(&self, x: u32) -> u128 {
+        debug_assert!(x < self.width, "column {} is off the screen", x);
+        1 << (self.width - 1 - x)
+    }
+
+    /// Moves a sprite row to start at column `x` of a row
+    fn place(&self, x: u32, sprite: u16, clip: bool) -> u128 {
+        let width = self.width;
+        // the sprite at column 0, narrow screens lose the columns past their edge
+        let sprite = if width >= 16 { u128::from(sprite) << (width - 16) } else { u128::from(sprite >> (16 - width)) };
+        let x = x % width.max(1);
+
+        let shifted = sprite >> x;
+        if clip || x == 0 {
+            shifted
+        } else {
+            shifted | ((sprite << (width - x)) & self.mask())
+        }
+    }
+
+    /// Stores a row of a plane, marking the columns that changed
+    fn replace_row(&mut self, index: usize, y: u32, row: u128) {
+        let changed = self.planes[index][y as usize] ^ row;
+        if changed == 0 {
+            return;
+        }
+
+        self.planes[index][y as usize] = row;
+        let left = self.width + changed.leading_zeros() - MAX_WIDTH;
+        let right = self.width - 1 - changed.trailing_zeros();
+        let span = &mut self.dirty[y as usize];
+        *span = match *span {
+            Some((l, r)) => Some((l.min(left), r.max(right))),
+            None => Some((left, right))
+        };
+    }
+
+    fn mark_all(&mut self) {
+        let span = if self.width > 0 { Some((0, self.width - 1)) } else { None };
+        self.dirty = vec![span; self.height as usize];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn presented(width: u32, height: u32) -> FrameBuffer {
+        let mut screen = FrameBuffer::new(width, height);
+        screen.present();
+        screen
+    }
+
+    #[test]
+    fn it_will_start_blank_and_dirty() {
+        let screen = FrameBuffer::new(4, 2);
+
+        assert_eq!(vec![0x0; 8], screen.to_bytes());
+        assert_eq!(Some(Rect { x: 0, y: 0, width: 4, height: 2 }), screen.dirty_rect());
+    }
+
+    #[test]
+    fn it_will_report_erased_pixels() {
+        let mut screen = presented(4, 2);
+
+        assert!(!screen.toggle(1, 1, 0x1));
+        assert_eq!(0x1, screen.pixel(1, 1));
+        assert!(screen.toggle(1, 1, 0x3));
+        assert_eq!(0x2, screen.pixel(1, 1));
+    }
+
+    #[test]
+    fn it_will_store_column_zero_in_the_highest_bit() {
+        let mut screen = presented(64, 2);
+        screen.set(0, 1, 0x1);
+        screen.set(63, 1, 0x3);
+
+        assert_eq!((1 << 63) | 1, screen.row(0x1, 1));
+        assert_eq!(1, screen.row(0x2, 1));
+    }
+
+    #[test]
+    fn it_will_draw_rows_with_collisions() {
+        let mut screen = presented(64, 2);
+
+        assert!(!screen.draw_row(0x1, 8, 0, 0xF0 << 8, false));
+        assert!(screen.draw_row(0x1, 10, 0, 0xF0 << 8, false));
+
+        let lit: Vec<u32> = (0..64).filter(|x| screen.pixel(*x, 0) != 0).collect();
+        assert_eq!(vec![8, 9, 12, 13], lit);
+    }
+
+    #[test]
+    fn it_will_wrap_or_clip_rows_at_the_right_edge() {
+        for width in [64, 128].iter() {
+            let mut screen = presented(*width, 2);
+            screen.draw_row(0x1, width - 2, 0, 0xFFFF, false);
+            screen.draw_row(0x1, width - 2, 1, 0xFFFF, true);
+
+            assert_eq!(0b11 | (0x3FFF << (width - 14)), screen.row(0x1, 0));
+            assert_eq!(0b11, screen.row(0x1, 1));
+        }
+    }
+
+    #[test]
+    fn it_will_track_the_columns_changed_in_each_row() {
+        let mut screen = presented(8, 4);
+        screen.toggle(6, 1, 0x1);
+        screen.toggle(2, 1, 0x1);
+        screen.toggle(4, 3, 0x1);
+
+        assert_eq!(vec![1, 3], screen.dirty_rows().collect::<Vec<_>>());
+        assert_eq!(Some(Rect { x: 2, y: 1, width: 5, height: 3 }), screen.dirty_rect());
+    }
+
+    #[test]
+    fn it_will_not_mark_pixels_set_to_their_value() {
+        let mut screen = presented(4, 2);
+        screen.set(0, 0, 0x0);
+
+        assert!(!screen.is_dirty());
+    }
+
+    #[test]
+    fn it_will_clear_only_the_given_planes_of_lit_rows() {
+        let mut screen = presented(4, 3);
+        screen.set(1, 0, 0x3);
+        screen.set(2, 2, 0x2);
+        screen.present();
+
+        screen.clear(0x1);
+
+        assert_eq!(0x2, screen.pixel(1, 0));
+        assert_eq!(0x2, screen.pixel(2, 2));
+        assert_eq!(vec![0], screen.dirty_rows().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn it_will_scroll_the_given_planes() {
+        let mut screen = presented(8, 4);
+        screen.set(4, 1, 0x3);
+
+        screen.scroll(0x1, -2, 1);
+
+        assert_eq!(0x1, screen.pixel(2, 2));
+        assert_eq!(0x2, screen.pixel(4, 1));
+        screen.scroll(0x1, 8, 0);
+        assert_eq!(0x0, screen.pixel(2, 2));
+    }
+
+    #[test]
+    fn it_will_expand_to_bytes_and_rgba() {
+        let pixels = [0x0, 0x1, 0x2, 0x3];
+        let screen = FrameBuffer::from_pixels(2, 2, &pixels);
+        let colours = [[0, 0, 0, 255], [1, 1, 1, 255], [2, 2, 2, 255], [3, 3, 3, 255]];
+
+        assert_eq!(pixels.to_vec(), screen.to_bytes());
+        assert_eq!(vec![0, 0, 0, 255, 1, 1, 1, 255, 2, 2, 2, 255, 3, 3, 3, 255], screen.to_rgba(&colours));
+    }
+
+    #[test]
+    fn it_will_blank_the_screen_when_resized() {
+        let mut screen = presented(4, 2);
+        screen.set(0, 0, 0x1);
+        screen.present();
+
+        screen.resize(8, 4);
+
+        assert_eq!(8, screen.width());
+        assert_eq!(4, screen.height());
+        assert!(screen.to_bytes().iter().all(|pixel| *pixel == 0));
+        assert_eq!(4, screen.dirty_rows().count());
+    }
+}
@@ -0,0 +1,157 @@
+//! The 16 key hexadecimal keypad.
+//!
+//! The keypad holds the set of keys held down as one bit per key, along with
+//! the keys pressed and released since the edges were last cleared.
+//!
+//! # Example:
+//!
+//! ```
+//! # use lib_chip::keypad::Keypad;
+//! let mut keypad = Keypad::new();
+//! keypad.press(0xA);
+//! keypad.release(0xA);
+//!
+//! # assert!(!keypad.is_pressed(0xA));
+//! # assert!(keypad.was_released(0xA));
+//! keypad.clear_edges();
+//! # assert!(!keypad.was_released(0xA));
+//! ```
+
+/// The number of keys on the keypad
+pub const KEY_COUNT: u8 = 16;
+
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct Keypad {
+    pressed: u16,
+    just_pressed: u16,
+    just_released: u16,
+}
+
+/// Returns the bit for a key, only the low nibble of the key is used
+fn bit(key: u8) -> u16 {
+    1 << (key & 0xF)
+}
+
+impl Keypad {
+    pub fn new() -> Keypad {
+        Default::default()
+    }
+
+    /// Creates a keypad with the given keys held down and no edges.
+    pub fn with_keys(keys: &[u8]) -> Keypad {
+        let pressed = keys.iter().fold(0, |set, key| set | bit(*key));
+        Keypad { pressed, ..Default::default() }
+    }
+
+    pub fn press(&mut self, key: u8) {
+        self.set_pressed(self.pressed | bit(key));
+    }
+
+    pub fn release(&mut self, key: u8) {
+        self.set_pressed(self.pressed & !bit(key));
+    }
+
+    /// Replaces the keys held down, one bit per key, recording the edges.
+    pub fn set_pressed(&mut self, pressed: u16) {
+        self.just_pressed |= pressed & !self.pressed;
+        self.just_released |= self.pressed & !pressed;
+        self.pressed = pressed;
+    }
+
+    /// Returns the keys held down, one bit per key
+    pub fn pressed(&self) -> u16 {
+        self.pressed
+    }
+
+    pub fn is_pressed(&self, key: u8) -> bool {
+        self.pressed & bit(key) != 0
+    }
+
+    /// Returns the lowest key held down
+    pub fn first_pressed(&self) -> Option<u8> {
+        if self.pressed == 0 {
+            None
+        } else {
+            Some(self.pressed.trailing_zeros() as u8)
+        }
+    }
+
+    /// Returns the keys that went down since the edges were cleared, one bit per key
+    pub fn just_pressed(&self) -> u16 {
+        self.just_pressed
+    }
+
+    /// Returns the keys that came up since the edges were cleared, one bit per key
+    pub fn just_released(&self) -> u16 {
+        self.just_released
+    }
+
+    /// Returns true if the key went down since the edges were cleared
+    pub fn was_pressed(&self, key: u8) -> bool {
+        self.just_pressed & bit(key) != 0
+    }
+
+    /// Returns true if the key came up since the edges were cleared
+    pub fn was_released(&self, key: u8) -> bool {
+        self.just_released & bit(key) != 0
+    }
+
+    /// Forgets a key was pressed or released, so an instruction that acts on
+    /// the edges only acts on them once.
+    pub fn forget_edges(&mut self, key: u8) {
+        self.just_pressed &= !bit(key);
+        self.just_released &= !bit(key);
+    }
+
+    /// Forgets the keys pressed and released, usually at the end of each frame.
+    pub fn clear_edges(&mut self) {
+        self.just_pressed = 0;
+        self.just_released = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_will_track_held_keys() {
+        let mut keypad = Keypad::with_keys(&[0x1, 0xF]);
+        keypad.press(0x4);
+        keypad.release(0x1);
+
+        assert_eq!(0b1000_0000_0001_0000, keypad.pressed());
+        assert_eq!(Some(0x4), keypad.first_pressed());
+        assert!(!keypad.is_pressed(0x1));
+    }
+
+    #[test]
+    fn it_will_record_edges_until_cleared() {
+        let mut keypad = Keypad::with_keys(&[0x2]);
+        keypad.press(0x3);
+        keypad.release(0x2);
+        keypad.press(0x3);
+
+        assert!(keypad.was_pressed(0x3));
+        assert!(!keypad.was_pressed(0x2));
+        assert!(keypad.was_released(0x2));
+
+        keypad.clear_edges();
+        assert!(!keypad.was_pressed(0x3));
+        assert!(keypad.is_pressed(0x3));
+    }
+
+    #[test]
+    fn it_will_forget_the_edges_of_one_key() {
+        let mut keypad = Keypad::new();
+        keypad.press(0x5);
+        keypad.release(0x5);
+        keypad.press(0x6);
+
+        keypad.forget_edges(0x5);
+
+        assert!(!keypad.was_pressed(0x5));
+        assert!(!keypad.was_released(0x5));
+        assert!(keypad.was_pressed(0x6));
+    }
+}
@@ -3,23 +3,56 @@
 //! A rust library for emulating chip8 programs.
 //! 
 //! This library will not provide any rendering functionality.  It represents all display code
-//! as a buffer of bytes and passes this off to the consumer to handle rendering.
+//! as a `FrameBuffer` of bytes, which reports the rows that changed so the consumer can
+//! redraw only those.
 //! 
 //! # Example:
 //! 
 //! ```
-//! # use lib_chip::state::State;
+//! # use lib_chip::machine::Machine;
 //! # use lib_chip::rom::Rom;
+//! # let rom = Rom::from_memory(vec![0x00, 0xE0, 0x12, 0x02]);
+//! let mut machine = Machine::new(Default::default());
+//! machine.load_rom(&rom)?;
+//! machine.press(0x5);
+//! machine.run_frame()?;
+//! let screen = machine.screen();
+//! # assert_eq!(64 * 32, screen.to_bytes().len());
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+//! 
+//! The parts of the machine can also be driven directly:
+//! 
+//! ```
+//! # use lib_chip::state::State;
 //! # use lib_chip::memory::Memory;
+//! # use lib_chip::keypad::Keypad;
 //! let mut state:State = Default::default();
 //! # let mut memory = Memory::new();
 //! # memory.set_range(0x200, &vec![0x00, 0xE0][..]);
-//! # let mut screen: Vec<u8> = vec![0x0; (32*64) as usize];
-//! # let keys = vec![0x0];
-//! state = state.step(&mut memory, &keys[..], &mut screen);
+//! # let mut screen = state.create_buffer();
+//! # let mut keypad = Keypad::new();
+//! state.execute(&mut memory, &mut keypad, &mut screen)?;
+//! # Ok::<(), lib_chip::state::ExecError>(())
 //! ```
 
 pub mod state;
+pub mod keypad;
 pub mod memory;
+pub mod framebuffer;
 pub mod rom;
-pub mod opcode;
\ No newline at end of file
+pub mod opcode;
+pub mod asm;
+pub mod disasm;
+pub mod debugger;
+pub mod savestate;
+pub mod rewind;
+pub mod random;
+pub mod clock;
+pub mod audio;
+pub mod export;
+pub mod machine;
+pub mod movie;
+pub mod trace;
+pub mod profile;
+pub mod engine;
@@ -0,0 +1,493 @@
+//! A complete machine that owns the state, memory, screen and keys of a program.
+//!
+//! # Example:
+//!
+//! ```
+//! # use lib_chip::machine::{Config, Machine};
+//! # use lib_chip::rom::Rom;
+//! let rom = Rom::from_memory(vec![0x60, 0x05, 0xF0, 0x29, 0xD0, 0x05, 0x12, 0x06]);
+//! let mut machine = Machine::new(Default::default());
+//! machine.load_rom(&rom)?;
+//!
+//! machine.run_frame()?;
+//! # assert_eq!(0x5, machine.state().registers[0]);
+//! # assert_eq!(64 * 32, machine.screen().to_bytes().len());
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+use std::io::Write;
+use std::mem;
+use crate::audio::{AudioConfig, Beeper};
+use crate::clock::{self, Clock, Event, DEFAULT_INSTRUCTIONS_PER_SECOND};
+use crate::engine::{BlockEngine, Engine};
+use crate::framebuffer::FrameBuffer;
+use crate::keypad::Keypad;
+use crate::memory::{Memory, MEMORY_SIZE};
+use crate::random::{SeededRandom, DEFAULT_SEED};
+use crate::rom::{Rom, RomTooLarge};
+use crate::state::{ExecError, Quirks, State};
+use crate::profile::Profiler;
+use crate::trace::Tracer;
+
+/// Describes the machine to emulate.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Config {
+    pub quirks: Quirks,
+    /// The bytes of memory, `MEMORY_SIZE` for chip8 and SUPER-CHIP, `XO_CHIP_MEMORY_SIZE` for XO-CHIP.
+    /// Sizes outside `Memory::with_size`'s range are clamped to it.
+    pub memory_size: usize,
+    pub instructions_per_second: u32,
+    /// The seed for the numbers drawn by CXKK
+    pub seed: u64,
+    /// The sound to make while the sound timer runs, none for silence
+    pub audio: Option<AudioConfig>,
+    /// How instructions are run, the interpreter is used while tracing or profiling
+    pub engine: Engine,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            quirks: Default::default(),
+            memory_size: MEMORY_SIZE,
+            instructions_per_second: DEFAULT_INSTRUCTIONS_PER_SECOND,
+            seed: DEFAULT_SEED,
+            audio: None,
+            engine: Engine::Interpreter,
+        }
+    }
+}
+
+pub struct Machine {
+    config: Config,
+    state: State,
+    memory: Memory,
+    screen: FrameBuffer,
+    keypad: Keypad,
+    clock: Clock,
+    beeper: Option<Beeper>,
+    tracer: Option<Tracer<Box<dyn Write>>>,
+    profiler: Option<Profiler>,
+    blocks: BlockEngine,
+    rom: Option<Rom>,
+}
+
+/// Executes an instruction, through the tracer and profiler if there are any
+fn execute_state(state: &mut State, memory: &mut Memory, keypad: &mut Keypad, screen: &mut FrameBuffer,
+    tracer: &mut Option<Tracer<Box<dyn Write>>>, profiler: &mut Option<Profiler>) -> Result<(), ExecError> {
+    let (pc, running) = (state.pc, state.run_flag);
+    match tracer {
+        Some(tracer) => tracer.execute(state, memory, keypad, screen)?,
+        None => state.execute(memory, keypad, screen)?
+    }
+
+    if let Some(profiler) = profiler.as_mut().filter(|_| running) {
+        profiler.record(pc, state);
+    }
+    Ok(())
+}
+
+/// Creates the state a program starts with
+fn initial_state(config: &Config) -> State {
+    State {
+        quirks: config.quirks,
+        random: Box::new(SeededRandom::new(config.seed)),
+        ..Default::default()
+    }
+}
+
+impl Machine {
+    /// Creates a machine with empty program memory.
+    pub fn new(config: Config) -> Machine {
+        let state = initial_state(&config);
+        let screen = state.create_buffer();
+        Machine {
+            config,
+            state,
+            memory: Memory::with_size(config.memory_size),
+            screen,
+            keypad: Keypad::new(),
+            clock: Clock::new(config.instructions_per_second),
+            beeper: config.audio.map(Beeper::new),
+            tracer: None,
+            profiler: None,
+            blocks: BlockEngine::new(),
+            rom: None,
+        }
+    }
+
+    /// Resets the machine and loads a rom at `PROGRAM_START`.
+    ///
+    /// Fails without changing the machine if the rom does not fit in memory.
+    pub fn load_rom(&mut self, rom: &Rom) -> Result<(), RomTooLarge> {
+        let mut memory = Memory::with_size(self.config.memory_size);
+        rom.load_into(&mut memory)?;
+
+        self.rom = Some(rom.clone());
+        self.reset();
+        Ok(())
+    }
+
+    /// Returns the machine to how it was when the rom was loaded, with no keys held.
+    pub fn reset(&mut self) {
+        self.state = initial_state(&self.config);
+        self.keypad = Keypad::new();
+        self.screen = self.state.create_buffer();
+        self.memory = Memory::with_size(self.config.memory_size);
+        self.clock = Clock::new(self.config.instructions_per_second);
+        self.beeper = self.config.audio.map(Beeper::new);
+        self.blocks.clear();
+
+        if let Some(rom) = &self.rom {
+            // the rom was checked to fit when it was loaded
+            let _ = rom.load_into(&mut self.memory);
+        }
+    }
+
+    /// Holds a key down, from 0x0 to 0xF
+    pub fn press(&mut self, key: u8) {
+        self.keypad.press(key);
+    }
+
+    pub fn release(&mut self, key: u8) {
+        self.keypad.release(key);
+    }
+
+    pub fn keypad(&self) -> &Keypad {
+        &self.keypad
+    }
+
+    pub fn keypad_mut(&mut self) -> &mut Keypad {
+        &mut self.keypad
+    }
+
+    /// Executes a single instruction, without running the timers.
+    ///
+    /// If the instruction fails the state is left as it was before it.
+    pub fn step(&mut self) -> Result<(), ExecError> {
+        if self.runs_blocks() {
+            return self.blocks.run(&mut self.state, &mut self.memory, &mut self.keypad, &mut self.screen, 1)
+                .map(|_| ());
+        }
+
+        execute_state(&mut self.state, &mut self.memory, &mut self.keypad,
+            &mut self.screen, &mut self.tracer, &mut self.profiler)
+    }
+
+    /// Runs a frame of instructions, ending with the timers counting down.
+    ///
+    /// The keys pressed and released during the frame are forgotten once it
+    /// has run.  If an instruction fails the state is left as it was before it.
+    pub fn run_frame(&mut self) -> Result<(), ExecError> {
+        if self.runs_blocks() {
+            return self.run_frame_blocks();
+        }
+
+        let Machine { state, memory, screen, keypad, clock, beeper, tracer, profiler, .. } = self;
+
+        clock.run_frame_with(|event, at| {
+            if let Some(beeper) = beeper {
+                beeper.advance(at, state.sound_timer > 0);
+            }
+
+            match event {
+                Event::Tick => {
+                    clock::count_down(state);
+                    Ok(())
+                },
+                Event::Instruction => execute_state(state, memory, keypad, screen, tracer, profiler)
+            }
+        })?;
+
+        self.keypad.clear_edges();
+        Ok(())
+    }
+
+    /// Returns true if instructions are run by the block engine
+    fn runs_blocks(&self) -> bool {
+        self.config.engine == Engine::Blocks && self.tracer.is_none() && self.profiler.is_none()
+    }
+
+    /// Runs a frame through the block engine, a run of instructions at a time
+    fn run_frame_blocks(&mut self) -> Result<(), ExecError> {
+        let Machine { state, memory, screen, keypad, clock, beeper, blocks, .. } = self;
+
+        clock.run_frame_batched(|event, at, due| {
+            if let Some(beeper) = beeper {
+                beeper.advance(at, state.sound_timer > 0);
+            }
+
+            match event {
+                Event::Tick => {
+                    clock::count_down(state);
+                    Ok(0)
+                },
+                Event::Instruction => blocks.run(state, memory, keypad, screen, due)
+            }
+        })?;
+
+        self.keypad.clear_edges();
+        Ok(())
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Changes how instructions are run from the next one on
+    pub fn set_engine(&mut self, engine: Engine) {
+        self.config.engine = engine;
+        self.blocks.clear();
+    }
+
+    /// Returns the rom last loaded
+    pub fn rom(&self) -> Option<&Rom> {
+        self.rom.as_ref()
+    }
+
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    pub fn memory(&self) -> &Memory {
+        &self.memory
+    }
+
+    pub fn screen(&self) -> &FrameBuffer {
+        &self.screen
+    }
+
+    /// Allows the frontend to present the screen once it has drawn the changes
+    pub fn screen_mut(&mut self) -> &mut FrameBuffer {
+        &mut self.screen
+    }
+
+    pub fn width(&self) -> u32 {
+        self.state.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.state.height
+    }
+
+    pub fn clock(&self) -> &Clock {
+        &self.clock
+    }
+
+    /// Traces every instruction the machine executes, or stops tracing given None.
+    ///
+    /// Returns the tracer that was replaced.
+    pub fn set_tracer(&mut self, tracer: Option<Tracer<Box<dyn Write>>>) -> Option<Tracer<Box<dyn Write>>> {
+        mem::replace(&mut self.tracer, tracer)
+    }
+
+    /// Profiles every instruction the machine executes, or stops profiling given None.
+    ///
+    /// Returns the profiler that was replaced.
+    pub fn set_profiler(&mut self, profiler: Option<Profiler>) -> Option<Profiler> {
+        mem::replace(&mut self.profiler, profiler)
+    }
+
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+
+    /// Returns the beeper, if the config asked for audio, to take its samples from
+    pub fn beeper_mut(&mut self) -> Option<&mut Beeper> {
+        self.beeper.as_mut()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::XO_CHIP_MEMORY_SIZE;
+    use crate::trace::Format;
+
+    fn machine(program: Vec<u8>) -> Machine {
+        let mut machine = Machine::new(Default::default());
+        machine.load_rom(&Rom::from_memory(program)).unwrap();
+        machine
+    }
+
+    #[test]
+    fn it_will_load_a_rom_at_the_program_start() {
+        let machine = machine(vec![0x12, 0x34]);
+
+        assert_eq!(0x12, machine.memory().read(0x200));
+        assert_eq!(0x200, machine.state().pc);
+    }
+
+    #[test]
+    fn it_will_reject_roms_that_do_not_fit() {
+        let mut machine = machine(vec![0x12, 0x34]);
+
+        let err = machine.load_rom(&Rom::from_memory(vec![0x0; 0x1000])).unwrap_err();
+
+        assert_eq!(RomTooLarge { size: 0x1000, available: 0xE00 }, err);
+        assert_eq!(0x12, machine.memory().read(0x200));
+    }
+
+    #[test]
+    fn it_will_load_large_roms_into_xo_chip_memory() {
+        let config = Config { memory_size: XO_CHIP_MEMORY_SIZE, ..Default::default() };
+        let mut machine = Machine::new(config);
+
+        assert!(machine.load_rom(&Rom::from_memory(vec![0x0; 0x1000])).is_ok());
+    }
+
+    #[test]
+    fn it_will_run_a_frame() {
+        let mut machine = machine(vec![0x70, 0x01, 0x12, 0x00]);
+
+        machine.run_frame().unwrap();
+
+        assert_eq!(5, machine.state().registers[0]);
+        assert_eq!(1, machine.clock().frames());
+    }
+
+    #[test]
+    fn it_will_make_room_for_the_fonts_in_a_small_memory() {
+        let mut machine = Machine::new(Config { memory_size: 0x10, ..Default::default() });
+
+        machine.reset();
+
+        assert_eq!(0x200, machine.memory().size());
+        assert_eq!(0xF0, machine.memory().read(0x0));
+    }
+
+    #[test]
+    fn it_will_reset_to_the_loaded_rom() {
+        let mut machine = machine(vec![0x60, 0x07, 0xA3, 0x00, 0xF0, 0x55]);
+        for _ in 0..3 {
+            machine.step().unwrap();
+        }
+        assert_eq!(0x7, machine.memory().read(0x300));
+
+        machine.press(0x3);
+        machine.release(0x3);
+        machine.press(0x5);
+
+        machine.reset();
+
+        assert_eq!(Keypad::new(), *machine.keypad());
+        assert_eq!(0x0, machine.memory().read(0x300));
+        assert_eq!(0x60, machine.memory().read(0x200));
+        assert_eq!(0x0, machine.state().registers[0]);
+        assert_eq!(0x200, machine.state().pc);
+    }
+
+    #[test]
+    fn it_will_keep_the_state_when_an_instruction_fails() {
+        let mut machine = machine(vec![0x60, 0x07, 0x00, 0xEE]);
+
+        let err = machine.run_frame().unwrap_err();
+
+        assert_eq!(ExecError::StackUnderflow { pc: 0x202, opcode: 0x00EE }, err);
+        assert_eq!(0x202, machine.state().pc);
+        assert_eq!(0x7, machine.state().registers[0]);
+    }
+
+    #[test]
+    fn it_will_wait_for_a_key_to_be_released() {
+        let mut machine = machine(vec![0xF0, 0x0A, 0x12, 0x02]);
+        machine.press(0x7);
+
+        machine.run_frame().unwrap();
+        assert_eq!(0x200, machine.state().pc);
+        assert!(!machine.keypad().was_pressed(0x7));
+
+        machine.release(0x7);
+        machine.run_frame().unwrap();
+
+        assert_eq!(0x202, machine.state().pc);
+        assert_eq!(0x7, machine.state().registers[0]);
+    }
+
+    #[test]
+    fn it_will_take_a_key_pressed_and_released_within_a_frame() {
+        let mut machine = machine(vec![0xF0, 0x0A, 0xF1, 0x0A, 0x12, 0x04]);
+        machine.press(0x7);
+        machine.release(0x7);
+
+        machine.run_frame().unwrap();
+
+        assert_eq!(0x202, machine.state().pc);
+        assert_eq!(0x7, machine.state().registers[0]);
+    }
+
+    #[test]
+    fn it_will_beep_while_the_sound_timer_runs() {
+        let audio = AudioConfig { sample_rate: 6000, ..Default::default() };
+        let mut machine = Machine::new(Config { audio: Some(audio), ..Default::default() });
+        // LD V0, 2; LD ST, V0; JP self
+        machine.load_rom(&Rom::from_memory(vec![0x60, 0x02, 0xF0, 0x18, 0x12, 0x04])).unwrap();
+
+        for _ in 0..4 {
+            machine.run_frame().unwrap();
+        }
+        let samples = machine.beeper_mut().unwrap().take_samples();
+
+        // the timer is set by the second of ten instructions a frame and counts two frames
+        assert_eq!(400, samples.len());
+        assert!(samples[..10].iter().all(|s| *s == 0));
+        assert!(samples[10..200].iter().all(|s| *s != 0));
+        assert!(samples[200..].iter().all(|s| *s == 0));
+    }
+
+    #[test]
+    fn it_will_trace_when_asked() {
+        let mut machine = machine(vec![0x70, 0x01, 0x12, 0x00]);
+        machine.set_tracer(Some(Tracer::new(Box::new(Vec::new()), Format::Text)));
+
+        machine.run_frame().unwrap();
+        let tracer = machine.set_tracer(None).unwrap();
+
+        assert_eq!(10, tracer.executed());
+    }
+
+    #[test]
+    fn it_will_profile_when_asked() {
+        let mut machine = machine(vec![0x70, 0x01, 0x12, 0x00]);
+        machine.set_profiler(Some(Profiler::new()));
+
+        machine.run_frame().unwrap();
+
+        assert_eq!(10, machine.profiler().unwrap().total());
+        assert_eq!(5, machine.profiler().unwrap().hot_loops()[0].taken);
+    }
+
+    #[test]
+    fn it_will_apply_the_config() {
+        let config = Config { quirks: Quirks::schip(), seed: 0x55, ..Default::default() };
+        let machine = Machine::new(config);
+
+        assert_eq!(Quirks::schip(), machine.state().quirks);
+        assert_eq!(0x55, machine.state().random.seed());
+    }
+
+    #[test]
+    fn it_will_run_frames_with_the_block_engine() {
+        let mut machine = machine(vec![0x70, 0x01, 0x12, 0x00]);
+        machine.set_engine(Engine::Blocks);
+
+        machine.run_frame().unwrap();
+        machine.step().unwrap();
+
+        assert_eq!(6, machine.state().registers[0]);
+        assert_eq!(10, machine.clock().instructions());
+        assert_eq!(1, machine.clock().frames());
+    }
+
+    #[test]
+    fn it_will_trace_with_the_interpreter_whatever_the_engine() {
+        let config = Config { engine: Engine::Blocks, ..Default::default() };
+        let mut machine = Machine::new(config);
+        machine.load_rom(&Rom::from_memory(vec![0x70, 0x01, 0x12, 0x00])).unwrap();
+        machine.set_tracer(Some(Tracer::new(Box::new(Vec::new()), Format::Text)));
+
+        machine.run_frame().unwrap();
+        let tracer = machine.set_tracer(None).unwrap();
+
+        assert_eq!(10, tracer.executed());
+    }
+}
@@ -1,5 +1,27 @@
+use std::ops::Range;
+use crate::opcode::OpCode;
+use crate::rom::PROGRAM_START;
+
+/// Address of the 5-byte low resolution font
+pub const FONT_ADDRESS: u16 = 0x0;
+/// Address of the 10-byte high resolution font
+pub const BIG_FONT_ADDRESS: u16 = 0x50;
+/// Size of the memory of a chip8 or SUPER-CHIP machine
+pub const MEMORY_SIZE: usize = 1024 * 4;
+/// Size of the memory of an XO-CHIP machine
+pub const XO_CHIP_MEMORY_SIZE: usize = 1024 * 64;
+/// Size of the longest instruction, the XO-CHIP long I load
+const LONG_OPCODE_SIZE: usize = 4;
+
 pub struct Memory {
-    data: [u8; 1024 * 4]
+    data: Vec<u8>,
+    /// The raw and decoded instruction at each address, allocated on first use
+    decoded: Vec<Option<(u16, OpCode)>>,
+    caching: bool,
+    /// The bytes compiled into blocks by the block engine, allocated on first use
+    watched: Vec<bool>,
+    /// The span of watched bytes written since it was last taken
+    written_code: Option<Range<usize>>
 }
 
 impl Default for Memory {
@@ -11,9 +33,37 @@ impl Default for Memory {
 impl Memory {
     /// Creates a new memory buffer and loads in all font data from 0x0 to 0x200
     /// 
+    /// The low resolution font is loaded at `FONT_ADDRESS` and the
+    /// high resolution font at `BIG_FONT_ADDRESS`
+    /// 
     /// memory is set to 4kb
     pub fn new() -> Memory {
-        let mut memory = Memory { data: [0; 1024 * 4]};
+        Memory::with_size(MEMORY_SIZE)
+    }
+
+    /// Creates a new memory buffer of the given size and loads in all font data
+    /// 
+    /// The size can be no larger than `XO_CHIP_MEMORY_SIZE`, the most that
+    /// can be addressed by a 16-bit I register, and no smaller than
+    /// `PROGRAM_START`, so the fonts always fit.
+    /// 
+    /// Example:
+    /// 
+    /// ```
+    /// # use lib_chip::memory::{Memory, XO_CHIP_MEMORY_SIZE};
+    /// let memory = Memory::with_size(XO_CHIP_MEMORY_SIZE);
+    /// # assert_eq!(0xF0, memory.read(0x0));
+    /// # assert_eq!(0x0, memory.read(0xFFFF));
+    /// ```
+    pub fn with_size(size: usize) -> Memory {
+        let size = size.clamp(usize::from(PROGRAM_START), XO_CHIP_MEMORY_SIZE);
+        let mut memory = Memory {
+            data: vec![0; size],
+            decoded: Vec::new(),
+            caching: true,
+            watched: Vec::new(),
+            written_code: None
+        };
         memory.reset();
         memory
     }
@@ -45,9 +95,18 @@ impl Memory {
     /// # assert_eq!(0x00, memory.read(0x200));
     /// ```
     pub fn reset(&mut self) {
-        self.data = [0; 1024 * 4];
+        for byte in self.data.iter_mut() {
+            *byte = 0;
+        }
+        self.decoded.clear();
+        if !self.watched.is_empty() {
+            self.watched.clear();
+            self.written_code = Some(0..self.data.len());
+        }
         let text = load_text();
-        self.set_range(0x0, &text[..]);
+        self.set_range(FONT_ADDRESS as usize, &text[..]);
+        let big_text = load_big_text();
+        self.set_range(BIG_FONT_ADDRESS as usize, &big_text[..]);
     }
 
     /// Sets an array of data into memory from specified address
@@ -61,7 +120,8 @@ impl Memory {
     /// # assert_eq!(0x01, memory.read(0x200));
     /// ```
     pub fn set_range(&mut self, from: usize, data: &[u8]) {
-        self.data[from..(data.len()+from)].clone_from_slice(data)
+        self.data[from..(data.len()+from)].clone_from_slice(data);
+        self.invalidate(from, data.len());
     }
 
     /// Sets data at specified address
@@ -76,6 +136,107 @@ impl Memory {
     /// ```
     pub fn set(&mut self, address: usize, data: u8) {
         self.data[address] = data;
+        self.invalidate(address, 1);
+    }
+
+    /// Returns the number of addressable bytes
+    /// 
+    /// Example:
+    /// 
+    /// ```
+    /// # use lib_chip::memory::Memory;
+    /// # let memory:Memory = Default::default();
+    /// let size = memory.size();
+    /// # assert_eq!(4096, size);
+    /// ```
+    pub fn size(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Returns the whole of memory as an array slice.
+    pub fn read_all(&self) -> &[u8] {
+        &self.data[..]
+    }
+
+    /// Turns the cache of decoded instructions on or off, it starts on.
+    /// 
+    /// Turning it off forgets everything cached, so each instruction is
+    /// decoded again every time it runs.
+    /// 
+    /// Example:
+    /// 
+    /// ```
+    /// # use lib_chip::memory::Memory;
+    /// # let mut memory:Memory = Default::default();
+    /// memory.set_decode_cache(false);
+    /// # assert!(!memory.is_decode_cache_enabled());
+    /// ```
+    pub fn set_decode_cache(&mut self, enabled: bool) {
+        self.caching = enabled;
+        self.decoded = Vec::new();
+    }
+
+    pub fn is_decode_cache_enabled(&self) -> bool {
+        self.caching
+    }
+
+    /// Returns the raw and decoded instruction cached at an address, if it
+    /// has not been written to since it was cached
+    pub(crate) fn decoded(&self, address: u16) -> Option<(u16, OpCode)> {
+        self.decoded.get(usize::from(address)).copied().flatten()
+    }
+
+    /// Caches the instruction decoded at an address
+    pub(crate) fn cache_decoded(&mut self, address: u16, raw: u16, opcode: OpCode) {
+        if !self.caching {
+            return;
+        }
+        if self.decoded.is_empty() {
+            self.decoded = vec![None; self.data.len()];
+        }
+        self.decoded[usize::from(address)] = Some((raw, opcode));
+    }
+
+    /// Marks bytes as compiled code, so writes to them are recorded
+    pub(crate) fn watch(&mut self, from: usize, len: usize) {
+        if self.watched.is_empty() {
+            self.watched = vec![false; self.data.len()];
+        }
+        for watched in &mut self.watched[from..from + len] {
+            *watched = true;
+        }
+    }
+
+    /// Returns true if compiled code has been written since it was last taken
+    pub(crate) fn has_written_code(&self) -> bool {
+        self.written_code.is_some()
+    }
+
+    /// Returns the span of compiled code written since this was last called
+    pub(crate) fn take_written_code(&mut self) -> Option<Range<usize>> {
+        self.written_code.take()
+    }
+
+    /// Forgets the instructions that overlap the bytes written
+    fn invalidate(&mut self, from: usize, len: usize) {
+        if len == 0 {
+            return;
+        }
+
+        if !self.decoded.is_empty() {
+            let start = from.saturating_sub(LONG_OPCODE_SIZE - 1);
+            for entry in &mut self.decoded[start..from + len] {
+                *entry = None;
+            }
+        }
+
+        if !self.watched.is_empty() && self.watched[from..from + len].contains(&true) {
+            let written = match self.written_code.take() {
+                Some(span) => span.start.min(from)..span.end.max(from + len),
+                None => from..from + len
+            };
+            self.written_code = Some(written);
+        }
     }
 }
 
@@ -101,4 +262,28 @@ fn load_text() -> Vec<u8> {
     ];
 
     mem.to_vec()
-}
\ No newline at end of file
+}
+
+/// Loads the high resolution font data into a buffer
+fn load_big_text() -> Vec<u8> {
+    let mem: [u8; 160] = [
+        0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+        0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+        0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+        0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+        0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+        0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+        0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+        0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+        0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+        0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+        0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+        0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+        0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+        0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+        0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+        0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0  // F
+    ];
+
+    mem.to_vec()
+}
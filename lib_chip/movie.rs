@@ -0,0 +1,616 @@
+//! Records the keys held each frame so a run can be replayed exactly.
+//!
+//! A movie holds a hash of the rom, the machine config, which includes the
+//! random seed, and for every frame the keys held down, the keys pressed
+//! and released since the frame before, and a hash of the machine once the
+//! frame had run.  Replaying the keys on a machine built
+//! from the same config and rom repeats the run, and comparing the hashes
+//! finds the first frame a replay went differently.
+//!
+//! A frame that failed is recorded with its error, so a movie of a crashing
+//! run replays up to and including the crash.
+//!
+//! Movies are stored big endian after a 4 byte magic number and a 16-bit
+//! format version.
+//!
+//! # Example:
+//!
+//! ```
+//! # use lib_chip::machine::Machine;
+//! # use lib_chip::movie::{Movie, Recorder};
+//! # use lib_chip::rom::Rom;
+//! let rom = Rom::from_memory(vec![0xC0, 0xFF, 0xE0, 0xA1, 0x70, 0x01, 0x12, 0x00]);
+//! let mut machine = Machine::new(Default::default());
+//! machine.load_rom(&rom)?;
+//!
+//! let mut recorder = Recorder::new(&mut machine);
+//! for frame in 0..120 {
+//!     if frame % 10 == 0 { machine.press(0x1) } else { machine.release(0x1) }
+//!     recorder.run_frame(&mut machine)?;
+//! }
+//!
+//! let data = recorder.finish().to_bytes();
+//! Movie::from_bytes(&data)?.verify(&rom)?;
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use crate::engine::Engine;
+use crate::keypad::{Keypad, KEY_COUNT};
+use crate::machine::{Config, Machine};
+use crate::memory::XO_CHIP_MEMORY_SIZE;
+use crate::rom::{Rom, RomTooLarge, PROGRAM_START};
+use crate::savestate;
+use crate::state::{ExecError, Quirks};
+
+/// Identifies the start of a movie
+pub const MAGIC: [u8; 4] = *b"C8MV";
+/// The version of the format written by `Movie::to_bytes`
+///
+/// Frame hashes are taken over save states, so this changes with `savestate::VERSION`.
+pub const VERSION: u16 = 1;
+
+/// Hashes bytes with 64-bit FNV-1a.
+pub fn hash(data: &[u8]) -> u64 {
+    data.iter().fold(0xCBF2_9CE4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0000_0100_0000_01B3)
+    })
+}
+
+/// Hashes the state, memory and screen of a machine.
+pub fn machine_hash(machine: &Machine) -> u64 {
+    hash(&savestate::save(machine.state(), machine.memory(), machine.screen()))
+}
+
+/// A frame of a movie
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Frame {
+    /// The keys held down during the frame, one bit per key
+    pub keys: u16,
+    /// The keys that went down since the frame before, even if they came up again
+    pub pressed: u16,
+    /// The keys that came up since the frame before, even if they went down again
+    pub released: u16,
+    /// The hash of the machine after the frame
+    pub hash: u64,
+    /// The error the frame stopped with, if an instruction failed
+    pub error: Option<ExecError>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Movie {
+    pub rom_hash: u64,
+    /// The config the run started from, audio and the engine are not recorded
+    /// as they do not change the run
+    pub config: Config,
+    pub frames: Vec<Frame>,
+}
+
+/// Describes why a movie could not be read.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum MovieError {
+    /// The data does not start with the movie magic number.
+    InvalidMagic,
+    /// The data was written by a different version of the format.
+    UnsupportedVersion(u16),
+    /// The data ends part way through the movie.
+    Truncated,
+    /// A frame failed with an error this version does not know.
+    UnknownError(u8),
+    /// The recorded memory is too small to hold the fonts or larger than can be addressed.
+    InvalidMemorySize(usize),
+    /// There are bytes left over after the movie.
+    TrailingData(usize),
+}
+
+impl Display for MovieError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            MovieError::InvalidMagic => write!(f, "not a movie"),
+            MovieError::UnsupportedVersion(v) => {
+                write!(f, "movie version {} is not supported, expected {}", v, VERSION)
+            },
+            MovieError::Truncated => write!(f, "movie is truncated"),
+            MovieError::UnknownError(kind) => write!(f, "frame failed with unknown error {}", kind),
+            MovieError::InvalidMemorySize(size) => write!(f, "recorded memory size {} is invalid", size),
+            MovieError::TrailingData(len) => write!(f, "{} unexpected bytes after movie", len)
+        }
+    }
+}
+
+impl Error for MovieError {}
+
+/// Describes why a replay failed.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ReplayError {
+    /// The rom is not the one the movie was recorded with.
+    RomMismatch { expected: u64, actual: u64 },
+    /// The rom does not fit in the memory of the recorded config.
+    RomTooLarge(RomTooLarge),
+    /// An instruction failed during the frame that did not fail that way when recorded.
+    Exec { frame: usize, error: ExecError },
+    /// The frame ran to the end but failed when recorded.
+    Completed { frame: usize, expected: ExecError },
+    /// The machine differs from the recording after the frame.
+    Diverged { frame: usize, expected: u64, actual: u64 },
+}
+
+impl Display for ReplayError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            ReplayError::RomMismatch { expected, actual } => {
+                write!(f, "rom hash {:016x} does not match the recorded {:016x}", actual, expected)
+            },
+            ReplayError::RomTooLarge(err) => write!(f, "{}", err),
+            ReplayError::Exec { frame, error } => write!(f, "frame {}: {}", frame, error),
+            ReplayError::Completed { frame, expected } => {
+                write!(f, "frame {} ran without the recorded error: {}", frame, expected)
+            },
+            ReplayError::Diverged { frame, expected, actual } => {
+                write!(f, "replay diverged at frame {}, hash {:016x} expected {:016x}", frame, actual, expected)
+            }
+        }
+    }
+}
+
+impl Error for ReplayError {}
+
+impl Movie {
+    /// Runs the movie on a new machine and returns it after the last frame.
+    ///
+    /// Frames must fail with the errors they were recorded with, so a movie
+    /// of a crash returns the machine as it crashed.  The hashes of the
+    /// frames are not checked, see `verify`.
+    pub fn replay(&self, rom: &Rom) -> Result<Machine, ReplayError> {
+        self.run(rom, false)
+    }
+
+    /// Runs the movie, failing at the first frame the machine differs from the recording.
+    pub fn verify(&self, rom: &Rom) -> Result<(), ReplayError> {
+        self.run(rom, true).map(|_| ())
+    }
+
+    fn run(&self, rom: &Rom, check: bool) -> Result<Machine, ReplayError> {
+        let actual = hash(rom.read_all());
+        if actual != self.rom_hash {
+            return Err(ReplayError::RomMismatch { expected: self.rom_hash, actual });
+        }
+
+        let mut machine = Machine::new(self.config);
+        machine.load_rom(rom).map_err(ReplayError::RomTooLarge)?;
+
+        for (index, frame) in self.frames.iter().enumerate() {
+            replay_keys(machine.keypad_mut(), frame);
+            match (machine.run_frame(), frame.error) {
+                (Err(error), expected) if expected != Some(error) => {
+                    return Err(ReplayError::Exec { frame: index, error });
+                },
+                (Ok(()), Some(expected)) => return Err(ReplayError::Completed { frame: index, expected }),
+                _ => ()
+            }
+
+            if check {
+                let actual = machine_hash(&machine);
+                if actual != frame.hash {
+                    return Err(ReplayError::Diverged { frame: index, expected: frame.hash, actual });
+                }
+            }
+        }
+
+        Ok(machine)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let quirks = self.config.quirks;
+        let flags = [quirks.shift_uses_vy, quirks.load_store_increments_i, quirks.logic_resets_vf,
+            quirks.jump_uses_vx, quirks.clip_sprites, quirks.display_wait];
+
+        let mut data = Vec::with_capacity(40 + self.frames.len() * 15);
+        data.extend_from_slice(&MAGIC);
+        data.extend_from_slice(&VERSION.to_be_bytes());
+        data.extend_from_slice(&self.rom_hash.to_be_bytes());
+        data.extend_from_slice(&self.config.seed.to_be_bytes());
+        data.extend_from_slice(&self.config.instructions_per_second.to_be_bytes());
+        data.extend_from_slice(&(self.config.memory_size as u32).to_be_bytes());
+        data.push(flags.iter().enumerate().fold(0, |acc, (bit, set)| acc | (u8::from(*set) << bit)));
+        data.extend_from_slice(&(self.frames.len() as u32).to_be_bytes());
+        for frame in self.frames.iter() {
+            data.extend_from_slice(&frame.keys.to_be_bytes());
+            data.extend_from_slice(&frame.pressed.to_be_bytes());
+            data.extend_from_slice(&frame.released.to_be_bytes());
+            data.extend_from_slice(&frame.hash.to_be_bytes());
+            write_error(&mut data, frame.error);
+        }
+
+        data
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Movie, MovieError> {
+        let mut reader = Reader { data };
+        if reader.take::<4>()? != MAGIC {
+            return Err(MovieError::InvalidMagic);
+        }
+
+        let version = u16::from_be_bytes(reader.take()?);
+        if version != VERSION {
+            return Err(MovieError::UnsupportedVersion(version));
+        }
+
+        let rom_hash = u64::from_be_bytes(reader.take()?);
+        let seed = u64::from_be_bytes(reader.take()?);
+        let instructions_per_second = u32::from_be_bytes(reader.take()?);
+        let memory_size = u32::from_be_bytes(reader.take()?) as usize;
+        if memory_size < usize::from(PROGRAM_START) || memory_size > XO_CHIP_MEMORY_SIZE {
+            return Err(MovieError::InvalidMemorySize(memory_size));
+        }
+        let [flags] = reader.take()?;
+        let quirk = |bit: u8| flags & (1 << bit) != 0;
+        let quirks = Quirks {
+            shift_uses_vy: quirk(0),
+            load_store_increments_i: quirk(1),
+            logic_resets_vf: quirk(2),
+            jump_uses_vx: quirk(3),
+            clip_sprites: quirk(4),
+            display_wait: quirk(5)
+        };
+
+        let count = u32::from_be_bytes(reader.take()?) as usize;
+        let mut frames = Vec::with_capacity(count.min(data.len() / 15));
+        for _ in 0..count {
+            let keys = u16::from_be_bytes(reader.take()?);
+            let pressed = u16::from_be_bytes(reader.take()?);
+            let released = u16::from_be_bytes(reader.take()?);
+            let hash = u64::from_be_bytes(reader.take()?);
+            let error = reader.error()?;
+            frames.push(Frame { keys, pressed, released, hash, error });
+        }
+
+        if !reader.data.is_empty() {
+            return Err(MovieError::TrailingData(reader.data.len()));
+        }
+
+        let config = Config { quirks, memory_size, instructions_per_second, seed, audio: None, engine: Engine::Interpreter };
+        Ok(Movie { rom_hash, config, frames })
+    }
+}
+
+/// Presses and releases keys so the keypad holds the frame's keys with its edges.
+///
+/// A key released and pressed again is released first, a key pressed and
+/// released again is pressed first, then every key is left as it was held.
+fn replay_keys(keypad: &mut Keypad, frame: &Frame) {
+    keypad.clear_edges();
+    for key in 0..KEY_COUNT {
+        let bit = 1 << key;
+        if frame.released & bit != 0 {
+            keypad.release(key);
+        }
+        if frame.pressed & bit != 0 {
+            keypad.press(key);
+        }
+        if frame.keys & bit != 0 {
+            keypad.press(key);
+        } else {
+            keypad.release(key);
+        }
+    }
+}
+
+/// Writes a byte for the kind of error, 0 for none, then its pc, opcode and any address
+fn write_error(data: &mut Vec<u8>, error: Option<ExecError>) {
+    let error = match error {
+        Some(error) => error,
+        None => return data.push(0)
+    };
+
+    data.push(match error {
+        ExecError::UnknownOpCode { .. } => 1,
+        ExecError::StackOverflow { .. } => 2,
+        ExecError::StackUnderflow { .. } => 3,
+        ExecError::MemoryOutOfRange { .. } => 4
+    });
+    data.extend_from_slice(&error.pc().to_be_bytes());
+    data.extend_from_slice(&error.opcode().to_be_bytes());
+    if let ExecError::MemoryOutOfRange { address, .. } = error {
+        data.extend_from_slice(&(address as u32).to_be_bytes());
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], MovieError> {
+        if self.data.len() < N {
+            return Err(MovieError::Truncated);
+        }
+
+        let mut bytes = [0; N];
+        bytes.copy_from_slice(&self.data[..N]);
+        self.data = &self.data[N..];
+        Ok(bytes)
+    }
+
+    fn error(&mut self) -> Result<Option<ExecError>, MovieError> {
+        let [kind] = self.take()?;
+        if kind == 0 {
+            return Ok(None);
+        }
+
+        let pc = u16::from_be_bytes(self.take()?);
+        let opcode = u16::from_be_bytes(self.take()?);
+        match kind {
+            1 => Ok(Some(ExecError::UnknownOpCode { pc, opcode })),
+            2 => Ok(Some(ExecError::StackOverflow { pc, opcode })),
+            3 => Ok(Some(ExecError::StackUnderflow { pc, opcode })),
+            4 => {
+                let address = u32::from_be_bytes(self.take()?) as usize;
+                Ok(Some(ExecError::MemoryOutOfRange { pc, opcode, address }))
+            },
+            _ => Err(MovieError::UnknownError(kind))
+        }
+    }
+}
+
+/// Records the frames a machine runs.
+pub struct Recorder {
+    movie: Movie,
+}
+
+impl Recorder {
+    /// Resets the machine, so the movie starts from the loaded rom, and starts recording.
+    pub fn new(machine: &mut Machine) -> Recorder {
+        machine.reset();
+        let rom = machine.rom().map(|rom| rom.read_all()).unwrap_or(&[]);
+
+        Recorder {
+            movie: Movie {
+                rom_hash: hash(rom),
+                // the memory is recorded at the size it was clamped to
+                config: Config {
+                    memory_size: machine.memory().size(),
+                    audio: None,
+                    engine: Engine::Interpreter,
+                    ..*machine.config()
+                },
+                frames: Vec::new(),
+            }
+        }
+    }
+
+    /// Runs a frame of the machine with the keys it has held down, recording them.
+    ///
+    /// A frame that fails is recorded with its error, which is returned.
+    pub fn run_frame(&mut self, machine: &mut Machine) -> Result<(), ExecError> {
+        let keypad = machine.keypad();
+        let (keys, pressed, released) = (keypad.pressed(), keypad.just_pressed(), keypad.just_released());
+        let result = machine.run_frame();
+        self.movie.frames.push(Frame { keys, pressed, released, hash: machine_hash(machine), error: result.err() });
+        result
+    }
+
+    pub fn movie(&self) -> &Movie {
+        &self.movie
+    }
+
+    pub fn finish(self) -> Movie {
+        self.movie
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A program that draws a random number while key 0 is held and counts frames in V1
+    fn rom() -> Rom {
+        Rom::from_memory(vec![
+            0xE0, 0xA1, // SKNP V0
+            0xC2, 0xFF, // RND V2, 0xFF
+            0xF3, 0x07, // LD V3, DT
+            0x33, 0x00, // SE V3, 0
+            0x12, 0x00, // JP 0x200
+            0x71, 0x01, // ADD V1, 1
+            0x63, 0x01, // LD V3, 1
+            0xF3, 0x15, // LD DT, V3
+            0x12, 0x00, // JP 0x200
+        ])
+    }
+
+    fn record(config: Config, frames: usize) -> Movie {
+        let mut machine = Machine::new(config);
+        machine.load_rom(&rom()).unwrap();
+        machine.run_frame().unwrap();
+
+        let mut recorder = Recorder::new(&mut machine);
+        for frame in 0..frames {
+            machine.keypad_mut().set_pressed(if frame % 3 == 0 { 0x1 } else { 0x0 });
+            recorder.run_frame(&mut machine).unwrap();
+        }
+        recorder.finish()
+    }
+
+    #[test]
+    fn it_will_replay_a_recording() {
+        let movie = record(Config { seed: 99, ..Default::default() }, 30);
+
+        let machine = movie.replay(&rom()).unwrap();
+
+        assert_eq!(movie.frames.last().unwrap().hash, machine_hash(&machine));
+        assert!(movie.verify(&rom()).is_ok());
+    }
+
+    #[test]
+    fn it_will_round_trip_a_movie() {
+        let movie = record(Config { quirks: Quirks::cosmac_vip(), seed: 7, ..Default::default() }, 10);
+
+        let restored = Movie::from_bytes(&movie.to_bytes()).unwrap();
+
+        assert_eq!(movie, restored);
+    }
+
+    #[test]
+    fn it_will_report_the_first_divergent_frame() {
+        let mut movie = record(Default::default(), 20);
+        movie.frames[6].keys ^= 0x1;
+
+        let err = movie.verify(&rom()).unwrap_err();
+
+        match err {
+            ReplayError::Diverged { frame, .. } => assert_eq!(6, frame),
+            other => panic!("unexpected {:?}", other)
+        }
+    }
+
+    #[test]
+    fn it_will_reject_a_different_rom() {
+        let movie = record(Default::default(), 1);
+
+        let err = movie.verify(&Rom::from_memory(vec![0x12, 0x00])).unwrap_err();
+
+        assert!(matches!(err, ReplayError::RomMismatch { .. }));
+    }
+
+    #[test]
+    fn it_will_reject_bad_data() {
+        let data = record(Default::default(), 2).to_bytes();
+
+        assert_eq!(Err(MovieError::InvalidMagic), Movie::from_bytes(b"C8STxxxx"));
+        assert_eq!(Err(MovieError::Truncated), Movie::from_bytes(&data[..data.len() - 1]));
+        assert_eq!(Err(MovieError::TrailingData(1)), Movie::from_bytes(&[&data[..], &[0x0]].concat()));
+    }
+
+    #[test]
+    fn it_will_reject_a_memory_size_the_machine_cannot_have() {
+        for size in [16, 0x1FF, XO_CHIP_MEMORY_SIZE + 1].iter() {
+            let mut movie = record(Default::default(), 1);
+            movie.config.memory_size = *size;
+
+            assert_eq!(Err(MovieError::InvalidMemorySize(*size)), Movie::from_bytes(&movie.to_bytes()));
+        }
+    }
+
+    /// Returns from a routine that was never called once key 0 is held
+    fn crashing_rom() -> Rom {
+        Rom::from_memory(vec![
+            0xE0, 0x9E, // SKP V0
+            0x12, 0x00, // JP 0x200
+            0x00, 0xEE, // RET
+        ])
+    }
+
+    fn record_crash() -> Movie {
+        let mut machine = Machine::new(Default::default());
+        machine.load_rom(&crashing_rom()).unwrap();
+
+        let mut recorder = Recorder::new(&mut machine);
+        for _ in 0..5 {
+            recorder.run_frame(&mut machine).unwrap();
+        }
+        machine.keypad_mut().set_pressed(0x1);
+        assert!(recorder.run_frame(&mut machine).is_err());
+        recorder.finish()
+    }
+
+    #[test]
+    fn it_will_record_the_frame_that_failed() {
+        let movie = record_crash();
+
+        assert_eq!(6, movie.frames.len());
+        assert_eq!(Some(ExecError::StackUnderflow { pc: 0x204, opcode: 0x00EE }), movie.frames[5].error);
+    }
+
+    #[test]
+    fn it_will_replay_a_recording_up_to_its_crash() {
+        let movie = record_crash();
+
+        let machine = movie.replay(&crashing_rom()).unwrap();
+
+        assert_eq!(0x204, machine.state().pc);
+        assert_eq!(movie.frames[5].hash, machine_hash(&machine));
+        assert!(movie.verify(&crashing_rom()).is_ok());
+    }
+
+    #[test]
+    fn it_will_report_a_replay_that_no_longer_crashes() {
+        let mut movie = record_crash();
+        movie.frames[5].keys = 0x0;
+
+        let err = movie.verify(&crashing_rom()).unwrap_err();
+
+        assert_eq!(ReplayError::Completed { frame: 5, expected: movie.frames[5].error.unwrap() }, err);
+    }
+
+    #[test]
+    fn it_will_report_a_replay_that_crashes_early() {
+        let mut movie = record_crash();
+        movie.frames[2].keys = 0x1;
+
+        let err = movie.verify(&crashing_rom()).unwrap_err();
+
+        assert!(matches!(err, ReplayError::Exec { frame: 2, .. }));
+    }
+
+    #[test]
+    fn it_will_round_trip_every_error() {
+        let errors = [
+            ExecError::UnknownOpCode { pc: 0x202, opcode: 0xFFFF },
+            ExecError::StackOverflow { pc: 0x204, opcode: 0x2204 },
+            ExecError::StackUnderflow { pc: 0x206, opcode: 0x00EE },
+            ExecError::MemoryOutOfRange { pc: 0x208, opcode: 0xF055, address: 0x1000 }
+        ];
+        let mut movie = record(Default::default(), errors.len() + 1);
+        for (frame, error) in movie.frames.iter_mut().zip(errors.iter()) {
+            frame.error = Some(*error);
+        }
+
+        let restored = Movie::from_bytes(&movie.to_bytes()).unwrap();
+
+        assert_eq!(movie, restored);
+    }
+
+    #[test]
+    fn it_will_reject_an_unknown_error() {
+        let mut data = record(Default::default(), 1).to_bytes();
+        *data.last_mut().unwrap() = 0x9;
+        data.extend_from_slice(&[0x2, 0x00, 0x00, 0xEE]);
+
+        assert_eq!(Err(MovieError::UnknownError(0x9)), Movie::from_bytes(&data));
+    }
+
+    /// Stores in V1 the key pressed and released while waiting in FX0A
+    fn key_rom() -> Rom {
+        Rom::from_memory(vec![
+            0xF1, 0x0A, // LD V1, K
+            0x12, 0x02, // JP 0x202
+        ])
+    }
+
+    #[test]
+    fn it_will_replay_a_key_tapped_between_frames() {
+        let mut machine = Machine::new(Default::default());
+        machine.load_rom(&key_rom()).unwrap();
+        let mut recorder = Recorder::new(&mut machine);
+        recorder.run_frame(&mut machine).unwrap();
+        machine.press(0x6);
+        machine.release(0x6);
+        recorder.run_frame(&mut machine).unwrap();
+        recorder.run_frame(&mut machine).unwrap();
+        assert_eq!(0x6, machine.state().registers[1]);
+
+        let movie = Movie::from_bytes(&recorder.finish().to_bytes()).unwrap();
+        let replayed = movie.replay(&key_rom()).unwrap();
+
+        assert_eq!(0x6, replayed.state().registers[1]);
+        assert!(movie.verify(&key_rom()).is_ok());
+    }
+
+    #[test]
+    fn it_will_record_the_memory_size_the_machine_has() {
+        let mut machine = Machine::new(Config { memory_size: 0x10, ..Default::default() });
+        let movie = Recorder::new(&mut machine).finish();
+
+        assert_eq!(0x200, Movie::from_bytes(&movie.to_bytes()).unwrap().config.memory_size);
+    }
+}
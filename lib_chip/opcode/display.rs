@@ -1,7 +1,7 @@
 //! Contains the formatting logic to be able to print the opcodes to console.
 
 use std::fmt::{self, Formatter, Display};
-use super::{AddOp, OpCode, ShiftOp, SkipOp, LoadOp, JumpOp};
+use super::{AddOp, OpCode, ShiftOp, SkipOp, LoadOp, JumpOp, ScrollOp};
 
 impl Display for OpCode {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
@@ -21,7 +21,13 @@ impl Display for OpCode {
             OpCode::DRW(x, y, n) => write!(f, "(0xDxyn): Draw {} at ({},{})", n, x, y),
             OpCode::OR(x, y) => write!(f, "(0x8xy1): Logically OR V[{}] and V[{}]", x, y),
             OpCode::AND(x, y) => write!(f, "(0x8xy2): Logically AND V[{}] and V[{}]", x, y),
-            OpCode::XOR(x, y) => write!(f, "0x8xy3): Logically XOR V[{}] and V[{}]", x, y)
+            OpCode::XOR(x, y) => write!(f, "0x8xy3): Logically XOR V[{}] and V[{}]", x, y),
+            OpCode::SCROLL(x) => write!(f, "{}", x),
+            OpCode::EXIT => write!(f, "(0x00FD): Exit"),
+            OpCode::LOW => write!(f, "(0x00FE): Low resolution"),
+            OpCode::HIGH => write!(f, "(0x00FF): High resolution"),
+            OpCode::PLANE(n) => write!(f, "(0xFn01): Select planes {}", n),
+            OpCode::AUDIO => write!(f, "(0xF002): Load audio pattern from I")
         }
     }
 }
@@ -29,8 +35,8 @@ impl Display for OpCode {
 impl Display for ShiftOp {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         match self {
-            ShiftOp::SHR(x) => write!(f, "(0x8xy6): Shift Right {}", x),
-            ShiftOp::SHL(x) => write!(f, "(0x8xyE): Shift Left {}", x)
+            ShiftOp::SHR(x, y) => write!(f, "(0x8xy6): Shift Right {} ({})", x, y),
+            ShiftOp::SHL(x, y) => write!(f, "(0x8xyE): Shift Left {} ({})", x, y)
         }
     }
 }
@@ -80,7 +86,24 @@ impl Display for LoadOp {
             LoadOp::LDF(x) => write!(f, "(0xFx29): Load Sprite at V[{}] into I", x),
             LoadOp::LDB(x) => write!(f, "(0xFx33): Load into I, I+1 and I+2 the BCD representation of V[{}]", x),
             LoadOp::LDIV0X(x) => write!(f, "(0xFx55): Load From I V0 to V[{}]", x),
-            LoadOp::LDV0XI(x) => write!(f, "(0xFx65): Read starting at I from V0 to V[{}]", x)
+            LoadOp::LDV0XI(x) => write!(f, "(0xFx65): Read starting at I from V0 to V[{}]", x),
+            LoadOp::LDHF(x) => write!(f, "(0xFx30): Load High Resolution Sprite at V[{}] into I", x),
+            LoadOp::LDRVX(x) => write!(f, "(0xFx75): Store V0 to V[{}] in RPL flags", x),
+            LoadOp::LDVXR(x) => write!(f, "(0xFx85): Read V0 to V[{}] from RPL flags", x),
+            LoadOp::LDIL(nnnn) => write!(f, "(0xF000nnnn): Set I to {}", nnnn),
+            LoadOp::LDIVXY(x, y) => write!(f, "(0x5xy2): Load From I V[{}] to V[{}]", x, y),
+            LoadOp::LDVXYI(x, y) => write!(f, "(0x5xy3): Read starting at I from V[{}] to V[{}]", x, y),
+            LoadOp::LDPITCH(x) => write!(f, "(0xFx3A): Set pitch to V[{}]", x)
+        }
+    }
+}
+
+impl Display for ScrollOp {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            ScrollOp::SCD(n) => write!(f, "(0x00Cn): Scroll down {}", n),
+            ScrollOp::SCR => write!(f, "(0x00FB): Scroll right 4"),
+            ScrollOp::SCL => write!(f, "(0x00FC): Scroll left 4")
         }
     }
 }
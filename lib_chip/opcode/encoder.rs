@@ -0,0 +1,180 @@
+//! Contains the encoders to turn an opcode back into the bytes stored in memory
+use super::{AddOp, OpCode, ShiftOp, SkipOp, LoadOp, JumpOp, ScrollOp};
+
+/// Builds an opcode from a high nibble and the x, y and n nibbles
+fn xyn(high: u16, x: u8, y: u8, n: u8) -> u16 {
+    high << 12 | (u16::from(x) & 0xF) << 8 | (u16::from(y) & 0xF) << 4 | (u16::from(n) & 0xF)
+}
+
+/// Builds an opcode from a high nibble, the x nibble and a byte
+fn xkk(high: u16, x: u8, kk: u8) -> u16 {
+    high << 12 | (u16::from(x) & 0xF) << 8 | u16::from(kk)
+}
+
+/// Builds an opcode from a high nibble and a 12-bit address
+fn nnn(high: u16, nnn: u16) -> u16 {
+    high << 12 | (nnn & 0x0FFF)
+}
+
+/// Encodes a structured opcode as the 16bit value that `parse_opcode` reads it from.
+///
+/// Operands wider than their field are truncated.  The XO-CHIP long I load
+/// encodes to its first two bytes, `0xF000`, use `encode_bytes` for the operand.
+///
+/// # Examples:
+///
+/// ```
+/// # use lib_chip::opcode::*;
+/// # use lib_chip::opcode::encoder::*;
+/// let opcode = encode_opcode(OpCode::DRW(0x1, 0x2, 0x5));
+/// # assert_eq!(0xD125, opcode);
+/// ```
+pub fn encode_opcode(opcode: OpCode) -> u16 {
+    match opcode {
+        OpCode::Unknown(raw) => raw,
+        OpCode::CLS => 0x00E0,
+        OpCode::RET => 0x00EE,
+        OpCode::EXIT => 0x00FD,
+        OpCode::LOW => 0x00FE,
+        OpCode::HIGH => 0x00FF,
+        OpCode::AUDIO => 0xF002,
+        OpCode::PLANE(n) => xkk(0xF, n, 0x01),
+        OpCode::SCROLL(op) => encode_scroll_op(op),
+        OpCode::CALL(location) => nnn(0x2, location),
+        OpCode::LD(op) => encode_load_op(op),
+        OpCode::JP(op) => encode_jump_op(op),
+        OpCode::SKIP(op) => encode_skip_op(op),
+        OpCode::ADD(op) => encode_add_op(op),
+        OpCode::SHIFT(op) => encode_shift_op(op),
+        OpCode::SUB(x, y) => xyn(0x8, x, y, 0x5),
+        OpCode::SUBN(x, y) => xyn(0x8, x, y, 0x7),
+        OpCode::OR(x, y) => xyn(0x8, x, y, 0x1),
+        OpCode::AND(x, y) => xyn(0x8, x, y, 0x2),
+        OpCode::XOR(x, y) => xyn(0x8, x, y, 0x3),
+        OpCode::RND(x, kk) => xkk(0xC, x, kk),
+        OpCode::DRW(x, y, n) => xyn(0xD, x, y, n)
+    }
+}
+
+/// Encodes a structured opcode as the bytes it occupies in memory.
+///
+/// This is the 16bit opcode, high byte first, followed by the operand of
+/// a long I load.
+///
+/// # Examples:
+///
+/// ```
+/// # use lib_chip::opcode::*;
+/// # use lib_chip::opcode::encoder::*;
+/// let bytes = encode_bytes(OpCode::LD(LoadOp::LDIL(0x1234)));
+/// # assert_eq!(vec![0xF0, 0x00, 0x12, 0x34], bytes);
+/// ```
+pub fn encode_bytes(opcode: OpCode) -> Vec<u8> {
+    let value = encode_opcode(opcode);
+    let mut bytes = vec![(value >> 8) as u8, value as u8];
+    if let OpCode::LD(LoadOp::LDIL(nnnn)) = opcode {
+        bytes.push((nnnn >> 8) as u8);
+        bytes.push(nnnn as u8);
+    }
+
+    bytes
+}
+
+fn encode_scroll_op(op: ScrollOp) -> u16 {
+    match op {
+        ScrollOp::SCD(n) => 0x00C0 | (u16::from(n) & 0xF),
+        ScrollOp::SCR => 0x00FB,
+        ScrollOp::SCL => 0x00FC
+    }
+}
+
+fn encode_jump_op(op: JumpOp) -> u16 {
+    match op {
+        JumpOp::JP(location) => nnn(0x1, location),
+        JumpOp::JPV0(location) => nnn(0xB, location)
+    }
+}
+
+fn encode_skip_op(op: SkipOp) -> u16 {
+    match op {
+        SkipOp::SE(x, kk) => xkk(0x3, x, kk),
+        SkipOp::SNE(x, kk) => xkk(0x4, x, kk),
+        SkipOp::SEXY(x, y) => xyn(0x5, x, y, 0x0),
+        SkipOp::SNEXY(x, y) => xyn(0x9, x, y, 0x0),
+        SkipOp::SKP(x) => xkk(0xE, x, 0x9E),
+        SkipOp::SKNP(x) => xkk(0xE, x, 0xA1)
+    }
+}
+
+fn encode_add_op(op: AddOp) -> u16 {
+    match op {
+        AddOp::ADD(x, kk) => xkk(0x7, x, kk),
+        AddOp::ADDREG(x, y) => xyn(0x8, x, y, 0x4),
+        AddOp::ADDI(x) => xkk(0xF, x, 0x1E)
+    }
+}
+
+fn encode_shift_op(op: ShiftOp) -> u16 {
+    match op {
+        ShiftOp::SHR(x, y) => xyn(0x8, x, y, 0x6),
+        ShiftOp::SHL(x, y) => xyn(0x8, x, y, 0xE)
+    }
+}
+
+fn encode_load_op(op: LoadOp) -> u16 {
+    match op {
+        LoadOp::LD(x, kk) => xkk(0x6, x, kk),
+        LoadOp::LDI(location) => nnn(0xA, location),
+        LoadOp::LDXY(x, y) => xyn(0x8, x, y, 0x0),
+        LoadOp::LDVXDT(x) => xkk(0xF, x, 0x07),
+        LoadOp::LDKEY(x) => xkk(0xF, x, 0x0A),
+        LoadOp::LDDTVX(x) => xkk(0xF, x, 0x15),
+        LoadOp::LDSTVX(x) => xkk(0xF, x, 0x18),
+        LoadOp::LDF(x) => xkk(0xF, x, 0x29),
+        LoadOp::LDHF(x) => xkk(0xF, x, 0x30),
+        LoadOp::LDB(x) => xkk(0xF, x, 0x33),
+        LoadOp::LDPITCH(x) => xkk(0xF, x, 0x3A),
+        LoadOp::LDIV0X(x) => xkk(0xF, x, 0x55),
+        LoadOp::LDV0XI(x) => xkk(0xF, x, 0x65),
+        LoadOp::LDRVX(x) => xkk(0xF, x, 0x75),
+        LoadOp::LDVXR(x) => xkk(0xF, x, 0x85),
+        LoadOp::LDIL(_) => 0xF000,
+        LoadOp::LDIVXY(x, y) => xyn(0x5, x, y, 0x2),
+        LoadOp::LDVXYI(x, y) => xyn(0x5, x, y, 0x3)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::parser::{parse_opcode, parse_long_opcode};
+
+    #[test]
+    fn it_will_round_trip_every_opcode() {
+        for raw in 0..=0xFFFFu16 {
+            let opcode = parse_opcode((raw >> 8) as u8, raw as u8);
+            assert_eq!(raw, encode_opcode(opcode), "{:04X} parsed as {:?}", raw, opcode);
+        }
+    }
+
+    #[test]
+    fn it_will_round_trip_long_instructions() {
+        for nnnn in [0x0000u16, 0x0200, 0x1234, 0xFFFF].iter() {
+            let bytes = encode_bytes(OpCode::LD(LoadOp::LDIL(*nnnn)));
+            let opcode = parse_long_opcode(bytes[0], bytes[1], bytes[2], bytes[3]);
+            assert_eq!(OpCode::LD(LoadOp::LDIL(*nnnn)), opcode);
+        }
+    }
+
+    #[test]
+    fn it_will_encode_short_instructions_as_two_bytes() {
+        let bytes = encode_bytes(OpCode::CALL(0x345));
+        assert_eq!(vec![0x23, 0x45], bytes);
+    }
+
+    #[test]
+    fn it_will_truncate_wide_operands() {
+        assert_eq!(0x2234, encode_opcode(OpCode::CALL(0x1234)));
+        assert_eq!(0x6F00, encode_opcode(OpCode::LD(LoadOp::LD(0x1F, 0x00))));
+    }
+}
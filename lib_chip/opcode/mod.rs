@@ -1,5 +1,6 @@
 pub mod display;
 pub mod parser;
+pub mod encoder;
 
 #[derive(Debug, Copy, Clone,PartialEq)]
 /// Represents all known opcodes for the Chip8 Emulator.
@@ -18,7 +19,7 @@ pub mod parser;
 /// 0xCxkk will be OpCode::RND
 /// where x will be the register index and kk will be the value to AND against random.
 pub enum OpCode {
-    /// Represents an unknown opcode.  Panics when parsed.
+    /// Represents an unknown opcode.  Executing it fails with `ExecError::UnknownOpCode`.
     Unknown(u16),
     /// Clear screen - 
     /// 
@@ -66,6 +67,8 @@ pub enum OpCode {
     /// of the screen. See instruction 8xy3 for more information on XOR, and section 2.4, #
     /// Display, for more information on the Chip-8 screen and sprites.
     /// 
+    /// When n is 0 a 16x16 sprite is drawn, made up of 32 bytes read two per row.
+    /// 
     /// 0xDxyn
     DRW(Register, Register, u8),
     /// Computes a bitwise OR of a and b
@@ -79,6 +82,65 @@ pub enum OpCode {
     XOR(Register, Register),
     /// Represents a collection of bitwise SHIFT operations
     SHIFT(ShiftOp),
+    /// Represents a collection of SUPER-CHIP screen scrolling operations
+    SCROLL(ScrollOp),
+    /// Exit the interpreter.
+    /// 
+    /// 0x00FD
+    EXIT,
+    /// Disable high resolution mode, the screen returns to 64x32.
+    /// 
+    /// 0x00FE
+    LOW,
+    /// Enable high resolution mode, the screen becomes 128x64.
+    /// 
+    /// 0x00FF
+    HIGH,
+    /// Select the XO-CHIP bitplanes n that draw, scroll and clear operate on.
+    /// 
+    /// 0xFn01
+    PLANE(u8),
+    /// Load the 16-byte XO-CHIP audio pattern buffer from memory starting at I.
+    /// 
+    /// 0xF002
+    AUDIO,
+}
+
+impl OpCode {
+    /// Returns the number of bytes the instruction occupies in memory.
+    /// 
+    /// This is 2 for every instruction other than the 4-byte XO-CHIP long I load.
+    /// 
+    /// Example:
+    /// 
+    /// ```
+    /// # use lib_chip::opcode::*;
+    /// # assert_eq!(2, OpCode::CLS.size());
+    /// let size = OpCode::LD(LoadOp::LDIL(0x1234)).size();
+    /// # assert_eq!(4, size);
+    /// ```
+    pub fn size(&self) -> u16 {
+        match self {
+            OpCode::LD(LoadOp::LDIL(_)) => 4,
+            _ => 2
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone,PartialEq)]
+pub enum ScrollOp {
+    /// Scroll the display down by n pixels.
+    /// 
+    /// 0x00Cn
+    SCD(u8),
+    /// Scroll the display right by 4 pixels.
+    /// 
+    /// 0x00FB
+    SCR,
+    /// Scroll the display left by 4 pixels.
+    /// 
+    /// 0x00FC
+    SCL,
 }
 
 #[derive(Debug, Copy, Clone,PartialEq)]
@@ -88,15 +150,19 @@ pub enum ShiftOp {
     /// If the least-significant bit of Vx is 1, then VF is set to 1, otherwise 0. 
     /// Then Vx is divided by 2.
     /// 
+    /// Vy is only used by interpreters that shift Vy into Vx.
+    /// 
     /// 0x8xy6
-    SHR(Register),
+    SHR(Register, Register),
     /// Set Vx = Vx SHL 1.
     /// 
     /// If the most-significant bit of Vx is 1, then VF is set to 1, otherwise to 0. 
     /// Then Vx is multiplied by 2.
     /// 
+    /// Vy is only used by interpreters that shift Vy into Vx.
+    /// 
     /// 0x8xyE
-    SHL(Register),
+    SHL(Register, Register),
 }
 
 #[derive(Debug, Copy, Clone,PartialEq)]
@@ -256,6 +322,40 @@ pub enum LoadOp {
     /// 
     /// (0xFx65)
     LDV0XI(Register),
+    /// Set I = location of the 10-byte high resolution sprite for digit Vx.
+    /// 
+    /// (0xFx30)
+    LDHF(Register),
+    /// Store registers V0 through Vx in the RPL user flags.
+    /// 
+    /// (0xFx75)
+    LDRVX(Register),
+    /// Read registers V0 through Vx from the RPL user flags.
+    /// 
+    /// (0xFx85)
+    LDVXR(Register),
+    /// Set I = nnnn.
+    /// 
+    /// The 16-bit address is read from the two bytes following the instruction.
+    /// 
+    /// (0xF000 0xnnnn)
+    LDIL(u16),
+    /// Store registers Vx through Vy in memory starting at location I.
+    /// 
+    /// The registers are stored in reverse order if x is greater than y, I is not modified.
+    /// 
+    /// (0x5xy2)
+    LDIVXY(Register, Register),
+    /// Read registers Vx through Vy from memory starting at location I.
+    /// 
+    /// The registers are read in reverse order if x is greater than y, I is not modified.
+    /// 
+    /// (0x5xy3)
+    LDVXYI(Register, Register),
+    /// Set the audio pitch register = Vx.
+    /// 
+    /// (0xFx3A)
+    LDPITCH(Register),
 }
 
 /// Represents a location in memory.
@@ -1,5 +1,5 @@
 //! Contains the parsers to take the data from memory and return an opcode
-use super::{AddOp, OpCode, ShiftOp, SkipOp, LoadOp, JumpOp};
+use super::{AddOp, OpCode, ShiftOp, SkipOp, LoadOp, JumpOp, ScrollOp};
 
 /// Generates a 16bit opcode from 2 8bit operands.
 /// These will generally be found at memory address x and x+1.
@@ -53,9 +53,15 @@ pub fn parse_opcode(high: u8, low: u8) -> OpCode {
 
     match opcode & 0xF000 {
         0x0000 => {
-            match kk {
+            match opcode {
                 0x00E0 => OpCode::CLS,
                 0x00EE => OpCode::RET,
+                0x00FB => OpCode::SCROLL(ScrollOp::SCR),
+                0x00FC => OpCode::SCROLL(ScrollOp::SCL),
+                0x00FD => OpCode::EXIT,
+                0x00FE => OpCode::LOW,
+                0x00FF => OpCode::HIGH,
+                _ if opcode & 0xFFF0 == 0x00C0 => OpCode::SCROLL(ScrollOp::SCD(n)),
                 _ => OpCode::Unknown(opcode)
             }
         },
@@ -63,7 +69,14 @@ pub fn parse_opcode(high: u8, low: u8) -> OpCode {
         0x2000 => OpCode::CALL(nnn),
         0x3000 => OpCode::SKIP(SkipOp::SE(x, kk)),
         0x4000 => OpCode::SKIP(SkipOp::SNE(x, kk)),
-        0x5000 => OpCode::SKIP(SkipOp::SEXY(x, y)),
+        0x5000 => {
+            match n {
+                0x0 => OpCode::SKIP(SkipOp::SEXY(x, y)),
+                0x2 => OpCode::LD(LoadOp::LDIVXY(x, y)),
+                0x3 => OpCode::LD(LoadOp::LDVXYI(x, y)),
+                _ => OpCode::Unknown(opcode)
+            }
+        },
         0x6000 => OpCode::LD(LoadOp::LD(x, kk)),
         0x7000 => OpCode::ADD(AddOp::ADD(x, kk)),
         0x8000 => {
@@ -74,9 +87,9 @@ pub fn parse_opcode(high: u8, low: u8) -> OpCode {
                 0x3 => OpCode::XOR(x, y),
                 0x4 => OpCode::ADD(AddOp::ADDREG(x, y)),
                 0x5 => OpCode::SUB(x, y),
-                0x6 => OpCode::SHIFT(ShiftOp::SHR(x)),
+                0x6 => OpCode::SHIFT(ShiftOp::SHR(x, y)),
                 0x7 => OpCode::SUBN(x, y),
-                0xE => OpCode::SHIFT(ShiftOp::SHL(x)),
+                0xE => OpCode::SHIFT(ShiftOp::SHL(x, y)),
                 _ => OpCode::Unknown(opcode)
             }
         },
@@ -99,15 +112,21 @@ pub fn parse_opcode(high: u8, low: u8) -> OpCode {
         },
         0xF000 => {
             match kk {
+                    0x01 => OpCode::PLANE(x),
+                    0x02 if x == 0 => OpCode::AUDIO,
                     0x07 => OpCode::LD(LoadOp::LDVXDT(x)),
                     0x0A => OpCode::LD(LoadOp::LDKEY(x)),
                     0x15 => OpCode::LD(LoadOp::LDDTVX(x)),
                     0x18 => OpCode::LD(LoadOp::LDSTVX(x)),
                     0x1E => OpCode::ADD(AddOp::ADDI(x)),
                     0x29 => OpCode::LD(LoadOp::LDF(x)),
+                    0x30 => OpCode::LD(LoadOp::LDHF(x)),
                     0x33 => OpCode::LD(LoadOp::LDB(x)),
+                    0x3A => OpCode::LD(LoadOp::LDPITCH(x)),
                     0x55 => OpCode::LD(LoadOp::LDIV0X(x)),
                     0x65 => OpCode::LD(LoadOp::LDV0XI(x)),
+                    0x75 => OpCode::LD(LoadOp::LDRVX(x)),
+                    0x85 => OpCode::LD(LoadOp::LDVXR(x)),
                 _ => OpCode::Unknown(opcode)
             }
         },
@@ -115,6 +134,40 @@ pub fn parse_opcode(high: u8, low: u8) -> OpCode {
     }
 }
 
+/// Returns true if the two bytes are the first half of the 4-byte
+/// XO-CHIP long I load, `0xF000 0xnnnn`.
+/// 
+/// ```
+/// # use lib_chip::opcode::parser::*;
+/// # assert!(!is_long_opcode(0xF1, 0x00));
+/// let long = is_long_opcode(0xF0, 0x00);
+/// # assert!(long);
+/// ```
+pub fn is_long_opcode(high: u8, low: u8) -> bool {
+    high == 0xF0 && low == 0x00
+}
+
+/// Parses an opcode that may be followed by a 16-bit operand.
+/// 
+/// If the first two bytes are a long I load the operand is read from the next
+/// two bytes, otherwise this is the same as `parse_opcode` and they are ignored.
+/// 
+/// # Examples:
+/// 
+/// ```
+/// # use lib_chip::opcode::*;
+/// # use lib_chip::opcode::parser::*;
+/// let opcode = parse_long_opcode(0xF0, 0x00, 0x12, 0x34);
+/// # assert_eq!(OpCode::LD(LoadOp::LDIL(0x1234)), opcode);
+/// ```
+pub fn parse_long_opcode(high: u8, low: u8, next_high: u8, next_low: u8) -> OpCode {
+    if is_long_opcode(high, low) {
+        OpCode::LD(LoadOp::LDIL(generate_opcode(next_high, next_low)))
+    } else {
+        parse_opcode(high, low)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -270,7 +323,7 @@ mod tests {
         const LOW:u8 = 0x46;
 
         let actual = parse_opcode(HIGH, LOW);
-        assert_eq!(OpCode::SHIFT(ShiftOp::SHR(0x05)), actual);
+        assert_eq!(OpCode::SHIFT(ShiftOp::SHR(0x05, 0x04)), actual);
     }
 
     #[test]
@@ -288,7 +341,7 @@ mod tests {
         const LOW:u8 = 0xCE;
 
         let actual = parse_opcode(HIGH, LOW);
-        assert_eq!(OpCode::SHIFT(ShiftOp::SHL(0x07)), actual);
+        assert_eq!(OpCode::SHIFT(ShiftOp::SHL(0x07, 0x0C)), actual);
     }
 
     #[test]
@@ -435,4 +488,145 @@ mod tests {
         let actual = parse_opcode(HIGH, LOW);
         assert_eq!(OpCode::LD(LoadOp::LDV0XI(0x0C)), actual);
     }
+
+    #[test]
+    fn it_will_scroll_down() {
+        const HIGH:u8 = 0x00;
+        const LOW:u8 = 0xC5;
+
+        let actual = parse_opcode(HIGH, LOW);
+        assert_eq!(OpCode::SCROLL(ScrollOp::SCD(0x05)), actual);
+    }
+
+    #[test]
+    fn it_will_scroll_right() {
+        const HIGH:u8 = 0x00;
+        const LOW:u8 = 0xFB;
+
+        let actual = parse_opcode(HIGH, LOW);
+        assert_eq!(OpCode::SCROLL(ScrollOp::SCR), actual);
+    }
+
+    #[test]
+    fn it_will_scroll_left() {
+        const HIGH:u8 = 0x00;
+        const LOW:u8 = 0xFC;
+
+        let actual = parse_opcode(HIGH, LOW);
+        assert_eq!(OpCode::SCROLL(ScrollOp::SCL), actual);
+    }
+
+    #[test]
+    fn it_will_exit() {
+        const HIGH:u8 = 0x00;
+        const LOW:u8 = 0xFD;
+
+        let actual = parse_opcode(HIGH, LOW);
+        assert_eq!(OpCode::EXIT, actual);
+    }
+
+    #[test]
+    fn it_will_switch_resolution() {
+        assert_eq!(OpCode::LOW, parse_opcode(0x00, 0xFE));
+        assert_eq!(OpCode::HIGH, parse_opcode(0x00, 0xFF));
+    }
+
+    #[test]
+    fn it_will_not_return_clear_screen_for_system_calls() {
+        const HIGH:u8 = 0x01;
+        const LOW:u8 = 0xE0;
+
+        let actual = parse_opcode(HIGH, LOW);
+        assert_eq!(OpCode::Unknown(0x01E0), actual);
+    }
+
+    #[test]
+    fn it_should_load_high_resolution_sprite_into_i() {
+        const HIGH:u8 = 0xF2;
+        const LOW:u8 = 0x30;
+
+        let actual = parse_opcode(HIGH, LOW);
+        assert_eq!(OpCode::LD(LoadOp::LDHF(0x02)), actual);
+    }
+
+    #[test]
+    fn it_will_store_registers_in_rpl_flags() {
+        const HIGH:u8 = 0xF7;
+        const LOW:u8 = 0x75;
+
+        let actual = parse_opcode(HIGH, LOW);
+        assert_eq!(OpCode::LD(LoadOp::LDRVX(0x07)), actual);
+    }
+
+    #[test]
+    fn it_will_read_registers_from_rpl_flags() {
+        const HIGH:u8 = 0xF3;
+        const LOW:u8 = 0x85;
+
+        let actual = parse_opcode(HIGH, LOW);
+        assert_eq!(OpCode::LD(LoadOp::LDVXR(0x03)), actual);
+    }
+
+    #[test]
+    fn it_will_not_parse_a_long_load_without_its_operand() {
+        const HIGH:u8 = 0xF0;
+        const LOW:u8 = 0x00;
+
+        let actual = parse_opcode(HIGH, LOW);
+        assert_eq!(OpCode::Unknown(0xF000), actual);
+    }
+
+    #[test]
+    fn it_will_load_long_address_into_i() {
+        let actual = parse_long_opcode(0xF0, 0x00, 0xAB, 0xCD);
+        assert_eq!(OpCode::LD(LoadOp::LDIL(0xABCD)), actual);
+    }
+
+    #[test]
+    fn it_will_parse_short_opcodes_ignoring_the_operand() {
+        let actual = parse_long_opcode(0x00, 0xE0, 0xAB, 0xCD);
+        assert_eq!(OpCode::CLS, actual);
+    }
+
+    #[test]
+    fn it_will_store_register_range() {
+        const HIGH:u8 = 0x52;
+        const LOW:u8 = 0x52;
+
+        let actual = parse_opcode(HIGH, LOW);
+        assert_eq!(OpCode::LD(LoadOp::LDIVXY(0x02, 0x05)), actual);
+    }
+
+    #[test]
+    fn it_will_read_register_range() {
+        const HIGH:u8 = 0x5A;
+        const LOW:u8 = 0x33;
+
+        let actual = parse_opcode(HIGH, LOW);
+        assert_eq!(OpCode::LD(LoadOp::LDVXYI(0x0A, 0x03)), actual);
+    }
+
+    #[test]
+    fn it_will_select_planes() {
+        const HIGH:u8 = 0xF3;
+        const LOW:u8 = 0x01;
+
+        let actual = parse_opcode(HIGH, LOW);
+        assert_eq!(OpCode::PLANE(0x03), actual);
+    }
+
+    #[test]
+    fn it_will_load_audio_pattern() {
+        assert_eq!(OpCode::AUDIO, parse_opcode(0xF0, 0x02));
+        assert_eq!(OpCode::Unknown(0xF102), parse_opcode(0xF1, 0x02));
+    }
+
+    #[test]
+    fn it_will_set_pitch() {
+        const HIGH:u8 = 0xF4;
+        const LOW:u8 = 0x3A;
+
+        let actual = parse_opcode(HIGH, LOW);
+        assert_eq!(OpCode::LD(LoadOp::LDPITCH(0x04)), actual);
+    }
 }
@@ -0,0 +1,319 @@
+//! Counts where a program spends its instructions.
+//!
+//! The profiler counts the instructions executed at each address and of each
+//! kind of opcode, the backward jumps taken, which mark the loops a program
+//! spends its time in, and the call stack each instruction ran under, built
+//! from CALL and RET.  Reports are written as a table or as collapsed stacks
+//! for flamegraph tools.
+//!
+//! # Example:
+//!
+//! ```
+//! # use lib_chip::keypad::Keypad;
+//! # use lib_chip::memory::Memory;
+//! # use lib_chip::state::State;
+//! # use lib_chip::profile::Profiler;
+//! let mut memory = Memory::new();
+//! // ADD V0, 1; SE V0, 10; JP 0x200; JP 0x206
+//! memory.set_range(0x200, &[0x70, 0x01, 0x30, 0x0A, 0x12, 0x00, 0x12, 0x06]);
+//! let mut state: State = Default::default();
+//! let mut screen = state.create_buffer();
+//!
+//! let mut profiler = Profiler::new();
+//! for _ in 0..35 {
+//!     profiler.execute(&mut state, &mut memory, &mut Keypad::new(), &mut screen)?;
+//! }
+//!
+//! let hottest = &profiler.hot_loops()[0];
+//! # assert_eq!((0x200, 0x204, 9), (hottest.start, hottest.end, hottest.taken));
+//! let mut table = Vec::new();
+//! profiler.write_table(&mut table, 10)?;
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+use std::collections::HashMap;
+use std::io::{self, Write};
+use crate::disasm::mnemonic;
+use crate::framebuffer::FrameBuffer;
+use crate::keypad::Keypad;
+use crate::memory::Memory;
+use crate::opcode::{AddOp, JumpOp, LoadOp, OpCode, ScrollOp, ShiftOp, SkipOp};
+use crate::state::{ExecError, State};
+
+/// Returns the name of the variant of an opcode, the inner variant for
+/// opcodes grouped by kind
+pub fn variant_name(opcode: OpCode) -> &'static str {
+    match opcode {
+        OpCode::Unknown(_) => "Unknown",
+        OpCode::CLS => "CLS",
+        OpCode::RET => "RET",
+        OpCode::CALL(_) => "CALL",
+        OpCode::SUB(..) => "SUB",
+        OpCode::SUBN(..) => "SUBN",
+        OpCode::RND(..) => "RND",
+        OpCode::DRW(..) => "DRW",
+        OpCode::OR(..) => "OR",
+        OpCode::AND(..) => "AND",
+        OpCode::XOR(..) => "XOR",
+        OpCode::EXIT => "EXIT",
+        OpCode::LOW => "LOW",
+        OpCode::HIGH => "HIGH",
+        OpCode::PLANE(_) => "PLANE",
+        OpCode::AUDIO => "AUDIO",
+        OpCode::JP(JumpOp::JP(_)) => "JP",
+        OpCode::JP(JumpOp::JPV0(_)) => "JPV0",
+        OpCode::SKIP(SkipOp::SE(..)) => "SE",
+        OpCode::SKIP(SkipOp::SNE(..)) => "SNE",
+        OpCode::SKIP(SkipOp::SEXY(..)) => "SEXY",
+        OpCode::SKIP(SkipOp::SNEXY(..)) => "SNEXY",
+        OpCode::SKIP(SkipOp::SKP(_)) => "SKP",
+        OpCode::SKIP(SkipOp::SKNP(_)) => "SKNP",
+        OpCode::ADD(AddOp::ADD(..)) => "ADD",
+        OpCode::ADD(AddOp::ADDREG(..)) => "ADDREG",
+        OpCode::ADD(AddOp::ADDI(_)) => "ADDI",
+        OpCode::SHIFT(ShiftOp::SHR(..)) => "SHR",
+        OpCode::SHIFT(ShiftOp::SHL(..)) => "SHL",
+        OpCode::SCROLL(ScrollOp::SCD(_)) => "SCD",
+        OpCode::SCROLL(ScrollOp::SCR) => "SCR",
+        OpCode::SCROLL(ScrollOp::SCL) => "SCL",
+        OpCode::LD(op) => match op {
+            LoadOp::LD(..) => "LD",
+            LoadOp::LDI(_) => "LDI",
+            LoadOp::LDXY(..) => "LDXY",
+            LoadOp::LDVXDT(_) => "LDVXDT",
+            LoadOp::LDDTVX(_) => "LDDTVX",
+            LoadOp::LDKEY(_) => "LDKEY",
+            LoadOp::LDSTVX(_) => "LDSTVX",
+            LoadOp::LDF(_) => "LDF",
+            LoadOp::LDB(_) => "LDB",
+            LoadOp::LDIV0X(_) => "LDIV0X",
+            LoadOp::LDV0XI(_) => "LDV0XI",
+            LoadOp::LDHF(_) => "LDHF",
+            LoadOp::LDRVX(_) => "LDRVX",
+            LoadOp::LDVXR(_) => "LDVXR",
+            LoadOp::LDIL(_) => "LDIL",
+            LoadOp::LDIVXY(..) => "LDIVXY",
+            LoadOp::LDVXYI(..) => "LDVXYI",
+            LoadOp::LDPITCH(_) => "LDPITCH"
+        }
+    }
+}
+
+/// A backward jump and how often it was taken
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Loop {
+    /// The target of the jump, the top of the loop
+    pub start: u16,
+    /// The address of the jump
+    pub end: u16,
+    pub taken: u64,
+    /// The instructions executed between the start and end of the loop
+    pub instructions: u64,
+}
+
+/// Counts the instructions a program executes.
+#[derive(Debug, Clone, Default)]
+pub struct Profiler {
+    total: u64,
+    /// The instructions executed at each address and the last opcode seen there
+    addresses: HashMap<u16, (u64, OpCode)>,
+    opcodes: HashMap<&'static str, u64>,
+    /// Backward jumps by address and target
+    jumps: HashMap<(u16, u16), u64>,
+    /// The routines entered and not yet returned from, the first is where profiling started
+    calls: Vec<u16>,
+    stacks: HashMap<Vec<u16>, u64>,
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        Default::default()
+    }
+
+    /// Executes an instruction like `State::execute` and counts it.
+    pub fn execute(&mut self, state: &mut State, memory: &mut Memory, keypad: &mut Keypad,
+        screen: &mut FrameBuffer) -> Result<(), ExecError> {
+        if !state.run_flag {
+            return Ok(());
+        }
+
+        let pc = state.pc;
+        state.execute(memory, keypad, screen)?;
+        self.record(pc, state);
+        Ok(())
+    }
+
+    /// Executes an instruction like `State::step` and counts it.
+    pub fn step(&mut self, state: State, memory: &mut Memory, keypad: &mut Keypad,
+        screen: &mut FrameBuffer) -> Result<State, ExecError> {
+        let mut state = state;
+        self.execute(&mut state, memory, keypad, screen)?;
+        Ok(state)
+    }
+
+    /// Counts an instruction executed at `pc` that left the machine in `after`.
+    pub fn record(&mut self, pc: u16, after: &State) {
+        let opcode = after.last_opcode;
+        self.total += 1;
+
+        let entry = self.addresses.entry(pc).or_insert((0, opcode));
+        *entry = (entry.0 + 1, opcode);
+        *self.opcodes.entry(variant_name(opcode)).or_insert(0) += 1;
+
+        if self.calls.is_empty() {
+            self.calls.push(pc);
+        }
+        match self.stacks.get_mut(&self.calls[..]) {
+            Some(count) => *count += 1,
+            None => { self.stacks.insert(self.calls.clone(), 1); }
+        }
+
+        match opcode {
+            OpCode::CALL(nnn) => self.calls.push(nnn),
+            // returning from the routine profiling started in leaves the root in place
+            OpCode::RET if self.calls.len() > 1 => { self.calls.pop(); },
+            OpCode::JP(_) if after.pc <= pc => {
+                *self.jumps.entry((pc, after.pc)).or_insert(0) += 1;
+            },
+            _ => ()
+        }
+    }
+
+    /// Returns the number of instructions counted
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// Returns the instructions executed at each address, most first
+    pub fn by_address(&self) -> Vec<(u16, u64, OpCode)> {
+        let mut addresses: Vec<_> = self.addresses.iter()
+            .map(|(address, (count, opcode))| (*address, *count, *opcode))
+            .collect();
+        addresses.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        addresses
+    }
+
+    /// Returns the instructions executed of each opcode variant, most first
+    pub fn by_opcode(&self) -> Vec<(&'static str, u64)> {
+        let mut opcodes: Vec<_> = self.opcodes.iter().map(|(name, count)| (*name, *count)).collect();
+        opcodes.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        opcodes
+    }
+
+    /// Returns the backward jumps taken, most taken first
+    pub fn hot_loops(&self) -> Vec<Loop> {
+        let mut loops: Vec<Loop> = self.jumps.iter().map(|((end, start), taken)| {
+            let instructions = self.addresses.iter()
+                .filter(|(address, _)| (*start..=*end).contains(address))
+                .map(|(_, (count, _))| count)
+                .sum();
+            Loop { start: *start, end: *end, taken: *taken, instructions }
+        }).collect();
+        loops.sort_by(|a, b| b.taken.cmp(&a.taken).then(a.start.cmp(&b.start)));
+        loops
+    }
+
+    /// Writes the busiest addresses, opcodes and loops as tables, up to `limit` rows each.
+    pub fn write_table<W: Write>(&self, writer: &mut W, limit: usize) -> io::Result<()> {
+        let percent = |count: u64| 100.0 * count as f64 / self.total.max(1) as f64;
+
+        writeln!(writer, "{} instructions", self.total)?;
+        writeln!(writer)?;
+        writeln!(writer, "address      count       %  instruction")?;
+        for (address, count, opcode) in self.by_address().into_iter().take(limit) {
+            writeln!(writer, "0x{:03X} {:>12} {:>6.2}  {}", address, count, percent(count), mnemonic(opcode))?;
+        }
+
+        writeln!(writer)?;
+        writeln!(writer, "opcode        count       %")?;
+        for (name, count) in self.by_opcode().into_iter().take(limit) {
+            writeln!(writer, "{:<8} {:>10} {:>7.2}", name, count, percent(count))?;
+        }
+
+        writeln!(writer)?;
+        writeln!(writer, "loop            taken  instructions       %")?;
+        for hot in self.hot_loops().into_iter().take(limit) {
+            writeln!(writer, "0x{:03X}-0x{:03X} {:>9} {:>13} {:>7.2}",
+                hot.start, hot.end, hot.taken, hot.instructions, percent(hot.instructions))?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the instructions counted under each call stack in the collapsed
+    /// format read by flamegraph tools, one `frame;frame count` line per stack.
+    pub fn write_collapsed<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let mut stacks: Vec<(String, u64)> = self.stacks.iter().map(|(calls, count)| {
+            let frames: Vec<String> = calls.iter().map(|address| format!("0x{:03X}", address)).collect();
+            (frames.join(";"), *count)
+        }).collect();
+        stacks.sort();
+
+        for (stack, count) in stacks {
+            writeln!(writer, "{} {}", stack, count)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(program: &[u8], steps: usize) -> Profiler {
+        let mut memory = Memory::new();
+        memory.set_range(0x200, program);
+        let mut state: State = Default::default();
+        let mut screen = state.create_buffer();
+        let mut profiler = Profiler::new();
+
+        for _ in 0..steps {
+            state = profiler.step(state, &mut memory, &mut Keypad::new(), &mut screen).unwrap();
+        }
+        profiler
+    }
+
+    /// CALL 0x206; JP 0x200; nothing; ADD V0, 1; RET
+    const PROGRAM: [u8; 12] = [0x22, 0x06, 0x12, 0x00, 0x00, 0x00, 0x70, 0x01, 0x00, 0xEE, 0x00, 0x00];
+
+    #[test]
+    fn it_will_count_by_address_and_opcode() {
+        let profiler = profile(&PROGRAM, 12);
+
+        assert_eq!(12, profiler.total());
+        assert_eq!((0x200, 3), (profiler.by_address()[0].0, profiler.by_address()[0].1));
+        assert_eq!(4, profiler.by_opcode().len());
+        assert_eq!(("ADD", 3), profiler.by_opcode()[0]);
+    }
+
+    #[test]
+    fn it_will_find_hot_loops() {
+        let profiler = profile(&PROGRAM, 12);
+
+        let loops = profiler.hot_loops();
+
+        assert_eq!(vec![Loop { start: 0x200, end: 0x202, taken: 3, instructions: 6 }], loops);
+    }
+
+    #[test]
+    fn it_will_write_collapsed_stacks() {
+        let profiler = profile(&PROGRAM, 12);
+        let mut out = Vec::new();
+
+        profiler.write_collapsed(&mut out).unwrap();
+
+        assert_eq!("0x200 6\n0x200;0x206 6\n", String::from_utf8(out).unwrap());
+    }
+
+    #[test]
+    fn it_will_write_a_table() {
+        let profiler = profile(&PROGRAM, 12);
+        let mut out = Vec::new();
+
+        profiler.write_table(&mut out, 2).unwrap();
+
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.starts_with("12 instructions\n"));
+        assert!(text.contains("0x200            3  25.00  CALL 0x206\n"));
+        assert!(text.contains("0x200-0x202         3             6   50.00\n"));
+    }
+}
@@ -0,0 +1,227 @@
+//! Sources of the random numbers used by CXKK.
+//!
+//! A state owns its source, so a program given the same seed always draws the
+//! same numbers.  `SeededRandom` is used unless another source is given.
+//!
+//! # Example:
+//!
+//! ```
+//! # use lib_chip::state::State;
+//! # use lib_chip::random::SeededRandom;
+//! let state = State {
+//!     random: Box::new(SeededRandom::new(42)),
+//!     ..Default::default()
+//! };
+//! # assert_eq!(42, state.random.seed());
+//! ```
+use std::fmt::Debug;
+use crate::memory::Memory;
+
+/// The seed used by `SeededRandom::default`
+pub const DEFAULT_SEED: u64 = 0x2545_F491_4F6C_DD1D;
+
+/// The kinds of random source, so a saved source can be rebuilt.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum RandomKind {
+    Seeded,
+    CosmacVip,
+    /// A source defined outside this crate
+    Custom,
+}
+
+impl RandomKind {
+    /// Builds a source of this kind continuing from `seed`, `None` for a custom source.
+    pub fn create(self, seed: u64) -> Option<Box<dyn RandomSource>> {
+        let mut source: Box<dyn RandomSource> = match self {
+            RandomKind::Seeded => Box::new(SeededRandom::default()),
+            RandomKind::CosmacVip => Box::new(CosmacVipRandom::default()),
+            RandomKind::Custom => return None
+        };
+        source.reseed(seed);
+        Some(source)
+    }
+}
+
+/// Produces the random bytes drawn by CXKK.
+pub trait RandomSource: Debug {
+    /// Draws the next random byte.
+    ///
+    /// Memory is given for sources that, like the original interpreter, read
+    /// their randomness from it.
+    fn next_byte(&mut self, memory: &Memory) -> u8;
+
+    /// Returns a seed that continues the sequence from the current position
+    /// when passed to `reseed`.
+    fn seed(&self) -> u64;
+
+    /// Moves the sequence to the position described by a seed.
+    fn reseed(&mut self, seed: u64);
+
+    /// Returns which kind of source this is.
+    fn kind(&self) -> RandomKind {
+        RandomKind::Custom
+    }
+
+    fn box_clone(&self) -> Box<dyn RandomSource>;
+}
+
+impl Clone for Box<dyn RandomSource> {
+    fn clone(&self) -> Self {
+        self.box_clone()
+    }
+}
+
+/// A seedable xorshift64* generator.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SeededRandom {
+    state: u64,
+}
+
+impl SeededRandom {
+    pub fn new(seed: u64) -> SeededRandom {
+        // xorshift never leaves zero, so zero is replaced by the default seed
+        let state = if seed == 0 { DEFAULT_SEED } else { seed };
+        SeededRandom { state }
+    }
+}
+
+impl Default for SeededRandom {
+    fn default() -> Self {
+        SeededRandom::new(DEFAULT_SEED)
+    }
+}
+
+impl RandomSource for SeededRandom {
+    fn next_byte(&mut self, _memory: &Memory) -> u8 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        (self.state.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 56) as u8
+    }
+
+    fn seed(&self) -> u64 {
+        self.state
+    }
+
+    fn reseed(&mut self, seed: u64) {
+        *self = SeededRandom::new(seed);
+    }
+
+    fn kind(&self) -> RandomKind {
+        RandomKind::Seeded
+    }
+
+    fn box_clone(&self) -> Box<dyn RandomSource> {
+        Box::new(*self)
+    }
+}
+
+/// Modelled on the random routine of the COSMAC VIP interpreter.
+///
+/// The VIP keeps a 16-bit counter in R9 and mixes it with bytes of the
+/// interpreter's own code, which it keeps in the page at 0x100.  Here each
+/// draw steps the low byte of the counter, uses it as an offset into that
+/// page and adds the byte found there, plus one, to the high byte, which is
+/// returned.  Loading a copy of the VIP interpreter at 0x0 gives numbers
+/// closer to the original, with the page left empty the sequence is a count.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct CosmacVipRandom {
+    counter: u16,
+}
+
+/// The page of memory the VIP routine reads from
+const VIP_RANDOM_PAGE: usize = 0x100;
+
+impl CosmacVipRandom {
+    pub fn new(seed: u16) -> CosmacVipRandom {
+        CosmacVipRandom { counter: seed }
+    }
+}
+
+impl RandomSource for CosmacVipRandom {
+    fn next_byte(&mut self, memory: &Memory) -> u8 {
+        let [high, low] = self.counter.to_be_bytes();
+        let low = low.wrapping_add(1);
+        let address = VIP_RANDOM_PAGE + usize::from(low);
+        let page = if address < memory.size() { memory.read(address as u16) } else { 0x0 };
+        let high = high.wrapping_add(page).wrapping_add(1);
+
+        self.counter = u16::from_be_bytes([high, low]);
+        high
+    }
+
+    fn seed(&self) -> u64 {
+        u64::from(self.counter)
+    }
+
+    fn reseed(&mut self, seed: u64) {
+        self.counter = seed as u16;
+    }
+
+    fn kind(&self) -> RandomKind {
+        RandomKind::CosmacVip
+    }
+
+    fn box_clone(&self) -> Box<dyn RandomSource> {
+        Box::new(*self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn draw(source: &mut dyn RandomSource, count: usize) -> Vec<u8> {
+        let memory = Memory::new();
+        (0..count).map(|_| source.next_byte(&memory)).collect()
+    }
+
+    #[test]
+    fn it_will_repeat_a_sequence_from_the_same_seed() {
+        let first = draw(&mut SeededRandom::new(7), 32);
+        let second = draw(&mut SeededRandom::new(7), 32);
+        let other = draw(&mut SeededRandom::new(8), 32);
+
+        assert_eq!(first, second);
+        assert_ne!(first, other);
+    }
+
+    #[test]
+    fn it_will_continue_a_sequence_from_its_seed() {
+        let mut source = SeededRandom::new(7);
+        draw(&mut source, 10);
+        let mut resumed = SeededRandom::default();
+        resumed.reseed(source.seed());
+
+        assert_eq!(draw(&mut source, 10), draw(&mut resumed, 10));
+    }
+
+    #[test]
+    fn it_will_not_get_stuck_on_a_zero_seed() {
+        let values = draw(&mut SeededRandom::new(0), 16);
+
+        assert!(values.iter().any(|v| *v != values[0]));
+    }
+
+    #[test]
+    fn it_will_create_a_source_of_the_same_kind() {
+        let mut source = CosmacVipRandom::new(0x1234);
+        let mut created = source.kind().create(source.seed()).unwrap();
+
+        assert_eq!(RandomKind::CosmacVip, created.kind());
+        assert_eq!(draw(&mut source, 10), draw(created.as_mut(), 10));
+    }
+
+    #[test]
+    fn it_will_read_the_vip_interpreter_page() {
+        let mut memory = Memory::new();
+        memory.set(0x101, 0x10);
+        memory.set(0x102, 0x20);
+        let mut source = CosmacVipRandom::new(0x0000);
+
+        let values: Vec<u8> = (0..3).map(|_| source.next_byte(&memory)).collect();
+
+        assert_eq!(vec![0x11, 0x32, 0x33], values);
+        assert_eq!(0x3303, source.seed());
+    }
+}
@@ -0,0 +1,275 @@
+//! Records the machine every frame so execution can be wound backwards.
+//!
+//! Snapshots are save states.  Only the newest is kept whole, every older
+//! snapshot is kept as the bytes that differ from the one after it, so a frame
+//! that only changes a few registers costs a few bytes.
+//!
+//! A save state only holds the seed of the random source, so snapshots are
+//! restored with a copy of the source the machine was recorded with.
+//!
+//! # Example:
+//!
+//! ```
+//! # use lib_chip::state::State;
+//! # use lib_chip::memory::Memory;
+//! # use lib_chip::rewind::Rewind;
+//! let mut rewind = Rewind::new(600);
+//! let memory = Memory::new();
+//! let mut state: State = Default::default();
+//! let screen = state.create_buffer();
+//!
+//! for pc in 0..10 {
+//!     state.pc = 0x200 + pc * 2;
+//!     rewind.record(&state, &memory, &screen);
+//! }
+//!
+//! let previous = rewind.step_back().unwrap();
+//! # assert_eq!(0x210, previous.state.pc);
+//! ```
+use std::collections::VecDeque;
+use crate::framebuffer::FrameBuffer;
+use crate::memory::Memory;
+use crate::random::RandomSource;
+use crate::savestate::{self, SaveState};
+use crate::state::State;
+
+/// The number of frames recorded each second
+pub const FRAMES_PER_SECOND: usize = 60;
+
+/// Runs of unchanged bytes shorter than this are stored in the delta rather
+/// than starting a new run
+const MIN_GAP: usize = 8;
+
+/// The changes that turn one snapshot into the snapshot before it
+struct Delta {
+    /// The length of the older snapshot
+    len: usize,
+    /// The bytes of the older snapshot that differ, with their offsets
+    runs: Vec<(usize, Vec<u8>)>,
+}
+
+impl Delta {
+    /// Finds the bytes of `older` that differ from `newer`.
+    fn between(newer: &[u8], older: &[u8]) -> Delta {
+        let mut runs: Vec<(usize, Vec<u8>)> = Vec::new();
+
+        for (offset, byte) in older.iter().enumerate() {
+            if newer.get(offset) == Some(byte) {
+                continue;
+            }
+
+            match runs.last_mut() {
+                Some((start, bytes)) if offset - (*start + bytes.len()) < MIN_GAP => {
+                    bytes.extend_from_slice(&older[*start + bytes.len()..=offset]);
+                },
+                _ => runs.push((offset, vec![*byte]))
+            }
+        }
+
+        Delta { len: older.len(), runs }
+    }
+
+    /// Turns the newer snapshot back into the older one.
+    fn apply(&self, snapshot: &mut Vec<u8>) {
+        snapshot.resize(self.len, 0x0);
+        for (offset, bytes) in self.runs.iter() {
+            snapshot[*offset..*offset + bytes.len()].copy_from_slice(bytes);
+        }
+    }
+
+    fn size(&self) -> usize {
+        self.runs.iter().map(|(_, bytes)| bytes.len()).sum()
+    }
+}
+
+/// A bounded history of snapshots, one per frame.
+pub struct Rewind {
+    capacity: usize,
+    latest: Option<Vec<u8>>,
+    /// Deltas back from the latest snapshot, the newest is last
+    deltas: VecDeque<Delta>,
+    /// The kind of random source recorded, reseeded when a snapshot is restored
+    random: Option<Box<dyn RandomSource>>,
+}
+
+impl Rewind {
+    /// Creates a history that holds up to `capacity` frames before the latest.
+    pub fn new(capacity: usize) -> Rewind {
+        Rewind {
+            capacity,
+            latest: None,
+            deltas: VecDeque::new(),
+            random: None,
+        }
+    }
+
+    /// Records a snapshot of the machine, dropping the oldest if the history is full.
+    pub fn record(&mut self, state: &State, memory: &Memory, screen: &FrameBuffer) {
+        let snapshot = savestate::save(state, memory, screen);
+
+        if let Some(latest) = self.latest.take() {
+            self.deltas.push_back(Delta::between(&snapshot, &latest));
+            if self.deltas.len() > self.capacity {
+                self.deltas.pop_front();
+            }
+        }
+
+        self.latest = Some(snapshot);
+        self.random = Some(state.random.clone());
+    }
+
+    /// Returns the snapshot recorded before the latest, which becomes the latest.
+    ///
+    /// Returns None if there is nothing earlier to go back to.
+    pub fn step_back(&mut self) -> Option<SaveState> {
+        self.back(1)
+    }
+
+    /// Goes back up to `seconds` worth of frames, stopping at the oldest snapshot.
+    ///
+    /// Returns None if there is nothing earlier to go back to.
+    pub fn jump_back(&mut self, seconds: usize) -> Option<SaveState> {
+        self.back(seconds * FRAMES_PER_SECOND)
+    }
+
+    fn back(&mut self, frames: usize) -> Option<SaveState> {
+        if frames == 0 || self.deltas.is_empty() {
+            return None;
+        }
+
+        let latest = self.latest.as_mut()?;
+        for _ in 0..frames {
+            match self.deltas.pop_back() {
+                Some(delta) => delta.apply(latest),
+                None => break
+            }
+        }
+
+        let random = self.random.clone()?;
+        savestate::load_with_random(latest, random).ok()
+    }
+
+    /// Returns the number of frames that can be stepped back
+    pub fn len(&self) -> usize {
+        self.deltas.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.deltas.is_empty()
+    }
+
+    /// Forgets every snapshot
+    pub fn clear(&mut self) {
+        self.latest = None;
+        self.deltas.clear();
+        self.random = None;
+    }
+
+    /// Returns the number of bytes held by the history
+    pub fn size(&self) -> usize {
+        let latest = self.latest.as_ref().map(|l| l.len()).unwrap_or(0);
+        latest + self.deltas.iter().map(|d| d.size()).sum::<usize>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keypad::Keypad;
+    use crate::random::CosmacVipRandom;
+
+    fn record_frames(rewind: &mut Rewind, frames: u16) {
+        let mut memory = Memory::new();
+        let mut state: State = Default::default();
+        let mut screen = state.create_buffer();
+
+        for frame in 0..frames {
+            state.pc = 0x200 + frame;
+            memory.set(0x300, frame as u8);
+            screen.toggle(u32::from(frame) % 64, 0, 0x1);
+            rewind.record(&state, &memory, &screen);
+        }
+    }
+
+    #[test]
+    fn it_will_step_back_one_frame_at_a_time() {
+        let mut rewind = Rewind::new(10);
+        record_frames(&mut rewind, 5);
+
+        let previous = rewind.step_back().unwrap();
+        assert_eq!(0x203, previous.state.pc);
+        assert_eq!(0x3, previous.memory.read(0x300));
+        assert_eq!(vec![0x1, 0x1, 0x1, 0x1, 0x0], previous.screen.to_bytes()[..5].to_vec());
+
+        let previous = rewind.step_back().unwrap();
+        assert_eq!(0x202, previous.state.pc);
+        assert_eq!(2, rewind.len());
+    }
+
+    #[test]
+    fn it_will_jump_back_seconds() {
+        let mut rewind = Rewind::new(600);
+        record_frames(&mut rewind, 200);
+
+        let previous = rewind.jump_back(2).unwrap();
+
+        assert_eq!(0x200 + 199 - 120, previous.state.pc);
+    }
+
+    #[test]
+    fn it_will_stop_at_the_oldest_frame() {
+        let mut rewind = Rewind::new(10);
+        record_frames(&mut rewind, 50);
+
+        let previous = rewind.jump_back(1).unwrap();
+
+        assert_eq!(0x200 + 39, previous.state.pc);
+        assert!(rewind.is_empty());
+        assert!(rewind.step_back().is_none());
+    }
+
+    #[test]
+    fn it_will_store_frames_as_deltas() {
+        let mut rewind = Rewind::new(100);
+        record_frames(&mut rewind, 1);
+        let single = rewind.size();
+
+        record_frames(&mut rewind, 100);
+
+        assert!(rewind.size() < single * 2);
+    }
+
+    #[test]
+    fn it_will_restore_the_random_source_the_machine_used() {
+        let mut memory = Memory::new();
+        memory.set_range(0x200, &[
+            0xC0, 0xFF, // RND V0, 0xFF
+            0xC1, 0xFF, // RND V1, 0xFF
+            0x80, 0x13, // XOR V0, V1
+            0xC2, 0x0F, // RND V2, 0x0F
+            0x12, 0x00, // JP 0x200
+        ]);
+        let mut state = State { random: Box::new(CosmacVipRandom::new(0x1234)), ..Default::default() };
+        let mut screen = state.create_buffer();
+        let mut keypad = Keypad::new();
+        let mut rewind = Rewind::new(10);
+        let mut run_frame = |state: &mut State, memory: &mut Memory, screen: &mut FrameBuffer| {
+            for _ in 0..20 {
+                state.execute(memory, &mut keypad, screen).unwrap();
+            }
+        };
+
+        let mut straight = Vec::new();
+        for _ in 0..10 {
+            run_frame(&mut state, &mut memory, &mut screen);
+            rewind.record(&state, &memory, &screen);
+            straight.push(savestate::save(&state, &memory, &screen));
+        }
+
+        let mut rewound = rewind.jump_back(1).unwrap();
+        for expected in straight.iter().skip(1) {
+            run_frame(&mut rewound.state, &mut rewound.memory, &mut rewound.screen);
+            assert!(*expected == savestate::save(&rewound.state, &rewound.memory, &rewound.screen));
+        }
+    }
+}
@@ -1,7 +1,31 @@
 //! Represents a rom file in memory
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
 use std::fs::File;
 use std::io::prelude::*;
+use crate::memory::Memory;
 
+/// The address roms are loaded at and execution starts from
+pub const PROGRAM_START: u16 = 0x200;
+
+/// Raised when a rom is too large to load into memory.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct RomTooLarge {
+    /// The size of the rom
+    pub size: usize,
+    /// The space between `PROGRAM_START` and the end of memory
+    pub available: usize,
+}
+
+impl Display for RomTooLarge {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "rom of {} bytes does not fit in the {} bytes of program memory", self.size, self.available)
+    }
+}
+
+impl Error for RomTooLarge {}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Rom {
     data: Vec<u8>
 }
@@ -38,6 +62,32 @@ impl Rom {
     pub fn read_all(&self) -> &[u8] {
         &self.data[..]
     }
+
+    /// Copies the rom into memory at `PROGRAM_START`.
+    /// 
+    /// Fails without changing memory if the rom runs past the end of memory.
+    /// 
+    /// Example:
+    /// 
+    /// ```
+    /// # use lib_chip::rom::Rom;
+    /// # use lib_chip::memory::Memory;
+    /// let rom = Rom::from_memory(vec![0x00, 0xE0]);
+    /// let mut memory = Memory::new();
+    /// rom.load_into(&mut memory)?;
+    /// # assert_eq!(0xE0, memory.read(0x201));
+    /// # Ok::<(), lib_chip::rom::RomTooLarge>(())
+    /// ```
+    pub fn load_into(&self, memory: &mut Memory) -> Result<(), RomTooLarge> {
+        let start = usize::from(PROGRAM_START);
+        let available = memory.size().saturating_sub(start);
+        if self.data.len() > available {
+            return Err(RomTooLarge { size: self.data.len(), available });
+        }
+
+        memory.set_range(start, &self.data);
+        Ok(())
+    }
 }
 
 fn load_rom_data(file: &str) -> Result<Vec<u8>, std::io::Error>  {
@@ -45,4 +95,32 @@ fn load_rom_data(file: &str) -> Result<Vec<u8>, std::io::Error>  {
     let mut f = File::open(file)?;
     f.read_to_end(&mut buffer)?;
     Ok(buffer)
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::{MEMORY_SIZE, XO_CHIP_MEMORY_SIZE};
+
+    #[test]
+    fn it_will_load_a_rom_that_fills_memory() {
+        let rom = Rom::from_memory(vec![0xAB; MEMORY_SIZE - 0x200]);
+        let mut memory = Memory::new();
+
+        rom.load_into(&mut memory).unwrap();
+
+        assert_eq!(0xAB, memory.read(0xFFF));
+    }
+
+    #[test]
+    fn it_will_reject_a_rom_past_the_end_of_memory() {
+        let rom = Rom::from_memory(vec![0xAB; MEMORY_SIZE - 0x1FF]);
+        let mut memory = Memory::new();
+
+        let err = rom.load_into(&mut memory).unwrap_err();
+
+        assert_eq!(RomTooLarge { size: 0xE01, available: 0xE00 }, err);
+        assert_eq!(0x0, memory.read(0x200));
+        assert!(rom.load_into(&mut Memory::with_size(XO_CHIP_MEMORY_SIZE)).is_ok());
+    }
+}
@@ -0,0 +1,549 @@
+//! Saves and restores a running machine in a versioned binary format.
+//!
+//! A save state holds every field of `State`, the whole of `Memory` and the
+//! pixels of the `FrameBuffer`.  Values are stored big endian after a 4 byte magic number
+//! and a 16-bit format version.
+//!
+//! The random source is saved as its kind and seed, so a restored machine
+//! draws the same numbers the saved one would have.
+//!
+//! # Example:
+//!
+//! ```
+//! # use lib_chip::state::State;
+//! # use lib_chip::memory::Memory;
+//! # use lib_chip::savestate;
+//! let state = State { pc: 0x204, ..Default::default() };
+//! let memory = Memory::new();
+//! let screen = state.create_buffer();
+//!
+//! let data = savestate::save(&state, &memory, &screen);
+//! let restored = savestate::load(&data)?;
+//! # assert_eq!(0x204, restored.state.pc);
+//! # Ok::<(), savestate::SaveStateError>(())
+//! ```
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use crate::framebuffer::FrameBuffer;
+use crate::memory::{Memory, XO_CHIP_MEMORY_SIZE};
+use crate::opcode::OpCode;
+use crate::opcode::encoder::encode_bytes;
+use crate::opcode::parser::{is_long_opcode, parse_long_opcode, parse_opcode};
+use crate::random::{RandomKind, RandomSource};
+use crate::rom::PROGRAM_START;
+use crate::state::{Quirks, State, HIRES_HEIGHT, HIRES_WIDTH, LORES_HEIGHT, LORES_WIDTH};
+
+/// Identifies the start of a save state
+pub const MAGIC: [u8; 4] = *b"C8ST";
+/// The version of the format written by `save`
+pub const VERSION: u16 = 1;
+
+/// Describes why a save state could not be loaded.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SaveStateError {
+    /// The data does not start with the save state magic number.
+    InvalidMagic,
+    /// The data was written by a different version of the format.
+    UnsupportedVersion(u16),
+    /// The data ends part way through the save state.
+    Truncated,
+    /// The saved memory is too small to hold the fonts or larger than can be addressed.
+    InvalidMemorySize(usize),
+    /// There are bytes left over after the save state.
+    TrailingData(usize),
+    /// The saved machine could not have been reached by running a program.
+    InvalidState(&'static str),
+    /// The saved random source is not built into the crate, so it must be given to `load_with_random`.
+    CustomRandomSource,
+    /// The random source given to `load_with_random` is not the kind that was saved.
+    RandomSourceMismatch(RandomKind),
+}
+
+impl Display for SaveStateError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            SaveStateError::InvalidMagic => write!(f, "not a save state"),
+            SaveStateError::UnsupportedVersion(v) => {
+                write!(f, "save state version {} is not supported, expected {}", v, VERSION)
+            },
+            SaveStateError::Truncated => write!(f, "save state is truncated"),
+            SaveStateError::InvalidMemorySize(size) => write!(f, "saved memory size {} is invalid", size),
+            SaveStateError::TrailingData(len) => write!(f, "{} unexpected bytes after save state", len),
+            SaveStateError::InvalidState(reason) => write!(f, "save state is invalid: {}", reason),
+            SaveStateError::CustomRandomSource => write!(f, "save state uses a custom random source"),
+            SaveStateError::RandomSourceMismatch(kind) => {
+                write!(f, "save state expects a {:?} random source", kind)
+            }
+        }
+    }
+}
+
+impl Error for SaveStateError {}
+
+/// A machine restored from a save state.
+pub struct SaveState {
+    pub state: State,
+    pub memory: Memory,
+    /// The screen, which is dirty so the frontend redraws all of it
+    pub screen: FrameBuffer,
+}
+
+struct Writer {
+    data: Vec<u8>,
+}
+
+impl Writer {
+    fn u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_be_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_be_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_be_bytes());
+    }
+
+    fn bytes(&mut self, value: &[u8]) {
+        self.data.extend_from_slice(value);
+    }
+
+    fn flags(&mut self, flags: &[bool]) {
+        let packed = flags.iter().enumerate().fold(0u8, |acc, (bit, set)| acc | (u8::from(*set) << bit));
+        self.u8(packed);
+    }
+
+    /// Opcodes are always written as 4 bytes so the long I load fits
+    fn opcode(&mut self, opcode: OpCode) {
+        let mut bytes = encode_bytes(opcode);
+        bytes.resize(4, 0x0);
+        self.bytes(&bytes);
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], SaveStateError> {
+        let end = self.pos.checked_add(len).ok_or(SaveStateError::Truncated)?;
+        let bytes = self.data.get(self.pos..end).ok_or(SaveStateError::Truncated)?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, SaveStateError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, SaveStateError> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, SaveStateError> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn u64(&mut self) -> Result<u64, SaveStateError> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.bytes(8)?);
+        Ok(u64::from_be_bytes(bytes))
+    }
+
+    fn array(&mut self) -> Result<[u8; 16], SaveStateError> {
+        let mut array = [0; 16];
+        array.copy_from_slice(self.bytes(16)?);
+        Ok(array)
+    }
+
+    fn flags(&mut self, count: usize) -> Result<Vec<bool>, SaveStateError> {
+        let packed = self.u8()?;
+        Ok((0..count).map(|bit| packed & (1 << bit) != 0).collect())
+    }
+
+    fn opcode(&mut self) -> Result<OpCode, SaveStateError> {
+        let bytes = self.bytes(4)?;
+        if is_long_opcode(bytes[0], bytes[1]) {
+            Ok(parse_long_opcode(bytes[0], bytes[1], bytes[2], bytes[3]))
+        } else {
+            Ok(parse_opcode(bytes[0], bytes[1]))
+        }
+    }
+}
+
+fn write_state(writer: &mut Writer, state: &State) {
+    state.stack.iter().for_each(|address| writer.u16(*address));
+    writer.bytes(&state.registers);
+    writer.u8(state.delay_timer);
+    writer.u8(state.sound_timer);
+    writer.u16(state.pc);
+    writer.u16(state.stack_pointer);
+    writer.u16(state.i);
+    writer.flags(&[state.run_flag, state.hires, state.vblank]);
+    writer.opcode(state.last_opcode);
+    writer.u8(u8::from(state.opcode.is_some()));
+    writer.opcode(state.opcode.unwrap_or(OpCode::Unknown(0)));
+    writer.u32(state.width);
+    writer.u32(state.height);
+    writer.bytes(&state.rpl);
+    writer.u8(state.plane);
+    writer.bytes(&state.audio_pattern);
+    writer.u8(state.pitch);
+
+    let quirks = state.quirks;
+    writer.flags(&[quirks.shift_uses_vy, quirks.load_store_increments_i, quirks.logic_resets_vf,
+        quirks.jump_uses_vx, quirks.clip_sprites, quirks.display_wait]);
+    writer.u8(random_kind_to_u8(state.random.kind()));
+    writer.u64(state.random.seed());
+    writer.u8(u8::from(state.key_wait.is_some()));
+    writer.u8(state.key_wait.unwrap_or(0));
+}
+
+fn random_kind_to_u8(kind: RandomKind) -> u8 {
+    match kind {
+        RandomKind::Seeded => 0,
+        RandomKind::CosmacVip => 1,
+        RandomKind::Custom => 0xFF
+    }
+}
+
+fn random_kind_from_u8(value: u8) -> Result<RandomKind, SaveStateError> {
+    match value {
+        0 => Ok(RandomKind::Seeded),
+        1 => Ok(RandomKind::CosmacVip),
+        0xFF => Ok(RandomKind::Custom),
+        _ => Err(SaveStateError::InvalidState("random source kind is unknown"))
+    }
+}
+
+fn read_state(reader: &mut Reader, random: Option<Box<dyn RandomSource>>) -> Result<State, SaveStateError> {
+    let mut stack = [0; 16];
+    for address in stack.iter_mut() {
+        *address = reader.u16()?;
+    }
+
+    let registers = reader.array()?;
+    let delay_timer = reader.u8()?;
+    let sound_timer = reader.u8()?;
+    let pc = reader.u16()?;
+    let stack_pointer = reader.u16()?;
+    let i = reader.u16()?;
+    let flags = reader.flags(3)?;
+    let last_opcode = reader.opcode()?;
+    let pending = reader.u8()? != 0;
+    let opcode = reader.opcode()?;
+    let width = reader.u32()?;
+    let height = reader.u32()?;
+    let rpl = reader.array()?;
+    let plane = reader.u8()?;
+    let audio_pattern = reader.array()?;
+    let pitch = reader.u8()?;
+    let quirks = reader.flags(6)?;
+    let kind = random_kind_from_u8(reader.u8()?)?;
+    let seed = reader.u64()?;
+    let random = match random {
+        Some(mut random) if random.kind() == kind => {
+            random.reseed(seed);
+            random
+        },
+        Some(_) => return Err(SaveStateError::RandomSourceMismatch(kind)),
+        None => kind.create(seed).ok_or(SaveStateError::CustomRandomSource)?
+    };
+    let waiting = reader.u8()? != 0;
+    let key = reader.u8()?;
+
+    if usize::from(stack_pointer) > stack.len() {
+        return Err(SaveStateError::InvalidState("stack pointer is past the end of the stack"));
+    }
+    if (width, height) != (LORES_WIDTH, LORES_HEIGHT) && (width, height) != (HIRES_WIDTH, HIRES_HEIGHT) {
+        return Err(SaveStateError::InvalidState("screen is neither low nor high resolution"));
+    }
+    if waiting && key > 0xF {
+        return Err(SaveStateError::InvalidState("waiting on a key that does not exist"));
+    }
+
+    Ok(State {
+        stack,
+        registers,
+        delay_timer,
+        sound_timer,
+        pc,
+        stack_pointer,
+        i,
+        run_flag: flags[0],
+        last_opcode,
+        opcode: if pending { Some(opcode) } else { None },
+        width,
+        height,
+        hires: flags[1],
+        rpl,
+        plane,
+        audio_pattern,
+        pitch,
+        quirks: Quirks {
+            shift_uses_vy: quirks[0],
+            load_store_increments_i: quirks[1],
+            logic_resets_vf: quirks[2],
+            jump_uses_vx: quirks[3],
+            clip_sprites: quirks[4],
+            display_wait: quirks[5]
+        },
+        vblank: flags[2],
+        random,
+        key_wait: if waiting { Some(key) } else { None }
+    })
+}
+
+/// Writes the state, memory and screen to a save state.
+pub fn save(state: &State, memory: &Memory, screen: &FrameBuffer) -> Vec<u8> {
+    let pixels = screen.to_bytes();
+    let mut writer = Writer { data: Vec::with_capacity(memory.size() + pixels.len() + 256) };
+    writer.bytes(&MAGIC);
+    writer.u16(VERSION);
+    write_state(&mut writer, state);
+    writer.u32(memory.size() as u32);
+    writer.bytes(memory.read_all());
+    writer.u32(screen.width());
+    writer.u32(screen.height());
+    writer.bytes(&pixels);
+
+    writer.data
+}
+
+/// Reads a save state written by `save`, rebuilding the kind of random source that was saved.
+///
+/// Fails if the data is not a save state, was written by another version of
+/// the format, is truncated, holds a machine no program could reach or was
+/// saved with a custom random source.
+pub fn load(data: &[u8]) -> Result<SaveState, SaveStateError> {
+    read(data, None)
+}
+
+/// Reads a save state written by `save`, reseeding `random` to restore the random numbers.
+///
+/// The source must be the same kind that was saved, which lets custom sources be restored.
+pub fn load_with_random(data: &[u8], random: Box<dyn RandomSource>) -> Result<SaveState, SaveStateError> {
+    read(data, Some(random))
+}
+
+fn read(data: &[u8], random: Option<Box<dyn RandomSource>>) -> Result<SaveState, SaveStateError> {
+    let mut reader = Reader { data, pos: 0 };
+    if reader.bytes(MAGIC.len()).map_err(|_| SaveStateError::InvalidMagic)? != MAGIC {
+        return Err(SaveStateError::InvalidMagic);
+    }
+
+    let version = reader.u16()?;
+    if version != VERSION {
+        return Err(SaveStateError::UnsupportedVersion(version));
+    }
+
+    let state = read_state(&mut reader, random)?;
+
+    let size = reader.u32()? as usize;
+    if size < usize::from(PROGRAM_START) || size > XO_CHIP_MEMORY_SIZE {
+        return Err(SaveStateError::InvalidMemorySize(size));
+    }
+
+    let mut memory = Memory::with_size(size);
+    memory.set_range(0, reader.bytes(size)?);
+
+    let width = reader.u32()?;
+    let height = reader.u32()?;
+    if (width, height) != (state.width, state.height) {
+        return Err(SaveStateError::InvalidState("screen size does not match the resolution"));
+    }
+    let screen = FrameBuffer::from_pixels(width, height, reader.bytes((width * height) as usize)?);
+
+    let remaining = data.len() - reader.pos;
+    if remaining > 0 {
+        return Err(SaveStateError::TrailingData(remaining));
+    }
+
+    Ok(SaveState { state, memory, screen })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opcode::LoadOp;
+    use crate::random::{CosmacVipRandom, SeededRandom};
+
+    fn sample() -> (State, Memory, FrameBuffer) {
+        let state = State {
+            stack: [0x202; 16],
+            registers: [0x1, 0x2, 0x3, 0x4, 0x5, 0x6, 0x7, 0x8, 0x9, 0xA, 0xB, 0xC, 0xD, 0xE, 0xF, 0x10],
+            delay_timer: 0x20,
+            sound_timer: 0x30,
+            pc: 0x456,
+            stack_pointer: 0x3,
+            i: 0xABCD,
+            last_opcode: OpCode::LD(LoadOp::LDIL(0x1234)),
+            opcode: Some(OpCode::LD(LoadOp::LDKEY(0x5))),
+            width: HIRES_WIDTH,
+            height: HIRES_HEIGHT,
+            hires: true,
+            rpl: [0x42; 16],
+            plane: 0x3,
+            audio_pattern: [0xAA; 16],
+            pitch: 0x70,
+            quirks: Quirks::cosmac_vip(),
+            vblank: true,
+            random: Box::new(SeededRandom::new(0xC0FFEE)),
+            key_wait: Some(0xB),
+            ..Default::default()
+        };
+        let mut memory = Memory::new();
+        memory.set_range(0x200, &[0x12, 0x34, 0x56]);
+        let screen = FrameBuffer::from_pixels(128, 64, &[0x1; 128 * 64]);
+
+        (state, memory, screen)
+    }
+
+    #[test]
+    fn it_will_restore_a_saved_machine() {
+        let (state, memory, screen) = sample();
+
+        let restored = load(&save(&state, &memory, &screen)).unwrap();
+
+        assert_eq!(format!("{:?}", state), format!("{:?}", restored.state));
+        assert_eq!(memory.read_all(), restored.memory.read_all());
+        assert_eq!(screen, restored.screen);
+    }
+
+    #[test]
+    fn it_will_continue_random_numbers() {
+        let (mut state, memory, screen) = sample();
+        state.random.next_byte(&memory);
+
+        let mut restored = load(&save(&state, &memory, &screen)).unwrap();
+
+        let expected: Vec<u8> = (0..8).map(|_| state.random.next_byte(&memory)).collect();
+        let actual: Vec<u8> = (0..8).map(|_| restored.state.random.next_byte(&memory)).collect();
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn it_will_restore_the_kind_of_random_source() {
+        let (mut state, memory, screen) = sample();
+        state.random = Box::new(CosmacVipRandom::new(0x1234));
+
+        let restored = load(&save(&state, &memory, &screen)).unwrap();
+
+        assert_eq!(RandomKind::CosmacVip, restored.state.random.kind());
+        assert_eq!(0x1234, restored.state.random.seed());
+    }
+
+    #[test]
+    fn it_will_restore_other_random_sources() {
+        let (mut state, memory, screen) = sample();
+        state.random = Box::new(CosmacVipRandom::new(0x1234));
+
+        let restored = load_with_random(&save(&state, &memory, &screen), Box::new(CosmacVipRandom::default())).unwrap();
+
+        assert_eq!(0x1234, restored.state.random.seed());
+    }
+
+    #[test]
+    fn it_will_reject_a_random_source_of_another_kind() {
+        let (mut state, memory, screen) = sample();
+        state.random = Box::new(CosmacVipRandom::new(0x1234));
+
+        let err = load_with_random(&save(&state, &memory, &screen), Box::new(SeededRandom::default())).err().unwrap();
+
+        assert_eq!(SaveStateError::RandomSourceMismatch(RandomKind::CosmacVip), err);
+    }
+
+    #[test]
+    fn it_will_reject_other_data() {
+        let err = load(b"PNG").err().unwrap();
+
+        assert_eq!(SaveStateError::InvalidMagic, err);
+    }
+
+    #[test]
+    fn it_will_reject_other_versions() {
+        let (state, memory, screen) = sample();
+        let mut data = save(&state, &memory, &screen);
+        data[5] = 0x9;
+
+        let err = load(&data).err().unwrap();
+
+        assert_eq!(SaveStateError::UnsupportedVersion(0x9), err);
+    }
+
+    #[test]
+    fn it_will_reject_truncated_data() {
+        let (state, memory, screen) = sample();
+        let data = save(&state, &memory, &screen);
+
+        for len in [6, 40, 120, data.len() - 1].iter() {
+            let err = load(&data[..*len]).err().unwrap();
+            assert_eq!(SaveStateError::Truncated, err);
+        }
+    }
+
+    #[test]
+    fn it_will_reject_trailing_data() {
+        let (state, memory, screen) = sample();
+        let mut data = save(&state, &memory, &screen);
+        data.push(0x0);
+
+        let err = load(&data).err().unwrap();
+
+        assert_eq!(SaveStateError::TrailingData(1), err);
+    }
+
+    #[test]
+    fn it_will_reject_a_stack_pointer_past_the_stack() {
+        let (mut state, memory, screen) = sample();
+        state.stack_pointer = 200;
+
+        let err = load(&save(&state, &memory, &screen)).err().unwrap();
+
+        assert!(matches!(err, SaveStateError::InvalidState(_)));
+    }
+
+    #[test]
+    fn it_will_reject_a_resolution_no_program_can_select() {
+        for (width, height) in [(256, 8), (0, 0), (64, 64)].iter() {
+            let (mut state, memory, screen) = sample();
+            state.width = *width;
+            state.height = *height;
+
+            let err = load(&save(&state, &memory, &screen)).err().unwrap();
+
+            assert!(matches!(err, SaveStateError::InvalidState(_)));
+        }
+    }
+
+    #[test]
+    fn it_will_reject_a_screen_of_another_size() {
+        let (state, memory, screen) = sample();
+        let mut data = save(&state, &memory, &screen);
+        let header = data.len() - (HIRES_WIDTH * HIRES_HEIGHT) as usize - 8;
+        data[header..header + 8].copy_from_slice(&[0, 0, 1, 0, 0, 0, 0, 8]);
+
+        let err = load(&data).err().unwrap();
+
+        assert!(matches!(err, SaveStateError::InvalidState(_)));
+    }
+
+    #[test]
+    fn it_will_reject_waiting_on_a_key_that_does_not_exist() {
+        let (mut state, memory, screen) = sample();
+        state.key_wait = Some(0x10);
+
+        let err = load(&save(&state, &memory, &screen)).err().unwrap();
+
+        assert!(matches!(err, SaveStateError::InvalidState(_)));
+    }
+}
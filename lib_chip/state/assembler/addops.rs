@@ -1,53 +1,33 @@
 use super::State;
-use crate::opcode::{AddOp, OpCode};
+use crate::opcode::AddOp;
 
 /// Adds kk to register V[x]
-fn add_to_vx(state: State, vx: u8, kk: u8, pc: u16) -> State {
-    let mut registers = state.registers;
-    let x = registers[vx as usize];
+fn add_to_vx(state: &mut State, vx: u8, kk: u8, pc: u16) {
+    let x = state.registers[vx as usize];
     let (val, _overflowed) = x.overflowing_add(kk);
-    registers[vx as usize] = val;
-
-    State {
-        last_opcode: OpCode::ADD(AddOp::ADD(vx,kk)),
-        registers,
-        pc,
-        ..state
-    }
+    state.registers[vx as usize] = val;
+    state.pc = pc;
 }
 
-fn add_vy_to_vx(state: State, vx: u8, vy: u8, pc: u16) -> State {
-    let mut registers = state.registers;
-    let x = registers[vx as usize];
-    let y = registers[vy as usize];
+fn add_vy_to_vx(state: &mut State, vx: u8, vy: u8, pc: u16) {
+    let x = state.registers[vx as usize];
+    let y = state.registers[vy as usize];
 
     let (result, carry) = x.overflowing_add(y);
 
-    registers[0xF] = if carry { 1 } else { 0 };
-    registers[vx as usize] = result;
-
-    State {
-        last_opcode: OpCode::ADD(AddOp::ADDREG(vx,vy)),
-        pc,
-        registers,
-        ..state
-    }
+    state.registers[vx as usize] = result;
+    state.registers[0xF] = if carry { 1 } else { 0 };
+    state.pc = pc;
 }
 
-fn add_vx_to_i(state: State, vx: u8, pc: u16) -> State {
+fn add_vx_to_i(state: &mut State, vx: u8, pc: u16) {
     let x = state.registers[vx as usize];
-    let i = state.i + u16::from(x);
-
-    State {
-        last_opcode: OpCode::ADD(AddOp::ADDI(vx)),
-        pc,
-        i,
-        ..state
-    }
+    state.i = state.i.wrapping_add(u16::from(x));
+    state.pc = pc;
 }
 
 /// Handles all operands that fall under the ADD category.
-pub fn handle_add_op(state: State, op: AddOp, pc: u16) -> State {
+pub fn handle_add_op(state: &mut State, op: AddOp, pc: u16) {
     match op {
         AddOp::ADD(vx, kk) => add_to_vx(state, vx, kk, pc),
         AddOp::ADDREG(vx, vy) => add_vy_to_vx(state, vx, vy, pc),
@@ -60,6 +40,12 @@ mod tests {
     use super::super::State;
     use super::*;
 
+    /// Runs the handler on the state and returns it
+    fn after_add(mut state: State, op: AddOp, pc: u16) -> State {
+        handle_add_op(&mut state, op, pc);
+        state
+    }
+
     #[test]
     fn it_should_add_kk_to_register_vx() {
         const VX:u8 = 0x3;
@@ -72,7 +58,7 @@ mod tests {
         };
 
 
-        let new_state = handle_add_op(state, AddOp::ADD(VX, 0xA1), 0x200);
+        let new_state = after_add(state, AddOp::ADD(VX, 0xA1), 0x200);
         let registers = new_state.registers;
 
         assert_eq!(registers[VX as usize], 0xB1);
@@ -89,7 +75,7 @@ mod tests {
             ..Default::default()
         };
 
-        let new_state = handle_add_op(state, AddOp::ADD(VX, 0xFF), 0x200);
+        let new_state = after_add(state, AddOp::ADD(VX, 0xFF), 0x200);
         let registers = new_state.registers;
 
         assert_eq!(registers[VX as usize], 0x0F);   
@@ -108,7 +94,7 @@ mod tests {
             ..Default::default()
         };
 
-        let new_state = handle_add_op(state, AddOp::ADDREG(VX,VY), 0x200);
+        let new_state = after_add(state, AddOp::ADDREG(VX,VY), 0x200);
         let registers = new_state.registers;
 
         assert_eq!(registers[VX as usize], 0x25);  
@@ -129,7 +115,7 @@ mod tests {
         };
 
 
-        let new_state = handle_add_op(state, AddOp::ADDREG(VX, VY), 0x200);
+        let new_state = after_add(state, AddOp::ADDREG(VX, VY), 0x200);
         let registers = new_state.registers;
 
         assert_eq!(registers[VX as usize], 0x0F);  
@@ -148,7 +134,7 @@ mod tests {
             ..Default::default()
         };
 
-        let new_state = handle_add_op(state, AddOp::ADDI(VX), 0x200);
+        let new_state = after_add(state, AddOp::ADDI(VX), 0x200);
         assert_eq!(0x25, new_state.i)
     }
 }
\ No newline at end of file
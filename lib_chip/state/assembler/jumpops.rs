@@ -1,18 +1,21 @@
-use crate::opcode::{JumpOp, OpCode};
+use crate::opcode::JumpOp;
 use super::State;
 
-fn handle_jump_from_v0(state: State, nnn: u16) -> State {
-    let v0 = u16::from(state.registers[0x0]);
-    State {
-        last_opcode: OpCode::JP(JumpOp::JPV0(nnn)),
-        pc: nnn+v0,
-        ..state
-    }
+/// Jumps to nnn offset by V0, or by Vx when the `jump_uses_vx` quirk is
+/// enabled, where x is the highest nibble of nnn.
+fn handle_jump_from_v0(state: &mut State, nnn: u16) {
+    let register = if state.quirks.jump_uses_vx {
+        ((nnn >> 8) & 0xF) as usize
+    } else {
+        0x0
+    };
+    let offset = u16::from(state.registers[register]);
+    state.pc = nnn + offset;
 }
 
-pub fn handle_jump_ops(state: State, op: JumpOp) -> State {
+pub fn handle_jump_ops(state: &mut State, op: JumpOp) {
     match op {
-        JumpOp::JP(nnn) => State {pc: nnn, last_opcode: OpCode::JP(JumpOp::JP(nnn)), ..state},
+        JumpOp::JP(nnn) => state.pc = nnn,
         JumpOp::JPV0(nnn) => handle_jump_from_v0(state, nnn)
     }
 }
@@ -22,12 +25,19 @@ mod tests {
     use super::super::State;
     use super::*;
     use crate::opcode::JumpOp;
+    use crate::state::Quirks;
+
+    /// Runs the handler on the state and returns it
+    fn after_jump(mut state: State, op: JumpOp) -> State {
+        handle_jump_ops(&mut state, op);
+        state
+    }
 
     #[test]
     fn it_should_jump_to_stated_location() {
         let state: State = Default::default();
 
-        let new_state = handle_jump_ops(state, JumpOp::JP(0x0FFF));
+        let new_state = after_jump(state, JumpOp::JP(0x0FFF));
 
         assert_eq!(0x0FFF, new_state.pc);
     }
@@ -41,8 +51,24 @@ mod tests {
             ..Default::default()
         };
 
-        let new_state = handle_jump_ops(state, JumpOp::JPV0(0x0FFF));
+        let new_state = after_jump(state, JumpOp::JPV0(0x0FFF));
 
         assert_eq!(0x1010, new_state.pc);
     }
+
+    #[test]
+    fn it_should_jump_to_stated_offset_from_vx_when_quirk_enabled() {
+        let mut registers = [0x0;16];
+        registers[0x0] = 0x11;
+        registers[0x3] = 0x02;
+        let state = State {
+            registers,
+            quirks: Quirks { jump_uses_vx: true, ..Default::default() },
+            ..Default::default()
+        };
+
+        let new_state = after_jump(state, JumpOp::JPV0(0x0312));
+
+        assert_eq!(0x0314, new_state.pc);
+    }
 }
\ No newline at end of file
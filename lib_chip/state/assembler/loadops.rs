@@ -1,171 +1,179 @@
 use super::State;
-use crate::memory::Memory;
+use super::{read_memory, write_memory};
+use super::super::error::Fault;
+use crate::keypad::{Keypad, KEY_COUNT};
+use crate::memory::{Memory, BIG_FONT_ADDRESS};
 use crate::opcode::{OpCode,LoadOp};
 
-fn load_x_from_y(state: State, vx: u8, vy: u8, pc: u16) -> State {
-    let mut registers = state.registers;
-    registers[vx as usize] = registers[vy as usize];
+/// Returns the lowest key pressed and released since the edges were cleared
+fn first_tapped(keypad: &Keypad) -> Option<u8> {
+    (0..KEY_COUNT).find(|key| keypad.was_pressed(*key) && keypad.was_released(*key) && !keypad.is_pressed(*key))
+}
 
-    State {
-        last_opcode: OpCode::LD(LoadOp::LDXY(vx,vy)),
-        registers,
-        pc,
-        ..state
+/// Waits for a key to be pressed and released, as the COSMAC VIP did.
+///
+/// The first key seen held down is remembered, the instruction completes
+/// with that key in Vx once it is released.  A key pressed and released
+/// between two polls completes it straight away.  The edges of the key are
+/// forgotten as they are used, so one press only completes one wait.
+fn handle_load_key(state: &mut State, vx: u8, pc: u16, keypad: &mut Keypad, loadop: LoadOp) {
+    let released = match state.key_wait {
+        Some(key) if !keypad.is_pressed(key) || keypad.was_released(key) => Some(key),
+        Some(_) => None,
+        None => first_tapped(keypad)
+    };
+
+    if let Some(key) = released {
+        keypad.forget_edges(key);
+        state.registers[vx as usize] = key;
+        state.key_wait = None;
+        state.opcode = None;
+        state.pc = pc;
+        return;
     }
-}
 
-fn load_delay_timer(state: State, vx: u8, pc: u16) -> State {
-    let mut registers = state.registers;
-    registers[vx as usize] = state.delay_timer;
-    State {
-        last_opcode: OpCode::LD(LoadOp::LDVXDT(vx)),
-        registers,
-        pc,
-        ..state
+    if state.key_wait.is_none() {
+        state.key_wait = keypad.first_pressed();
+        if let Some(key) = state.key_wait {
+            // a release from before the key was seen held must not complete the wait
+            keypad.forget_edges(key);
+        }
     }
+    state.opcode = Some(OpCode::LD(loadop));
 }
 
-fn set_i(state: State, pc: u16, kk: u16) -> State {
-    State {
-        last_opcode: OpCode::LD(LoadOp::LDI(kk)),
-        i: kk,
-        pc,
-        ..state
-    }
+const BYTES_PER_SPRITE: u16 = 5;
+
+fn load_sprite(state: &mut State, vx: u8) {
+    let sprite = u16::from(state.registers[vx as usize]);
+    state.i = BYTES_PER_SPRITE * sprite;
 }
 
-fn set_delay_timer(state: State, vx: u8, pc: u16) -> State {
-    let delay = state.registers[vx as usize];
-    State {
-        delay_timer: delay,
-        last_opcode: OpCode::LD(LoadOp::LDDTVX(vx)),
-        pc,
-        ..state
-    }
+const BYTES_PER_BIG_SPRITE: u16 = 10;
+
+fn load_big_sprite(state: &mut State, vx: u8) {
+    let sprite = u16::from(state.registers[vx as usize] & 0xF);
+    state.i = BIG_FONT_ADDRESS + BYTES_PER_BIG_SPRITE * sprite;
 }
 
-fn handle_load_key(state: State, vx: u8, pc: u16, keycode: &[u8], loadop: LoadOp) -> State {
-    let mut registers = state.registers;
-    let mut pc = pc-2;
-    let next_opcode = match keycode.len() {
-      0 => Some(OpCode::LD(loadop)),
-      x => {
-          registers[vx as usize] = keycode[x-1];
-          pc += 2;
-          None
-      }  
-    };
+/// Stores V0 to Vx in the RPL user flags, registers beyond the
+/// number of flags are ignored.
+fn store_flags(state: &mut State, vx: u8) {
+    let count = usize::from(vx) + 1;
+    for (flag, val) in state.rpl.iter_mut().zip(state.registers.iter()).take(count) {
+        *flag = *val;
+    }
+}
 
-    State {
-        opcode: next_opcode,
-        last_opcode: OpCode::LD(LoadOp::LDKEY(vx)),
-        pc,
-        registers,
-        ..state
+/// Reads V0 to Vx from the RPL user flags, registers beyond the
+/// number of flags are left unchanged.
+fn read_flags(state: &mut State, vx: u8) {
+    let count = usize::from(vx) + 1;
+    for (val, flag) in state.registers.iter_mut().zip(state.rpl.iter()).take(count) {
+        *val = *flag;
     }
 }
 
-fn set_sound_timer(state: State, vx: u8, pc: u16) -> State {
-    let sound_timer = state.registers[vx as usize];
-    State {
-        last_opcode: OpCode::LD(LoadOp::LDSTVX(vx)),
-        pc,
-        sound_timer,
-        ..state
+/// Returns the registers from Vx to Vy, in reverse order if x is greater than y
+fn register_range(vx: u8, vy: u8) -> Vec<usize> {
+    if vx <= vy {
+        (usize::from(vx)..=usize::from(vy)).collect()
+    } else {
+        (usize::from(vy)..=usize::from(vx)).rev().collect()
     }
 }
 
-const BYTES_PER_SPRITE: u16 = 5;
+fn store_register_range(state: &State, memory: &mut Memory, vx: u8, vy: u8) -> Result<(), Fault> {
+    let values: Vec<u8> = register_range(vx, vy).into_iter().map(|v| state.registers[v]).collect();
+    write_memory(memory, usize::from(state.i), &values)
+}
 
-fn load_sprite(state: State, vx: u8, pc: u16) -> State {
-    let sprite = u16::from(state.registers[vx as usize]);
-    let i = BYTES_PER_SPRITE * sprite;
-    State {
-        last_opcode: OpCode::LD(LoadOp::LDF(vx)),
-        pc,
-        i,
-        ..state
+/// Registers are only changed once every byte has been read
+fn read_register_range(state: &mut State, memory: &Memory, vx: u8, vy: u8) -> Result<(), Fault> {
+    let mut registers = state.registers;
+    let i = usize::from(state.i);
+    for (offset, v) in register_range(vx, vy).into_iter().enumerate() {
+        registers[v] = read_memory(memory, i + offset)?;
     }
+
+    state.registers = registers;
+    Ok(())
 }
 
-fn handle_bcd_representation(state: State, memory: &mut Memory, pc: u16, vx: u8) -> State {
+fn handle_bcd_representation(state: &State, memory: &mut Memory, vx: u8) -> Result<(), Fault> {
     let val = state.registers[vx as usize];
+    let hundreds = val / 100;
+    let tens = val / 10 % 10;
     let units = val % 10;
-    let tens = (val - units) % 100;
-    let hundreds = val - tens - units;
-    let i = state.i;
-
-    memory.set(i as usize, hundreds);
-    memory.set((i + 1) as usize, tens);
-    memory.set((i + 2) as usize, units);
 
-    State {
-        last_opcode: OpCode::LD(LoadOp::LDB(vx)),
-        pc,
-        ..state
-    }
+    write_memory(memory, usize::from(state.i), &[hundreds, tens, units])
 }
 
-fn load_from_registers(state: State, memory: &mut Memory, vx: u8, pc: u16) -> State {
-    let registers = state.registers;
-    let i = state.i;
-    for v in 0..=u16::from(vx) {
-        let val = registers[v as usize];
-        let addr = i+v;
-        memory.set(addr as usize, val);
-    }
-    
-    State {
-        last_opcode: OpCode::LD(LoadOp::LDIV0X(vx)),
-        pc,
-        ..state
+/// Returns the value of I after registers V0 to Vx are loaded or stored.
+/// 
+/// I is only moved past the registers when the quirk is enabled.
+fn register_range_end(state: &State, vx: u8) -> u16 {
+    if state.quirks.load_store_increments_i {
+        state.i.wrapping_add(u16::from(vx) + 1)
+    } else {
+        state.i
     }
 }
 
-fn set_register(state: State, pc: u16, vx: u8, kk: u8) -> State {
-    let mut registers = state.registers;
-    registers[vx as usize] = kk;
-    State {
-        last_opcode: OpCode::LD(LoadOp::LD(vx, kk)),
-        registers,
-        pc,
-        ..state
-    }
+fn load_from_registers(state: &mut State, memory: &mut Memory, vx: u8) -> Result<(), Fault> {
+    write_memory(memory, usize::from(state.i), &state.registers[..=usize::from(vx)])?;
+
+    state.i = register_range_end(state, vx);
+    Ok(())
 }
 
-fn set_registers(state: State, pc: u16, vx: u8, memory: &Memory) -> State {
+/// Registers are only changed once every byte has been read
+fn set_registers(state: &mut State, vx: u8, memory: &Memory) -> Result<(), Fault> {
     let mut registers = state.registers;
-    let i = state.i;
+    let i = usize::from(state.i);
 
-    for v in 0..=u16::from(vx) {
-        let addr = i+v;
-        let val = memory.read(addr);
-        registers[v as usize] = val;
+    for (v, register) in registers.iter_mut().enumerate().take(usize::from(vx) + 1) {
+        *register = read_memory(memory, i + v)?;
     }
 
-    State {
-        registers,
-        pc,
-        last_opcode: OpCode::LD(LoadOp::LDV0XI(vx)),
-        ..state
-    }
+    state.registers = registers;
+    state.i = register_range_end(state, vx);
+    Ok(())
 }
 
-pub fn handle_load_operands(state: State, load_op: LoadOp, pc: u16,
-    memory: &mut Memory, keycode: &[u8]) -> State {
+/// Handles all operands that fall under the LD category.
+/// 
+/// Faults if an operand reads or writes memory out of range, leaving the
+/// state unchanged.
+pub fn handle_load_operands(state: &mut State, load_op: LoadOp, pc: u16,
+    memory: &mut Memory, keypad: &mut Keypad) -> Result<(), Fault> {
     match load_op {
-        LoadOp::LD(vx, kk) => set_register(state, pc, vx, kk),
-        LoadOp::LDV0XI(vx) => set_registers(state, pc, vx, memory),
-        LoadOp::LDIV0X(vx) => load_from_registers(state, memory, vx, pc),
-        LoadOp::LDB(vx) => handle_bcd_representation(state, memory, pc, vx),
-        LoadOp::LDF(vx) => load_sprite(state, vx, pc),
-        LoadOp::LDSTVX(vx) => set_sound_timer(state, vx, pc),
-        LoadOp::LDKEY(vx) => handle_load_key(state, vx, pc, keycode, load_op),
-        LoadOp::LDDTVX(vx) => set_delay_timer(state, vx, pc),
-        LoadOp::LDI(kk) => set_i(state, pc, kk),
-        LoadOp::LDVXDT(vx) => load_delay_timer(state, vx, pc),
-        LoadOp::LDXY(vx, vy) => load_x_from_y(state, vx, vy, pc)
+        LoadOp::LD(vx, kk) => state.registers[vx as usize] = kk,
+        LoadOp::LDV0XI(vx) => set_registers(state, vx, memory)?,
+        LoadOp::LDIV0X(vx) => load_from_registers(state, memory, vx)?,
+        LoadOp::LDB(vx) => handle_bcd_representation(state, memory, vx)?,
+        LoadOp::LDF(vx) => load_sprite(state, vx),
+        LoadOp::LDSTVX(vx) => state.sound_timer = state.registers[vx as usize],
+        LoadOp::LDKEY(vx) => {
+            // the program counter only moves once the key is released
+            handle_load_key(state, vx, pc, keypad, load_op);
+            return Ok(());
+        },
+        LoadOp::LDDTVX(vx) => state.delay_timer = state.registers[vx as usize],
+        LoadOp::LDI(nnn) => state.i = nnn,
+        LoadOp::LDVXDT(vx) => state.registers[vx as usize] = state.delay_timer,
+        LoadOp::LDXY(vx, vy) => state.registers[vx as usize] = state.registers[vy as usize],
+        LoadOp::LDHF(vx) => load_big_sprite(state, vx),
+        LoadOp::LDRVX(vx) => store_flags(state, vx),
+        LoadOp::LDVXR(vx) => read_flags(state, vx),
+        LoadOp::LDIL(nnnn) => state.i = nnnn,
+        LoadOp::LDIVXY(vx, vy) => store_register_range(state, memory, vx, vy)?,
+        LoadOp::LDVXYI(vx, vy) => read_register_range(state, memory, vx, vy)?,
+        LoadOp::LDPITCH(vx) => state.pitch = state.registers[vx as usize]
     }
+
+    state.pc = pc;
+    Ok(())
 }
 
 #[cfg(test)]
@@ -174,6 +182,13 @@ mod tests {
     use super::*;
     use crate::opcode::{OpCode, LoadOp};
     use crate::memory::Memory;
+    use crate::state::Quirks;
+
+    /// Runs the handler on the state and returns it
+    fn after_load(mut state: State, load_op: LoadOp, pc: u16, memory: &mut Memory, keypad: &mut Keypad) -> Result<State, Fault> {
+        handle_load_operands(&mut state, load_op, pc, memory, keypad)?;
+        Ok(state)
+    }
 
     #[test]
     fn it_should_load_value_into_vx() {
@@ -182,7 +197,7 @@ mod tests {
         const VX:u8 = 0x4;
         const KK:u8 = 0xFF;
 
-        let new_state = handle_load_operands(state, LoadOp::LD(VX,KK), 0x299, &mut memory, &Vec::new()[..]);
+        let new_state = after_load(state, LoadOp::LD(VX,KK), 0x299, &mut memory, &mut Keypad::new()).unwrap();
         let actual = new_state.registers[VX as usize];
 
         assert_eq!(KK, actual);
@@ -201,7 +216,7 @@ mod tests {
             ..Default::default()
         };
 
-        let new_state = handle_load_operands(state, LoadOp::LDV0XI(VX), 0x299, &mut memory, &Vec::new()[..]);
+        let new_state = after_load(state, LoadOp::LDV0XI(VX), 0x299, &mut memory, &mut Keypad::new()).unwrap();
         let registers = new_state.registers;
         let slice = &registers[..4];
         assert_eq!(mem, slice);
@@ -224,7 +239,7 @@ mod tests {
             ..Default::default()
         };
 
-        let new_state = handle_load_operands(state, LoadOp::LDIV0X(VX), 0x200, &mut memory, &Vec::new()[..]);
+        let new_state = after_load(state, LoadOp::LDIV0X(VX), 0x200, &mut memory, &mut Keypad::new()).unwrap();
         let registers = new_state.registers;
         let reg_slice = &registers[0..5];
         let mem = [memory.read(I), memory.read(I+1), memory.read(I+2),
@@ -247,13 +262,13 @@ mod tests {
             ..Default::default()
         };
 
-        let new_state = handle_load_operands(state, LoadOp::LDB(VX), 0x200, &mut memory, &Vec::new()[..]);
+        let new_state = after_load(state, LoadOp::LDB(VX), 0x200, &mut memory, &mut Keypad::new()).unwrap();
 
         let i = new_state.i;
         let (h,t,u) = (memory.read(i), memory.read(i+1), memory.read(i+2));
 
-        assert_eq!(200, h);
-        assert_eq!(50, t);
+        assert_eq!(2, h);
+        assert_eq!(5, t);
         assert_eq!(4, u);
     }
 
@@ -272,11 +287,102 @@ mod tests {
             ..Default::default()
         };
 
-        let new_state = handle_load_operands(state, LoadOp::LDF(VX), 0x200, &mut memory, &Vec::new()[..]);
+        let new_state = after_load(state, LoadOp::LDF(VX), 0x200, &mut memory, &mut Keypad::new()).unwrap();
 
         assert_eq!(u16::from(DATA) * 5, new_state.i);
     }
 
+    #[test]
+    fn it_should_load_big_sprite_into_i() {
+        let mut registers = [0x0;16];
+        const VX:u8 = 0xE;
+        registers[VX as usize] = 0x3;
+        let mut memory = Memory::new();
+
+        let state = State {
+            registers,
+            ..Default::default()
+        };
+
+        let new_state = after_load(state, LoadOp::LDHF(VX), 0x200, &mut memory, &mut Keypad::new()).unwrap();
+
+        assert_eq!(BIG_FONT_ADDRESS + 30, new_state.i);
+    }
+
+    #[test]
+    fn it_should_store_and_read_rpl_flags() {
+        let mut registers = [0x0;16];
+        registers[0x0] = 0x12;
+        registers[0x1] = 0x34;
+        registers[0x2] = 0x56;
+        let mut memory = Memory::new();
+
+        let state = State {
+            registers,
+            ..Default::default()
+        };
+
+        let stored = after_load(state, LoadOp::LDRVX(0x1), 0x200, &mut memory, &mut Keypad::new()).unwrap();
+        assert_eq!([0x12, 0x34, 0x0, 0x0], stored.rpl[..4]);
+
+        let cleared = State { registers: [0x0;16], ..stored };
+        let loaded = after_load(cleared, LoadOp::LDVXR(0xF), 0x200, &mut memory, &mut Keypad::new()).unwrap();
+        assert_eq!(0x12, loaded.registers[0x0]);
+        assert_eq!(0x34, loaded.registers[0x1]);
+        assert_eq!(0x0, loaded.registers[0x2]);
+    }
+
+    #[test]
+    fn it_should_load_long_address_into_i() {
+        let state:State = Default::default();
+        let mut memory = Memory::new();
+
+        let new_state = after_load(state, LoadOp::LDIL(0xBEEF), 0x204, &mut memory, &mut Keypad::new()).unwrap();
+
+        assert_eq!(0xBEEF, new_state.i);
+        assert_eq!(0x204, new_state.pc);
+    }
+
+    #[test]
+    fn it_should_store_and_read_register_ranges() {
+        let mut registers = [0x0;16];
+        registers[0x2] = 0x1;
+        registers[0x3] = 0x2;
+        registers[0x4] = 0x3;
+        const I:u16 = 0x300;
+        let mut memory = Memory::new();
+
+        let state = State {
+            i: I,
+            registers,
+            ..Default::default()
+        };
+
+        let stored = after_load(state, LoadOp::LDIVXY(0x4, 0x2), 0x200, &mut memory, &mut Keypad::new()).unwrap();
+        assert_eq!([0x3, 0x2, 0x1], [memory.read(I), memory.read(I+1), memory.read(I+2)]);
+        assert_eq!(I, stored.i);
+
+        let loaded = after_load(stored, LoadOp::LDVXYI(0x7, 0x9), 0x200, &mut memory, &mut Keypad::new()).unwrap();
+        assert_eq!([0x3, 0x2, 0x1], loaded.registers[0x7..=0x9]);
+    }
+
+    #[test]
+    fn it_should_set_the_pitch() {
+        let mut registers = [0x0;16];
+        const VX:u8 = 0x3;
+        registers[VX as usize] = 0x70;
+        let mut memory = Memory::new();
+
+        let state = State {
+            registers,
+            ..Default::default()
+        };
+
+        let new_state = after_load(state, LoadOp::LDPITCH(VX), 0x200, &mut memory, &mut Keypad::new()).unwrap();
+
+        assert_eq!(0x70, new_state.pitch);
+    }
+
     #[test]
     fn it_should_set_the_sound_timer() {
         let mut registers = [0x0;16];
@@ -290,7 +396,7 @@ mod tests {
             ..Default::default()
         };
 
-        let new_state = handle_load_operands(state, LoadOp::LDSTVX(VX), 0x200, &mut memory, &Vec::new()[..]);
+        let new_state = after_load(state, LoadOp::LDSTVX(VX), 0x200, &mut memory, &mut Keypad::new()).unwrap();
         assert_eq!(0x12, new_state.sound_timer);
     }
 
@@ -306,7 +412,7 @@ mod tests {
             ..Default::default()
         };
 
-        let new_state = handle_load_operands(state, LoadOp::LDKEY(VX), 0x202, &mut memory, &Vec::new()[..]);
+        let new_state = after_load(state, LoadOp::LDKEY(VX), 0x202, &mut memory, &mut Keypad::new()).unwrap();
 
         assert_eq!(0x200, new_state.pc);
         assert_eq!(Some(OpCode::LD(LoadOp::LDKEY(VX))), new_state.opcode);
@@ -316,7 +422,7 @@ mod tests {
     fn when_key_pressed_should_progress() {
         let registers = [0x0;16];
         const VX:u8=0x01;
-        const KEY:u8 = 0x12;
+        const KEY:u8 = 0xC;
 
         let mut memory = Memory::new();
 
@@ -325,14 +431,86 @@ mod tests {
             ..Default::default()
         };
 
-        let new_state = handle_load_operands(state, LoadOp::LDKEY(VX), 0x200, &mut memory, &vec![KEY][..]);
+        let pressed = after_load(state, LoadOp::LDKEY(VX), 0x202, &mut memory, &mut Keypad::with_keys(&[KEY])).unwrap();
+        assert_eq!(0x200, pressed.pc);
+        assert_eq!(Some(KEY), pressed.key_wait);
 
-        assert_eq!(None, new_state.opcode);
-        let registers = new_state.registers;
+        let released = after_load(pressed, LoadOp::LDKEY(VX), 0x202, &mut memory, &mut Keypad::new()).unwrap();
+
+        assert_eq!(None, released.opcode);
+        assert_eq!(None, released.key_wait);
+        assert_eq!(0x202, released.pc);
+        let registers = released.registers;
 
         assert_eq!(KEY, registers[VX as usize]);
     }
 
+    #[test]
+    fn when_key_pressed_and_released_between_polls_should_progress() {
+        const VX:u8 = 0x01;
+        let mut memory = Memory::new();
+        let mut keypad = Keypad::new();
+        keypad.press(0x9);
+        keypad.release(0x9);
+
+        let tapped = after_load(Default::default(), LoadOp::LDKEY(VX), 0x202, &mut memory, &mut keypad).unwrap();
+
+        assert_eq!(0x202, tapped.pc);
+        assert_eq!(0x9, tapped.registers[VX as usize]);
+        assert_eq!(None, tapped.opcode);
+
+        let next = after_load(tapped, LoadOp::LDKEY(VX), 0x204, &mut memory, &mut keypad).unwrap();
+
+        assert_eq!(0x202, next.pc);
+        assert_eq!(Some(OpCode::LD(LoadOp::LDKEY(VX))), next.opcode);
+    }
+
+    #[test]
+    fn when_held_key_released_and_pressed_between_polls_should_progress() {
+        const VX:u8 = 0x01;
+        let mut memory = Memory::new();
+        let mut keypad = Keypad::with_keys(&[0x4]);
+
+        let held = after_load(Default::default(), LoadOp::LDKEY(VX), 0x202, &mut memory, &mut keypad).unwrap();
+        keypad.release(0x4);
+        keypad.press(0x4);
+        let released = after_load(held, LoadOp::LDKEY(VX), 0x202, &mut memory, &mut keypad).unwrap();
+
+        assert_eq!(0x202, released.pc);
+        assert_eq!(0x4, released.registers[VX as usize]);
+    }
+
+    #[test]
+    fn when_key_released_before_it_was_seen_held_should_wait() {
+        const VX:u8 = 0x01;
+        let mut memory = Memory::new();
+        let mut keypad = Keypad::with_keys(&[0x4]);
+        keypad.release(0x4);
+        keypad.press(0x4);
+
+        let held = after_load(Default::default(), LoadOp::LDKEY(VX), 0x202, &mut memory, &mut keypad).unwrap();
+        let still_held = after_load(held, LoadOp::LDKEY(VX), 0x202, &mut memory, &mut keypad).unwrap();
+
+        assert_eq!(0x200, still_held.pc);
+        assert_eq!(Some(0x4), still_held.key_wait);
+    }
+
+    #[test]
+    fn when_other_keys_change_should_wait_for_the_first_key() {
+        const VX:u8=0x01;
+        let mut memory = Memory::new();
+        let state: State = Default::default();
+
+        let state = after_load(state, LoadOp::LDKEY(VX), 0x202, &mut memory, &mut Keypad::with_keys(&[0x3, 0x7])).unwrap();
+        let state = after_load(state, LoadOp::LDKEY(VX), 0x202, &mut memory, &mut Keypad::with_keys(&[0x3])).unwrap();
+        assert_eq!(0x200, state.pc);
+
+        let state = after_load(state, LoadOp::LDKEY(VX), 0x202, &mut memory, &mut Keypad::with_keys(&[0x9])).unwrap();
+
+        assert_eq!(0x202, state.pc);
+        assert_eq!(0x3, state.registers[VX as usize]);
+    }
+
     #[test]
     fn it_should_load_delay_timer_in_vx() {
         const VX:u8 = 0x04;
@@ -346,7 +524,7 @@ mod tests {
             ..Default::default()
         };
 
-        let new_state = handle_load_operands(state, LoadOp::LDVXDT(VX), 0x200, &mut memory, &Vec::new()[..]);
+        let new_state = after_load(state, LoadOp::LDVXDT(VX), 0x200, &mut memory, &mut Keypad::new()).unwrap();
 
         assert_eq!(0xFF, new_state.registers[VX as usize]);
     }
@@ -364,8 +542,57 @@ mod tests {
 
         let state = State { registers, ..Default::default()};
 
-        let new_state = handle_load_operands(state, LoadOp::LDXY(VX, VY), 0x200, &mut memory, &Vec::new()[..]);
+        let new_state = after_load(state, LoadOp::LDXY(VX, VY), 0x200, &mut memory, &mut Keypad::new()).unwrap();
 
         assert_eq!(0xAE, new_state.registers[VX as usize]);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn it_should_advance_i_past_registers_when_quirk_enabled() {
+        const VX:u8 = 0x3;
+        const I:u16 = 0x300;
+        let mut memory = Memory::new();
+
+        let state = State {
+            i: I,
+            quirks: Quirks { load_store_increments_i: true, ..Default::default() },
+            ..Default::default()
+        };
+
+        let stored = after_load(state, LoadOp::LDIV0X(VX), 0x200, &mut memory, &mut Keypad::new()).unwrap();
+        assert_eq!(I + 4, stored.i);
+
+        let loaded = after_load(stored, LoadOp::LDV0XI(VX), 0x200, &mut memory, &mut Keypad::new()).unwrap();
+        assert_eq!(I + 8, loaded.i);
+    }
+
+    #[test]
+    fn it_should_not_read_registers_past_the_end_of_memory() {
+        const VX:u8 = 0x3;
+        let mut memory = Memory::new();
+
+        let state = State { i: 0xFFE, ..Default::default() };
+
+        let result = after_load(state, LoadOp::LDV0XI(VX), 0x200, &mut memory, &mut Keypad::new());
+
+        assert_eq!(Fault::MemoryOutOfRange(0x1000), result.unwrap_err());
+    }
+
+    #[test]
+    fn it_should_not_write_any_memory_when_a_store_runs_past_the_end() {
+        let stores = [LoadOp::LDIV0X(0x3), LoadOp::LDB(0x0), LoadOp::LDIVXY(0x0, 0x3)];
+
+        for load_op in stores.iter() {
+            let mut memory = Memory::new();
+            memory.set_range(0xFFC, &[0x62, 0x00]);
+            memory.cache_decoded(0xFFC, 0x6200, OpCode::LD(LoadOp::LD(0x2, 0x00)));
+            let state = State { i: 0xFFE, registers: [0xFF; 16], ..Default::default() };
+
+            let result = after_load(state, *load_op, 0x202, &mut memory, &mut Keypad::new());
+
+            assert_eq!(Fault::MemoryOutOfRange(0x1000), result.unwrap_err());
+            assert_eq!(&[0x62, 0x00, 0x0, 0x0], &memory.read_all()[0xFFC..]);
+            assert_eq!(Some((0x6200, OpCode::LD(LoadOp::LD(0x2, 0x00)))), memory.decoded(0xFFC));
+        }
+    }
+}
@@ -1,4 +1,7 @@
 use super::State;
+use super::error::Fault;
+use crate::framebuffer::FrameBuffer;
+use crate::keypad::Keypad;
 use crate::memory::Memory;
 use crate::opcode::OpCode;
 
@@ -7,206 +10,292 @@ mod jumpops;
 mod skipops;
 mod addops;
 mod shiftops;
+mod scrollops;
 
 use self::loadops::handle_load_operands;
 use self::jumpops::handle_jump_ops;
 use self::skipops::handle_skip_ops;
 use self::addops::handle_add_op;
 use self::shiftops::handle_shift_op;
+use self::scrollops::handle_scroll_op;
+use super::{LORES_WIDTH, LORES_HEIGHT, HIRES_WIDTH, HIRES_HEIGHT};
 
-use rand::Rng;
+/// The XO-CHIP bitplanes, as the bit each sets in a screen pixel
+const PLANES: [u8; 2] = [0x1, 0x2];
 
 enum Logical {
-    AND,
-    OR, 
-    XOR
+    And,
+    Or, 
+    Xor
 }
 
-fn call_routine(location: u16, pc: u16, state: State) -> State {
-    let mut stack = state.stack;
-    let mut stack_pointer = state.stack_pointer;
-    stack[stack_pointer as usize] = pc;
-    stack_pointer += 1;
-
-    State {
-        pc: location,
-        stack_pointer,
-        stack,
-        last_opcode: OpCode::CALL(location),
-        ..state
+/// Reads a byte from memory, faulting if the address is out of range
+fn read_memory(memory: &Memory, address: usize) -> Result<u8, Fault> {
+    if address < memory.size() {
+        Ok(memory.read(address as u16))
+    } else {
+        Err(Fault::MemoryOutOfRange(address))
     }
 }
 
-fn return_from_routine(state: State) -> State {
-    let stack_pointer = state.stack_pointer-1;
-    let pc = state.stack[stack_pointer as usize];
-    State {
-        pc,
-        stack_pointer,
-        last_opcode: OpCode::RET,
-        ..state
+/// Writes bytes to memory from the address, faulting before any are
+/// written if one is out of range
+fn write_memory(memory: &mut Memory, address: usize, data: &[u8]) -> Result<(), Fault> {
+    if address + data.len() <= memory.size() {
+        memory.set_range(address, data);
+        Ok(())
+    } else {
+        Err(Fault::MemoryOutOfRange(address.max(memory.size())))
     }
 }
 
-fn subtract_y_from_x(state: State, pc: u16, vx: u8, vy: u8) -> State {
-    let mut registers = state.registers;
-    let x = registers[vx as usize];
-    let y = registers[vy as usize];
+fn call_routine(state: &mut State, location: u16, pc: u16) -> Result<(), Fault> {
+    let stack_pointer = state.stack_pointer as usize;
+    if stack_pointer >= state.stack.len() {
+        return Err(Fault::StackOverflow);
+    }
 
-    let (result, _overflows) = x.overflowing_sub(y);
-    registers[vx as usize] = result;
-    registers[0xF] = if x > y { 1 } else { 0 };
+    state.stack[stack_pointer] = pc;
+    state.stack_pointer += 1;
+    state.pc = location;
+    Ok(())
+}
 
-    State {
-        registers,
-        pc,
-        last_opcode: OpCode::SUB(vx,vy),
-        ..state
+fn return_from_routine(state: &mut State) -> Result<(), Fault> {
+    if state.stack_pointer == 0 {
+        return Err(Fault::StackUnderflow);
     }
-}
 
-fn subtract_x_from_y(state: State, pc: u16, vx: u8, vy: u8) -> State {
-    let mut registers = state.registers;
-    let x = registers[vx as usize];
-    let y = registers[vy as usize];
+    state.stack_pointer -= 1;
+    state.pc = state.stack[state.stack_pointer as usize];
+    Ok(())
+}
 
-    let (result, _overflows) = y.overflowing_sub(x);
-    registers[vx as usize] = result;
-    registers[0xF] = if y > x  { 1 } else { 0 };
+fn subtract_y_from_x(state: &mut State, pc: u16, vx: u8, vy: u8) {
+    let x = state.registers[vx as usize];
+    let y = state.registers[vy as usize];
 
-    State {
-        registers,
-        pc,
-        last_opcode: OpCode::SUBN(vx,vy),
-        ..state
-    }
+    let (result, _overflows) = x.overflowing_sub(y);
+    state.registers[vx as usize] = result;
+    // VF is set when there is no borrow, including when the values are equal
+    state.registers[0xF] = if x >= y { 1 } else { 0 };
+    state.pc = pc;
 }
 
-fn set_rnd(state: State, vx: u8, pc: u16, kk: u8) -> State {
-    let mut rng = rand::thread_rng();
-    let r:u8 = rng.gen();
-    let val = r & kk;
-    let mut registers = state.registers;
-    registers[vx as usize] = val;
+fn subtract_x_from_y(state: &mut State, pc: u16, vx: u8, vy: u8) {
+    let x = state.registers[vx as usize];
+    let y = state.registers[vy as usize];
 
-    State {
-        registers,
-        pc,
-        last_opcode: OpCode::RND(vx, kk),
-        ..state
-    }
+    let (result, _overflows) = y.overflowing_sub(x);
+    state.registers[vx as usize] = result;
+    state.registers[0xF] = if y >= x { 1 } else { 0 };
+    state.pc = pc;
+}
+
+fn set_rnd(state: &mut State, vx: u8, pc: u16, kk: u8, memory: &Memory) {
+    state.registers[vx as usize] = state.random.next_byte(memory) & kk;
+    state.pc = pc;
 }
 
-fn wrap(val: u8, max: u8) -> u8 {
-    if val > max {
+fn wrap(val: u32, max: u32) -> u32 {
+    if val >= max {
         val % max
     } else {
         val
     }
 }
 
-fn handle_draw(state: State, pc: u16, vx: u8, vy: u8, n: u8, memory: &Memory, screen: &mut [u8]) -> State {
-    let mut erased = 0;
-    let row = state.registers[vx as usize];
-    let col = state.registers[vy as usize];
-    let width = state.width;
-    let height = state.height;
+fn handle_draw(state: &mut State, pc: u16, vx: u8, vy: u8, n: u8, memory: &Memory, screen: &mut FrameBuffer) -> Result<(), Fault> {
+    // waiting leaves the pc on the draw, so it runs again next time
+    if state.quirks.display_wait && !state.vblank {
+        return Ok(());
+    }
 
-    for yline in 0..n {
-        let sprite = memory.read(state.i + u16::from(yline));
-        for xline in 0..8{
-            if (sprite & (0x80 >> xline)) != 0 {
-                let x = u32::from(wrap(row + xline, width as u8));
-                let y = u32::from(wrap(yline  + col, height as u8));
-                let idx = ((y*width) + x) as usize;
-                let current_pixel = screen[idx];
-                if current_pixel == 1 {
-                    erased = 1;
-                }
-
-                screen[idx] ^=1;
+    let height = state.height;
+    let x = wrap(u32::from(state.registers[vx as usize]), state.width);
+    let top = wrap(u32::from(state.registers[vy as usize]), height);
+    let clip = state.quirks.clip_sprites;
+
+    // a sprite of 0 rows is a 16x16 sprite stored as two bytes per row
+    let (cols, rows) = if n == 0 { (16, 16) } else { (8, u32::from(n)) };
+    let bytes_per_row = cols / 8;
+    let planes = PLANES.iter().filter(|plane| state.plane & **plane != 0);
+
+    // the sprites are read before drawing so a fault leaves the screen as it was
+    let mut sprites = Vec::with_capacity(2 * rows as usize);
+    let mut address = usize::from(state.i);
+    for plane in planes {
+        for yline in 0..rows {
+            let row_address = address + (yline * bytes_per_row) as usize;
+            let mut sprite = u16::from(read_memory(memory, row_address)?) << 8;
+            if bytes_per_row == 2 {
+                sprite |= u16::from(read_memory(memory, row_address + 1)?);
             }
+            sprites.push((*plane, yline, sprite));
         }
+
+        address += (rows * bytes_per_row) as usize;
     }
 
-    let mut registers = state.registers;
-    registers[0xF] = erased;
+    let mut erased = 0;
+    for (plane, yline, sprite) in sprites {
+        let y = top + yline;
+        if clip && y >= height {
+            continue;
+        }
 
-    State {
-        registers,
-        pc,
-        draw_flag: true,
-        last_opcode: OpCode::DRW(vx,vy,n),
-        ..state
+        if screen.draw_row(plane, x, wrap(y, height), sprite, clip) {
+            erased = 1;
+        }
     }
+
+    state.registers[0xF] = erased;
+    state.vblank = false;
+    state.pc = pc;
+    Ok(())
 }
 
-fn handle_logical(state: State, pc: u16, vx: u8, vy: u8, logical: Logical) -> State {
-    let mut registers = state.registers;
-    let x = registers[vx as usize];
-    let y = registers[vy as usize];
-    let (r,o) = match logical {
-        Logical::AND => (x & y, OpCode::AND(vx,vy)),
-        Logical::OR => (x | y, OpCode::OR(vx,vy)),
-        Logical::XOR => (x ^ y, OpCode::XOR(vx,vy))
+/// Switches between the 64x32 and 128x64 screen modes.
+/// 
+/// The screen buffer is resized by `State::execute` when the size changes.
+fn set_resolution(state: &mut State, pc: u16, hires: bool) {
+    let (width, height) = if hires {
+        (HIRES_WIDTH, HIRES_HEIGHT)
+    } else {
+        (LORES_WIDTH, LORES_HEIGHT)
     };
 
-    registers[vx as usize] = r;
+    state.width = width;
+    state.height = height;
+    state.hires = hires;
+    state.pc = pc;
+}
+
+/// Clears the selected planes, XO-CHIP programs can leave the others drawn
+fn clear_screen(state: &mut State, pc: u16, screen: &mut FrameBuffer) {
+    screen.clear(state.plane);
+    state.pc = pc;
+}
 
-    State {
-        pc,
-        registers,
-        last_opcode: o,
-        ..state
+fn load_audio_pattern(state: &mut State, pc: u16, memory: &Memory) -> Result<(), Fault> {
+    let mut audio_pattern = state.audio_pattern;
+    let i = usize::from(state.i);
+    for (offset, byte) in audio_pattern.iter_mut().enumerate() {
+        *byte = read_memory(memory, i + offset)?;
     }
+
+    state.audio_pattern = audio_pattern;
+    state.pc = pc;
+    Ok(())
 }
 
-pub fn assemble(state: State, memory: &mut Memory, keycode: &[u8], screen: &mut [u8], opcode: OpCode) -> State {
-    let pc: u16 = state.pc+2;
+fn handle_logical(state: &mut State, pc: u16, vx: u8, vy: u8, logical: Logical) {
+    let x = state.registers[vx as usize];
+    let y = state.registers[vy as usize];
+    state.registers[vx as usize] = match logical {
+        Logical::And => x & y,
+        Logical::Or => x | y,
+        Logical::Xor => x ^ y
+    };
+
+    if state.quirks.logic_resets_vf {
+        state.registers[0xF] = 0;
+    }
+    state.pc = pc;
+}
+
+/// Executes a single opcode against the state, in place.
+/// 
+/// Returns a fault if the opcode is unknown, would overflow or underflow the
+/// stack, or accesses memory out of range, in which case the state is left
+/// as it was.
+pub fn execute(state: &mut State, memory: &mut Memory, keypad: &mut Keypad, screen: &mut FrameBuffer, opcode: OpCode) -> Result<(), Fault> {
+    let pc: u16 = state.pc.wrapping_add(opcode.size());
 
     match opcode {
-        OpCode::Unknown(c) => panic!("Unknown opcode: {:04X}", c),
-        OpCode::CLS => State {clear_flag: true, pc, last_opcode: OpCode::CLS, ..state},
-        OpCode::CALL(nnn) => call_routine(nnn, pc, state),
-        OpCode::RET => return_from_routine(state),
-        OpCode::LD(ld) => handle_load_operands(state, ld, pc, memory, keycode),
+        OpCode::Unknown(_) => return Err(Fault::UnknownOpCode),
+        OpCode::CLS => clear_screen(state, pc, screen),
+        OpCode::CALL(nnn) => call_routine(state, nnn, pc)?,
+        OpCode::RET => return_from_routine(state)?,
+        OpCode::LD(ld) => handle_load_operands(state, ld, pc, memory, keypad)?,
         OpCode::JP(jp) => handle_jump_ops(state, jp),
-        OpCode::SKIP(sp) => handle_skip_ops(state, sp, pc, keycode),
+        OpCode::SKIP(sp) => handle_skip_ops(state, sp, pc, keypad, memory),
         OpCode::ADD(op) => handle_add_op(state, op, pc),
         OpCode::SUB(vx, vy) => subtract_y_from_x(state, pc, vx, vy),
         OpCode::SUBN(vx, vy) => subtract_x_from_y(state, pc, vx, vy),
-        OpCode::RND(vx, kk) => set_rnd(state, vx, pc, kk),
-        OpCode::DRW(vx, vy, n) => handle_draw(state, pc, vx, vy, n, memory, screen),
-        OpCode::OR(vx, vy) => handle_logical(state, pc, vx, vy, Logical::OR),
-        OpCode::AND(vx, vy) => handle_logical(state, pc, vx, vy, Logical::AND),
-        OpCode::XOR(vx, vy) => handle_logical(state, pc, vx, vy, Logical::XOR),
-        OpCode::SHIFT(so) => handle_shift_op(state, pc, so)
+        OpCode::RND(vx, kk) => set_rnd(state, vx, pc, kk, memory),
+        OpCode::DRW(vx, vy, n) => handle_draw(state, pc, vx, vy, n, memory, screen)?,
+        OpCode::OR(vx, vy) => handle_logical(state, pc, vx, vy, Logical::Or),
+        OpCode::AND(vx, vy) => handle_logical(state, pc, vx, vy, Logical::And),
+        OpCode::XOR(vx, vy) => handle_logical(state, pc, vx, vy, Logical::Xor),
+        OpCode::SHIFT(so) => handle_shift_op(state, pc, so),
+        OpCode::SCROLL(so) => handle_scroll_op(state, pc, so, screen),
+        OpCode::EXIT => {
+            state.run_flag = false;
+            state.pc = pc;
+        },
+        OpCode::LOW => set_resolution(state, pc, false),
+        OpCode::HIGH => set_resolution(state, pc, true),
+        OpCode::PLANE(n) => {
+            state.plane = n;
+            state.pc = pc;
+        },
+        OpCode::AUDIO => load_audio_pattern(state, pc, memory)?
     }
+
+    state.last_opcode = opcode;
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::opcode::OpCode;
+    use crate::opcode::{OpCode, LoadOp};
     use crate::memory::Memory;
+    use crate::framebuffer::Rect;
+    use crate::state::Quirks;
+    use crate::random::{RandomSource, SeededRandom};
+
+    /// Executes the opcode on the state and returns it
+    fn after(mut state: State, memory: &mut Memory, keypad: &mut Keypad, screen: &mut FrameBuffer, opcode: OpCode) -> Result<State, Fault> {
+        execute(&mut state, memory, keypad, screen, opcode)?;
+        Ok(state)
+    }
 
     #[test]
-    fn it_sets_the_clear_flag() {
+    fn it_clears_the_screen() {
         let state:State = Default::default();
-        let mut screen = [0x0;200];
+        let mut screen = FrameBuffer::new(64, 32);
+        screen.set(5, 3, 0x1);
+        screen.present();
+        let mut memory = Memory::new();
+
+        let new_state = after(state, &mut memory, &mut Keypad::new(), &mut screen, OpCode::CLS).unwrap();
+
+        assert!(screen.to_bytes().iter().all(|p| *p == 0));
+        assert_eq!(vec![3], screen.dirty_rows().collect::<Vec<_>>());
+        assert_eq!(0x202, new_state.pc);
+    }
+
+    #[test]
+    fn it_will_only_clear_the_selected_planes() {
+        let state = State { plane: 0x2, ..Default::default() };
+        let mut screen = FrameBuffer::new(64, 32);
+        screen.set(0, 0, 0x3);
         let mut memory = Memory::new();
 
-        let new_state = assemble(state, &mut memory, &Vec::new()[..], &mut screen[..], OpCode::CLS);
-        assert!(new_state.clear_flag);
+        after(state, &mut memory, &mut Keypad::new(), &mut screen, OpCode::CLS).unwrap();
+
+        assert_eq!(0x1, screen.pixel(0, 0));
     }
 
     #[test]
     fn it_calls_the_new_routine() {
         let state:State = State { pc: 0x200, ..Default::default() };
-        let mut screen = [0x0;200];
+        let mut screen = FrameBuffer::new(64, 32);
         let mut memory = Memory::new();
 
-        let new_state = assemble(state, &mut memory, &Vec::new()[..], &mut screen[..], OpCode::CALL(0x0123));
+        let new_state = after(state, &mut memory, &mut Keypad::new(), &mut screen, OpCode::CALL(0x0123)).unwrap();
         
         assert_eq!(0x0123, new_state.pc);
 
@@ -227,15 +316,81 @@ mod tests {
             ..Default::default()
         };
 
-        let mut screen = [0x0;200];
+        let mut screen = FrameBuffer::new(64, 32);
         let mut memory = Memory::new();
 
-        let new_state = assemble(state, &mut memory, &Vec::new()[..], &mut screen[..], OpCode::RET);
+        let new_state = after(state, &mut memory, &mut Keypad::new(), &mut screen, OpCode::RET).unwrap();
 
         assert_eq!(0xF334, new_state.pc);
         assert_eq!(0, new_state.stack_pointer);
     }
 
+    #[test]
+    fn it_will_not_call_with_a_full_stack() {
+        let state = State { stack_pointer: 16, ..Default::default() };
+        let mut screen = FrameBuffer::new(64, 32);
+        let mut memory = Memory::new();
+
+        let result = after(state, &mut memory, &mut Keypad::new(), &mut screen, OpCode::CALL(0x0123));
+
+        assert_eq!(Fault::StackOverflow, result.unwrap_err());
+    }
+
+    #[test]
+    fn it_will_not_return_with_an_empty_stack() {
+        let state:State = Default::default();
+        let mut screen = FrameBuffer::new(64, 32);
+        let mut memory = Memory::new();
+
+        let result = after(state, &mut memory, &mut Keypad::new(), &mut screen, OpCode::RET);
+
+        assert_eq!(Fault::StackUnderflow, result.unwrap_err());
+    }
+
+    #[test]
+    fn it_will_not_execute_unknown_opcodes() {
+        let state:State = Default::default();
+        let mut screen = FrameBuffer::new(64, 32);
+        let mut memory = Memory::new();
+
+        let result = after(state, &mut memory, &mut Keypad::new(), &mut screen, OpCode::Unknown(0xFFFF));
+
+        assert_eq!(Fault::UnknownOpCode, result.unwrap_err());
+    }
+
+    #[test]
+    fn it_will_wrap_sprites_drawn_at_the_screen_edge() {
+        let mut registers = [0x0;16];
+        registers[0x0] = 64;
+        registers[0x1] = 32;
+        let state = State { registers, i: 0x300, ..Default::default() };
+        let mut screen = state.create_buffer();
+        let mut memory = Memory::new();
+        memory.set(0x300, 0x80);
+
+        let new_state = after(state, &mut memory, &mut Keypad::new(), &mut screen, OpCode::DRW(0x0, 0x1, 1)).unwrap();
+
+        assert_eq!(1, screen.pixel(0, 0));
+        assert_eq!(0, new_state.registers[0xF]);
+    }
+
+    #[test]
+    fn it_will_mark_the_rows_drawn_as_dirty() {
+        let mut registers = [0x0;16];
+        registers[0x0] = 8;
+        registers[0x1] = 4;
+        let state = State { registers, i: 0x300, ..Default::default() };
+        let mut screen = state.create_buffer();
+        screen.present();
+        let mut memory = Memory::new();
+        memory.set_range(0x300, &[0x80, 0x00, 0x81]);
+
+        after(state, &mut memory, &mut Keypad::new(), &mut screen, OpCode::DRW(0x0, 0x1, 3)).unwrap();
+
+        assert_eq!(vec![4, 6], screen.dirty_rows().collect::<Vec<_>>());
+        assert_eq!(Some(Rect { x: 8, y: 4, width: 8, height: 3 }), screen.dirty_rect());
+    }
+
     #[test]
     fn it_will_subtract_vy_from_vx() {
         let mut registers = [0x0;16];
@@ -245,14 +400,14 @@ mod tests {
         registers[VY as usize] = 0xF0;
 
         let mut memory = Memory::new();
-        let mut screen = [0x0;200];
+        let mut screen = FrameBuffer::new(64, 32);
 
         let state = State {
             registers,
             ..Default::default()
         };
 
-        let new_state = assemble(state, &mut memory, &Vec::new()[..], &mut screen[..], OpCode::SUB(VX, VY));
+        let new_state = after(state, &mut memory, &mut Keypad::new(), &mut screen, OpCode::SUB(VX, VY)).unwrap();
 
         let registers = new_state.registers;
         assert_eq!(0x0F, registers[VX as usize]);
@@ -269,14 +424,14 @@ mod tests {
         registers[VY as usize] = 0xFF;
 
         let mut memory = Memory::new();
-        let mut screen = [0x0;200];
+        let mut screen = FrameBuffer::new(64, 32);
 
         let state = State {
             registers,
             ..Default::default()
         };
 
-        let new_state = assemble(state, &mut memory, &Vec::new()[..], &mut screen[..], OpCode::SUB(VX, VY));
+        let new_state = after(state, &mut memory, &mut Keypad::new(), &mut screen, OpCode::SUB(VX, VY)).unwrap();
 
         let registers = new_state.registers;
         assert_eq!(0xF1, registers[VX as usize]);
@@ -294,14 +449,14 @@ mod tests {
         registers[VY as usize] = 0xFF;
 
         let mut memory = Memory::new();
-        let mut screen = [0x0;200];
+        let mut screen = FrameBuffer::new(64, 32);
 
         let state = State {
             registers,
             ..Default::default()
         };
 
-        let new_state = assemble(state, &mut memory, &Vec::new()[..], &mut screen[..], OpCode::SUBN(VX, VY));
+        let new_state = after(state, &mut memory, &mut Keypad::new(), &mut screen, OpCode::SUBN(VX, VY)).unwrap();
 
         let registers = new_state.registers;
 
@@ -319,14 +474,14 @@ mod tests {
         registers[VY as usize] = 0xF0;
 
         let mut memory = Memory::new();
-        let mut screen = [0x0;200];
+        let mut screen = FrameBuffer::new(64, 32);
 
         let state = State {
             registers,
             ..Default::default()
         };
 
-        let new_state = assemble(state, &mut memory, &Vec::new()[..], &mut screen[..], OpCode::SUBN(VX, VY));
+        let new_state = after(state, &mut memory, &mut Keypad::new(), &mut screen, OpCode::SUBN(VX, VY)).unwrap();
 
         let registers = new_state.registers;
 
@@ -335,27 +490,49 @@ mod tests {
     }
 
     #[test]
-    #[ignore]
     fn it_will_set_random_number() {
-        // note: This is a relatively fragile test because 
-        // can't inject random (yet)
-
         let mut memory = Memory::new();
-        let mut screen = [0x0;200];
-        let state:State = Default::default();
+        let mut screen = FrameBuffer::new(64, 32);
+        let state = State {
+            random: Box::new(SeededRandom::new(0x1234)),
+            ..Default::default()
+        };
 
         const VX:u8 = 0xD;
         const KK:u8 = 0x12;
 
-        let new_state = assemble(state, &mut memory, &Vec::new()[..], &mut screen[..], OpCode::RND(VX, KK));
+        let mut expected = SeededRandom::new(0x1234);
+        let expected = expected.next_byte(&memory) & KK;
+
+        let new_state = after(state, &mut memory, &mut Keypad::new(), &mut screen, OpCode::RND(VX, KK)).unwrap();
         let registers = new_state.registers;
-        assert_ne!(0x0, registers[VX as usize]);
+        assert_eq!(expected, registers[VX as usize]);
+    }
+
+    #[test]
+    fn it_will_repeat_random_numbers_from_the_same_seed() {
+        let mut memory = Memory::new();
+        let mut screen = FrameBuffer::new(64, 32);
+        let mut values = Vec::new();
+
+        for _ in 0..2 {
+            let mut state = State {
+                random: Box::new(SeededRandom::new(99)),
+                ..Default::default()
+            };
+            for _ in 0..8 {
+                state = after(state, &mut memory, &mut Keypad::new(), &mut screen, OpCode::RND(0x0, 0xFF)).unwrap();
+                values.push(state.registers[0x0]);
+            }
+        }
+
+        assert_eq!(values[..8], values[8..]);
     }
 
     #[test]
     fn it_will_or_vx_and_vy(){
         let mut memory = Memory::new();
-        let mut screen = [0x0;200];
+        let mut screen = FrameBuffer::new(64, 32);
         const VX:u8 = 0xD;
         const VY:u8 = 0x2;
         let mut registers = [0x0;16];
@@ -368,8 +545,8 @@ mod tests {
             ..Default::default() 
         };
 
-        let new_state = assemble(state, &mut memory, &Vec::new()[..],
-         &mut screen[..], OpCode::OR(VX, VY));
+        let new_state = after(state, &mut memory, &mut Keypad::new(),
+         &mut screen, OpCode::OR(VX, VY)).unwrap();
         
         let registers = new_state.registers;
         assert_eq!(0xFF, registers[VX as usize]);
@@ -378,7 +555,7 @@ mod tests {
     #[test]
     fn it_will_and_vx_and_vy(){
         let mut memory = Memory::new();
-        let mut screen = [0x0;200];
+        let mut screen = FrameBuffer::new(64, 32);
         const VX:u8 = 0xD;
         const VY:u8 = 0x2;
         let mut registers = [0x0;16];
@@ -391,8 +568,8 @@ mod tests {
             ..Default::default() 
         };
 
-        let new_state = assemble(state, &mut memory, &Vec::new()[..],
-         &mut screen[..], OpCode::AND(VX, VY));
+        let new_state = after(state, &mut memory, &mut Keypad::new(),
+         &mut screen, OpCode::AND(VX, VY)).unwrap();
         
         let registers = new_state.registers;
         assert_eq!(0x00, registers[VX as usize]);
@@ -401,7 +578,7 @@ mod tests {
     #[test]
     fn it_will_exclusive_or_vx_and_vy(){
         let mut memory = Memory::new();
-        let mut screen = [0x0;200];
+        let mut screen = FrameBuffer::new(64, 32);
         const VX:u8 = 0xD;
         const VY:u8 = 0x2;
         let mut registers = [0x0;16];
@@ -414,10 +591,168 @@ mod tests {
             ..Default::default() 
         };
 
-        let new_state = assemble(state, &mut memory, &Vec::new()[..],
-         &mut screen[..], OpCode::XOR(VX, VY));
+        let new_state = after(state, &mut memory, &mut Keypad::new(),
+         &mut screen, OpCode::XOR(VX, VY)).unwrap();
         
         let registers = new_state.registers;
         assert_eq!(0b01110111, registers[VX as usize]);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn it_will_draw_16x16_sprites() {
+        let state = State { i: 0x300, ..Default::default() };
+        let mut screen = state.create_buffer();
+        let mut memory = Memory::new();
+        memory.set_range(0x300, &[0xFF; 32]);
+
+        after(state, &mut memory, &mut Keypad::new(), &mut screen, OpCode::DRW(0x0, 0x1, 0)).unwrap();
+
+        assert_eq!(256, screen.to_bytes().iter().filter(|p| **p == 1).count());
+        assert_eq!(1, screen.pixel(15, 15));
+    }
+
+    #[test]
+    fn it_will_draw_to_each_selected_plane() {
+        let state = State { i: 0x300, plane: 0x3, ..Default::default() };
+        let mut screen = state.create_buffer();
+        screen.set(1, 0, 0x2);
+        let mut memory = Memory::new();
+        memory.set_range(0x300, &[0x80, 0x40]);
+
+        let new_state = after(state, &mut memory, &mut Keypad::new(), &mut screen, OpCode::DRW(0x0, 0x1, 1)).unwrap();
+
+        assert_eq!(0x1, screen.pixel(0, 0));
+        assert_eq!(0x0, screen.pixel(1, 0));
+        assert_eq!(1, new_state.registers[0xF]);
+    }
+
+    #[test]
+    fn it_will_not_draw_with_no_planes_selected() {
+        let state = State { i: 0x300, ..Default::default() };
+        let mut screen = state.create_buffer();
+        let mut memory = Memory::new();
+        memory.set(0x300, 0xFF);
+
+        let state = after(state, &mut memory, &mut Keypad::new(), &mut screen, OpCode::PLANE(0)).unwrap();
+        after(state, &mut memory, &mut Keypad::new(), &mut screen, OpCode::DRW(0x0, 0x1, 1)).unwrap();
+
+        assert!(screen.to_bytes().iter().all(|p| *p == 0));
+    }
+
+    #[test]
+    fn it_will_load_the_audio_pattern() {
+        let state = State { i: 0x300, ..Default::default() };
+        let mut screen = FrameBuffer::new(64, 32);
+        let mut memory = Memory::new();
+        memory.set_range(0x300, &[0xAA; 16]);
+
+        let new_state = after(state, &mut memory, &mut Keypad::new(), &mut screen, OpCode::AUDIO).unwrap();
+
+        assert_eq!([0xAA; 16], new_state.audio_pattern);
+        assert_eq!(0x202, new_state.pc);
+    }
+
+    #[test]
+    fn it_will_advance_past_long_instructions() {
+        let state:State = Default::default();
+        let mut screen = FrameBuffer::new(64, 32);
+        let mut memory = Memory::new();
+
+        let new_state = after(state, &mut memory, &mut Keypad::new(), &mut screen, OpCode::LD(LoadOp::LDIL(0x1234))).unwrap();
+
+        assert_eq!(0x204, new_state.pc);
+        assert_eq!(0x1234, new_state.i);
+    }
+
+    #[test]
+    fn it_will_switch_to_high_resolution() {
+        let state:State = Default::default();
+        let mut screen = FrameBuffer::new(64, 32);
+        let mut memory = Memory::new();
+
+        let new_state = after(state, &mut memory, &mut Keypad::new(), &mut screen, OpCode::HIGH).unwrap();
+
+        assert!(new_state.hires);
+        assert_eq!(128, new_state.width);
+        assert_eq!(64, new_state.height);
+
+        let new_state = after(new_state, &mut memory, &mut Keypad::new(), &mut screen, OpCode::LOW).unwrap();
+
+        assert!(!new_state.hires);
+        assert_eq!(64, new_state.width);
+        assert_eq!(32, new_state.height);
+    }
+
+    #[test]
+    fn it_will_stop_running_on_exit() {
+        let state:State = Default::default();
+        let mut screen = FrameBuffer::new(64, 32);
+        let mut memory = Memory::new();
+
+        let new_state = after(state, &mut memory, &mut Keypad::new(), &mut screen, OpCode::EXIT).unwrap();
+
+        assert!(!new_state.run_flag);
+    }
+
+    #[test]
+    fn it_will_reset_vf_after_logical_ops_when_quirk_enabled() {
+        let mut memory = Memory::new();
+        let mut screen = FrameBuffer::new(64, 32);
+        let mut registers = [0x0;16];
+        registers[0xF] = 0x1;
+
+        let state = State {
+            registers,
+            quirks: Quirks { logic_resets_vf: true, ..Default::default() },
+            ..Default::default()
+        };
+
+        let new_state = after(state, &mut memory, &mut Keypad::new(),
+         &mut screen, OpCode::OR(0x1, 0x2)).unwrap();
+
+        assert_eq!(0x0, new_state.registers[0xF]);
+    }
+
+    #[test]
+    fn it_will_clip_sprites_drawn_at_the_screen_edge_when_quirk_enabled() {
+        let mut registers = [0x0;16];
+        registers[0x0] = 63;
+        registers[0x1] = 31;
+        let state = State {
+            registers,
+            i: 0x300,
+            quirks: Quirks { clip_sprites: true, ..Default::default() },
+            ..Default::default()
+        };
+        let mut screen = state.create_buffer();
+        let mut memory = Memory::new();
+        memory.set_range(0x300, &[0xC0, 0xC0]);
+
+        after(state, &mut memory, &mut Keypad::new(), &mut screen, OpCode::DRW(0x0, 0x1, 2)).unwrap();
+
+        assert_eq!(1, screen.pixel(63, 31));
+        assert_eq!(1, screen.to_bytes().iter().filter(|p| **p == 1).count());
+    }
+
+    #[test]
+    fn it_will_wait_for_vblank_before_drawing_when_quirk_enabled() {
+        let state = State {
+            i: 0x300,
+            quirks: Quirks { display_wait: true, ..Default::default() },
+            ..Default::default()
+        };
+        let mut screen = state.create_buffer();
+        let mut memory = Memory::new();
+        memory.set(0x300, 0x80);
+
+        let waiting = after(state, &mut memory, &mut Keypad::new(), &mut screen, OpCode::DRW(0x0, 0x1, 1)).unwrap();
+        assert_eq!(0x200, waiting.pc);
+        assert_eq!(0, screen.pixel(0, 0));
+
+        let state = State { vblank: true, ..waiting };
+        let drawn = after(state, &mut memory, &mut Keypad::new(), &mut screen, OpCode::DRW(0x0, 0x1, 1)).unwrap();
+        assert_eq!(0x202, drawn.pc);
+        assert_eq!(1, screen.pixel(0, 0));
+        assert!(!drawn.vblank);
+    }
+}
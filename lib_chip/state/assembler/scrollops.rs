@@ -0,0 +1,87 @@
+use super::State;
+use crate::framebuffer::FrameBuffer;
+use crate::opcode::ScrollOp;
+
+/// Number of pixels moved by a horizontal scroll
+const HORIZONTAL_SCROLL: i32 = 4;
+
+/// Handles the SUPER-CHIP scroll operations.
+///
+/// Scrolling is measured in pixels of the current resolution and only
+/// moves the selected planes.
+pub fn handle_scroll_op(state: &mut State, pc: u16, op: ScrollOp, screen: &mut FrameBuffer) {
+    let (dx, dy) = match op {
+        ScrollOp::SCD(n) => (0, i32::from(n)),
+        ScrollOp::SCR => (HORIZONTAL_SCROLL, 0),
+        ScrollOp::SCL => (-HORIZONTAL_SCROLL, 0)
+    };
+
+    screen.scroll(state.plane, dx, dy);
+    state.pc = pc;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    use super::*;
+    use crate::opcode::ScrollOp;
+
+    /// Runs the handler on the state and returns it
+    fn after_scroll(mut state: State, pc: u16, op: ScrollOp, screen: &mut FrameBuffer) -> State {
+        handle_scroll_op(&mut state, pc, op, screen);
+        state
+    }
+
+    #[test]
+    fn it_will_scroll_down() {
+        let state: State = Default::default();
+        let mut screen = state.create_buffer();
+        screen.set(3, 0, 1);
+
+        let new_state = after_scroll(state, 0x202, ScrollOp::SCD(2), &mut screen);
+
+        assert_eq!(0, screen.pixel(3, 0));
+        assert_eq!(1, screen.pixel(3, 2));
+        assert_eq!(0x202, new_state.pc);
+    }
+
+    #[test]
+    fn it_will_scroll_right() {
+        let state: State = Default::default();
+        let mut screen = state.create_buffer();
+        screen.set(0, 1, 1);
+        screen.set(63, 0, 1);
+
+        after_scroll(state, 0x202, ScrollOp::SCR, &mut screen);
+
+        assert_eq!(1, screen.pixel(4, 1));
+        assert_eq!(0, screen.pixel(0, 1));
+        assert_eq!(1, screen.to_bytes().iter().filter(|p| **p == 1).count());
+    }
+
+    #[test]
+    fn it_will_scroll_left() {
+        let state: State = Default::default();
+        let mut screen = state.create_buffer();
+        screen.set(4, 1, 1);
+        screen.set(0, 1, 1);
+
+        after_scroll(state, 0x202, ScrollOp::SCL, &mut screen);
+
+        assert_eq!(1, screen.pixel(0, 1));
+        assert_eq!(0, screen.pixel(4, 1));
+        assert_eq!(1, screen.to_bytes().iter().filter(|p| **p == 1).count());
+    }
+
+    #[test]
+    fn it_will_only_scroll_selected_planes() {
+        let state = State { plane: 0x2, ..Default::default() };
+        let mut screen = state.create_buffer();
+        screen.set(0, 0, 0x3);
+
+        after_scroll(state, 0x202, ScrollOp::SCD(1), &mut screen);
+
+        assert_eq!(0x1, screen.pixel(0, 0));
+        assert_eq!(0x2, screen.pixel(0, 1));
+    }
+}
@@ -1,41 +1,35 @@
 use super::State;
-use crate::opcode::{ShiftOp, OpCode};
-
-fn handle_shift_left(state: State, pc: u16, vx: u8) -> State {
-    let mut registers = state.registers;
-    let x = registers[vx as usize] << 1;
-    let msb = (x & 0xF0) >> 7;
-    registers[0xF] = if msb == 1 { 1 } else { 0 };
-    registers[vx as usize] = x;
-
-    State {
-        last_opcode: OpCode::SHIFT(ShiftOp::SHL(vx)),
-        pc,
-        registers,
-        ..state
+use crate::opcode::ShiftOp;
+
+/// Returns the register that is shifted into Vx
+fn source_register(state: &State, vx: u8, vy: u8) -> usize {
+    if state.quirks.shift_uses_vy {
+        vy as usize
+    } else {
+        vx as usize
     }
 }
 
-fn handle_shift_right(state: State, pc: u16, vx: u8) -> State {
-    let mut registers = state.registers;
-    let x = registers[vx as usize] >> 1;
-    let lsb = x & 0x01;
-    registers[0xF] = if lsb == 1 { 1 } else { 0 };
-    registers[vx as usize] = x;
-
-    State {
-        last_opcode: OpCode::SHIFT(ShiftOp::SHR(vx)),
-        pc,
-        registers,
-        ..state
-    }
+/// VF is set last, from the bit shifted out, so it holds the flag when Vx is VF
+fn handle_shift_left(state: &mut State, pc: u16, vx: u8, vy: u8) {
+    let value = state.registers[source_register(state, vx, vy)];
+    state.registers[vx as usize] = value << 1;
+    state.registers[0xF] = value >> 7;
+    state.pc = pc;
+}
+
+fn handle_shift_right(state: &mut State, pc: u16, vx: u8, vy: u8) {
+    let value = state.registers[source_register(state, vx, vy)];
+    state.registers[vx as usize] = value >> 1;
+    state.registers[0xF] = value & 0x01;
+    state.pc = pc;
 }
 
 /// Handles shift right and shift left operations
-pub fn handle_shift_op(state: State, pc: u16, op: ShiftOp) -> State {
+pub fn handle_shift_op(state: &mut State, pc: u16, op: ShiftOp) {
     match op {
-        ShiftOp::SHL(vx) => handle_shift_left(state, pc, vx),
-        ShiftOp::SHR(vx) => handle_shift_right(state, pc, vx)
+        ShiftOp::SHL(vx, vy) => handle_shift_left(state, pc, vx, vy),
+        ShiftOp::SHR(vx, vy) => handle_shift_right(state, pc, vx, vy)
     }
 }
 
@@ -44,6 +38,13 @@ mod tests {
     use super::super::*;
     use super::*;
     use crate::opcode::ShiftOp;
+    use crate::state::Quirks;
+
+    /// Runs the handler on the state and returns it
+    fn after_shift(mut state: State, pc: u16, op: ShiftOp) -> State {
+        handle_shift_op(&mut state, pc, op);
+        state
+    }
 
     #[test]
     fn it_will_shift_left_msb_true() {
@@ -56,7 +57,7 @@ mod tests {
             ..Default::default()
         };
 
-        let new_state = handle_shift_op(state, 0x200, ShiftOp::SHL(VX));
+        let new_state = after_shift(state, 0x200, ShiftOp::SHL(VX, 0x0));
 
         let msb = new_state.registers[0xF];
         let vx = new_state.registers[VX as usize];
@@ -69,20 +70,20 @@ mod tests {
     fn it_will_shift_left_msb_false() {
         const VX:u8 = 0xD;
         let mut registers = [0x0;16];
-        registers[VX as usize] = 0xBF;
+        registers[VX as usize] = 0x7F;
 
         let state = State {
             registers,
             ..Default::default()
         };
 
-        let new_state = handle_shift_op(state, 0x200, ShiftOp::SHL(VX));
+        let new_state = after_shift(state, 0x200, ShiftOp::SHL(VX, 0x0));
 
         let msb = new_state.registers[0xF];
         let vx = new_state.registers[VX as usize];
 
         assert_eq!(0, msb);
-        assert_eq!(0x7E, vx);
+        assert_eq!(0xFE, vx);
     }
 
     #[test]
@@ -96,7 +97,7 @@ mod tests {
             ..Default::default()
         };
 
-        let new_state = handle_shift_op(state, 0x200, ShiftOp::SHR(VX));
+        let new_state = after_shift(state, 0x200, ShiftOp::SHR(VX, 0x0));
 
         let lsb = new_state.registers[0xF];
         let vx = new_state.registers[VX as usize];
@@ -109,14 +110,14 @@ mod tests {
     fn it_will_shift_right_lsb_false() {
         const VX:u8 = 0xD;
         let mut registers = [0x0;16];
-        registers[VX as usize] = 0xFD;
+        registers[VX as usize] = 0xFC;
 
         let state = State {
             registers,
             ..Default::default()
         };
 
-        let new_state = handle_shift_op(state, 0x200, ShiftOp::SHR(VX));
+        let new_state = after_shift(state, 0x200, ShiftOp::SHR(VX, 0x0));
 
         let lsb = new_state.registers[0xF];
         let vx = new_state.registers[VX as usize];
@@ -124,4 +125,50 @@ mod tests {
         assert_eq!(0, lsb);
         assert_eq!(0x7E, vx);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn it_will_set_the_flag_from_the_bit_shifted_out() {
+        let mut registers = [0x0;16];
+        registers[0x1] = 0x81;
+        let state = State { registers, ..Default::default() };
+
+        let left = after_shift(state.clone(), 0x200, ShiftOp::SHL(0x1, 0x0));
+        let right = after_shift(state, 0x200, ShiftOp::SHR(0x1, 0x0));
+
+        assert_eq!((0x02, 1), (left.registers[0x1], left.registers[0xF]));
+        assert_eq!((0x40, 1), (right.registers[0x1], right.registers[0xF]));
+    }
+
+    #[test]
+    fn it_will_keep_the_flag_when_shifting_vf() {
+        let mut registers = [0x0;16];
+        registers[0xF] = 0x40;
+        let state = State { registers, ..Default::default() };
+
+        let left = after_shift(state.clone(), 0x200, ShiftOp::SHL(0xF, 0x0));
+        let right = after_shift(state, 0x200, ShiftOp::SHR(0xF, 0x0));
+
+        assert_eq!(0, left.registers[0xF]);
+        assert_eq!(0, right.registers[0xF]);
+    }
+
+    #[test]
+    fn it_will_shift_vy_into_vx_when_quirk_enabled() {
+        const VX:u8 = 0xD;
+        const VY:u8 = 0xE;
+        let mut registers = [0x0;16];
+        registers[VX as usize] = 0x00;
+        registers[VY as usize] = 0x81;
+
+        let state = State {
+            registers,
+            quirks: Quirks { shift_uses_vy: true, ..Default::default() },
+            ..Default::default()
+        };
+
+        let new_state = after_shift(state, 0x200, ShiftOp::SHL(VX, VY));
+
+        assert_eq!(0x02, new_state.registers[VX as usize]);
+        assert_eq!(0x81, new_state.registers[VY as usize]);
+    }
+}
@@ -1,114 +1,39 @@
 use super::State;
-use crate::opcode::{SkipOp, OpCode};
-
-fn handle_skip_if_equal(state: State, vx: u8, kk: u8, pc: u16) -> State {
-    let x = state.registers[vx as usize];
-    let mut pc = pc;
-    if x == kk {
-        pc +=2;
-    }
-
-    State {
-        last_opcode: OpCode::SKIP(SkipOp::SE(vx, kk)),
-        pc,
-        ..state
-    }
-}
-
-fn handle_skip_if_not_equal(state: State, vx: u8, kk: u8, pc: u16) -> State {
-    let x = state.registers[vx as usize];
-    let mut pc = pc;
-    if x != kk {
-        pc +=2;
-    }
-
-    State {
-        pc,
-        last_opcode: OpCode::SKIP(SkipOp::SNE(vx, kk)),
-        ..state
+use crate::keypad::Keypad;
+use crate::memory::Memory;
+use crate::opcode::SkipOp;
+use crate::opcode::parser::is_long_opcode;
+
+/// Returns the number of bytes to skip to step over the instruction at pc,
+/// the 4-byte XO-CHIP long I load is skipped as a whole.
+fn next_instruction_size(memory: &Memory, pc: u16) -> u16 {
+    let address = usize::from(pc);
+    if address + 1 < memory.size() && is_long_opcode(memory.read(pc), memory.read(pc + 1)) {
+        4
+    } else {
+        2
     }
 }
 
-fn handle_skip_if_registers_equal(state: State, vx: u8, vy: u8, pc: u16) -> State {
-    let x = state.registers[vx as usize];
-    let y = state.registers[vy as usize];
-    let mut pc = pc;
-    if x == y {
-        pc +=2;
-    }
-
-    State {
-        last_opcode: OpCode::SKIP(SkipOp::SEXY(vx,vy)),
-        pc,
-        ..state
-    }
-}
-
-fn handle_skip_if_registers_not_equal(state: State, vx: u8, vy: u8, pc: u16) -> State {
-    let x = state.registers[vx as usize];
-    let y = state.registers[vy as usize];
-    let mut pc = pc;
-    if x != y {
-        pc +=2;
-    }
-
-    State {
-        last_opcode: OpCode::SKIP(SkipOp::SNEXY(vx,vy)),
-        pc,
-        ..state
-    }
-}
-
-fn handle_skip_on_keyboard(state: State, keycode: &[u8], vx: u8, pc: u16) -> State {
-    let value = state.registers[vx as usize];
-    let mut pc = pc;
-    println!("Waiting on: {}", value);
-    match keycode.len() {
-        0 => (),
-        _ => {
-            if keycode.contains(&value) {
-                pc += 2;
-            }
-        }
-    };
-
-    State {
-        last_opcode: OpCode::SKIP(SkipOp::SKP(vx)),
-        pc,
-        ..state
+/// Returns true if the skip's condition holds
+fn should_skip(state: &State, op: SkipOp, keypad: &Keypad) -> bool {
+    let registers = &state.registers;
+    match op {
+        SkipOp::SE(vx, kk) => registers[vx as usize] == kk,
+        SkipOp::SNE(vx, kk) => registers[vx as usize] != kk,
+        SkipOp::SEXY(vx, vy) => registers[vx as usize] == registers[vy as usize],
+        SkipOp::SNEXY(vx, vy) => registers[vx as usize] != registers[vy as usize],
+        SkipOp::SKP(vx) => keypad.is_pressed(registers[vx as usize]),
+        SkipOp::SKNP(vx) => !keypad.is_pressed(registers[vx as usize])
     }
 }
 
-fn handle_skip_on_keyboard_up(state: State, keycode: &[u8], vx: u8, pc: u16) -> State {
-    let value = state.registers[vx as usize];
-    let mut pc = pc;
-    match keycode.len() {
-        0 => {pc+=2;},
-        _ => {
-            if !keycode.contains(&value) {
-                pc += 2;
-            }
-        }
+pub fn handle_skip_ops(state: &mut State, op: SkipOp, pc: u16, keypad: &Keypad, memory: &Memory) {
+    state.pc = if should_skip(state, op, keypad) {
+        pc.wrapping_add(next_instruction_size(memory, pc))
+    } else {
+        pc
     };
-
-    State {
-        last_opcode: OpCode::SKIP(SkipOp::SKNP(vx)),
-        pc,
-        ..state
-    }
-}
-
-
-pub fn handle_skip_ops(state: State, op: SkipOp, pc: u16, keycode: &[u8]) -> State {
-    match op {
-        SkipOp::SE(vx, kk) => handle_skip_if_equal(state, vx, kk, pc),
-        SkipOp::SNE(vx, kk) => handle_skip_if_not_equal(state, vx, kk, pc),
-        SkipOp::SEXY(vx, vy) => handle_skip_if_registers_equal(state, vx, vy, pc),
-        SkipOp::SNEXY(vx, vy) => handle_skip_if_registers_not_equal(state, vx, vy, pc),
-        // todo: Need to ensure these don't need wait for inputs
-        SkipOp::SKP(vx) => handle_skip_on_keyboard(state, keycode, vx, pc),
-        SkipOp::SKNP(vx) => handle_skip_on_keyboard_up(state, keycode, vx, pc)
-    }
 }
 
 #[cfg(test)]
@@ -117,6 +42,12 @@ mod tests {
     use super::*;
     use crate::opcode::{SkipOp};
 
+    /// Runs the handler on the state and returns it
+    fn after_skip(mut state: State, op: SkipOp, pc: u16, keypad: &Keypad, memory: &Memory) -> State {
+        handle_skip_ops(&mut state, op, pc, keypad, memory);
+        state
+    }
+
     #[test]
     fn it_should_not_skip_if_kk_not_equal() {
         let mut registers = [0x0;16];
@@ -129,7 +60,7 @@ mod tests {
             ..Default::default()
         };
 
-        let new_state = handle_skip_ops(state, SkipOp::SE(VX, KK), 0x200, &Vec::new()[..]);
+        let new_state = after_skip(state, SkipOp::SE(VX, KK), 0x200, &Keypad::new(), &Memory::new());
 
         assert_eq!(0x200, new_state.pc);
     }
@@ -147,7 +78,7 @@ mod tests {
             ..Default::default()
         };
 
-        let new_state = handle_skip_ops(state, SkipOp::SE(VX, KK), 0x200, &Vec::new()[..]);
+        let new_state = after_skip(state, SkipOp::SE(VX, KK), 0x200, &Keypad::new(), &Memory::new());
 
         assert_eq!(0x202, new_state.pc);
     }
@@ -164,7 +95,7 @@ mod tests {
             ..Default::default()
         };
 
-        let new_state = handle_skip_ops(state, SkipOp::SNE(VX, KK), 0x200, &Vec::new()[..]);
+        let new_state = after_skip(state, SkipOp::SNE(VX, KK), 0x200, &Keypad::new(), &Memory::new());
 
         assert_eq!(0x200, new_state.pc);
     }
@@ -182,7 +113,7 @@ mod tests {
             ..Default::default()
         };
 
-        let new_state = handle_skip_ops(state, SkipOp::SNE(VX, KK), 0x200, &Vec::new()[..]);
+        let new_state = after_skip(state, SkipOp::SNE(VX, KK), 0x200, &Keypad::new(), &Memory::new());
 
         assert_eq!(0x202, new_state.pc);
     }
@@ -202,7 +133,7 @@ mod tests {
             ..Default::default()
         };
 
-        let new_state = handle_skip_ops(state, SkipOp::SEXY(VX, VY), 0x200, &Vec::new()[..]);
+        let new_state = after_skip(state, SkipOp::SEXY(VX, VY), 0x200, &Keypad::new(), &Memory::new());
 
         assert_eq!(0x202, new_state.pc);
     }
@@ -221,7 +152,7 @@ mod tests {
             ..Default::default()
         };
 
-        let new_state = handle_skip_ops(state, SkipOp::SEXY(VX, VY), 0x200, &Vec::new()[..]);
+        let new_state = after_skip(state, SkipOp::SEXY(VX, VY), 0x200, &Keypad::new(), &Memory::new());
 
         assert_eq!(0x200, new_state.pc);
     }
@@ -240,7 +171,7 @@ mod tests {
             ..Default::default()
         };
 
-        let new_state = handle_skip_ops(state, SkipOp::SNEXY(VX, VY), 0x200, &Vec::new()[..]);
+        let new_state = after_skip(state, SkipOp::SNEXY(VX, VY), 0x200, &Keypad::new(), &Memory::new());
 
         assert_eq!(0x202, new_state.pc);
     }
@@ -260,7 +191,7 @@ mod tests {
             ..Default::default()
         };
 
-        let new_state = handle_skip_ops(state, SkipOp::SNEXY(VX, VY), 0x200, &Vec::new()[..]);
+        let new_state = after_skip(state, SkipOp::SNEXY(VX, VY), 0x200, &Keypad::new(), &Memory::new());
 
         assert_eq!(0x200, new_state.pc);
     }
@@ -280,7 +211,7 @@ mod tests {
 
         let key = 5u8;
 
-        let new_state = handle_skip_ops(state, SkipOp::SKP(VX), 0x200, &vec![key][..]);
+        let new_state = after_skip(state, SkipOp::SKP(VX), 0x200, &Keypad::with_keys(&[key]), &Memory::new());
 
         assert_eq!(0x202, new_state.pc);   
     }
@@ -300,7 +231,7 @@ mod tests {
 
         let key = 6u8;
 
-        let new_state = handle_skip_ops(state, SkipOp::SKP(VX), 0x200, &vec![key][..]);
+        let new_state = after_skip(state, SkipOp::SKP(VX), 0x200, &Keypad::with_keys(&[key]), &Memory::new());
 
         assert_eq!(0x200, new_state.pc);  
     }
@@ -320,7 +251,7 @@ mod tests {
 
         let key = 6u8;
 
-        let new_state = handle_skip_ops(state, SkipOp::SKNP(VX), 0x200, &vec![key][..]);
+        let new_state = after_skip(state, SkipOp::SKNP(VX), 0x200, &Keypad::with_keys(&[key]), &Memory::new());
 
         assert_eq!(0x202, new_state.pc);  
     }
@@ -340,8 +271,20 @@ mod tests {
 
         let key = 5u8;
 
-        let new_state = handle_skip_ops(state, SkipOp::SKNP(VX), 0x200, &vec![key][..]);
+        let new_state = after_skip(state, SkipOp::SKNP(VX), 0x200, &Keypad::with_keys(&[key]), &Memory::new());
 
         assert_eq!(0x200, new_state.pc);     
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn it_should_skip_over_long_instructions() {
+        let mut memory = Memory::new();
+        memory.set_range(0x200, &[0xF0, 0x00, 0x12, 0x34]);
+
+        let state: State = Default::default();
+
+        let new_state = after_skip(state, SkipOp::SE(0x0, 0x0), 0x200, &Keypad::new(), &memory);
+
+        assert_eq!(0x204, new_state.pc);
+    }
+}
@@ -26,7 +26,6 @@ impl Display for State {
         writeln!(f, "delay: {}, sound {}", self.delay_timer, self.sound_timer)?;
         writeln!(f, "pc: {} | stack pointer: {} | i: {}", self.pc,
             self.stack_pointer, self.i)?;
-        writeln!(f, "draw: {} | run: {} | clear: {} ", self.draw_flag, 
-            self.run_flag, self.clear_flag)
+        writeln!(f, "run: {} | hires: {}", self.run_flag, self.hires)
     }
 }
\ No newline at end of file
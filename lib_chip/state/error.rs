@@ -0,0 +1,86 @@
+//! Errors raised while executing a chip8 program.
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+
+/// Describes why the interpreter was unable to execute an instruction.
+///
+/// Every variant carries the program counter of the faulting instruction
+/// and the raw opcode that was fetched from it.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ExecError {
+    /// The opcode does not map to any known instruction.
+    UnknownOpCode { pc: u16, opcode: u16 },
+    /// A routine was called while all 16 stack entries were in use.
+    StackOverflow { pc: u16, opcode: u16 },
+    /// A return was made with no routine on the stack to return from.
+    StackUnderflow { pc: u16, opcode: u16 },
+    /// The instruction read or wrote an address outside of memory.
+    ///
+    /// If the opcode itself could not be fetched then `opcode` only
+    /// holds the bytes that could be read.
+    MemoryOutOfRange { pc: u16, opcode: u16, address: usize },
+}
+
+impl ExecError {
+    /// Returns the program counter of the faulting instruction
+    pub fn pc(&self) -> u16 {
+        match *self {
+            ExecError::UnknownOpCode { pc, .. } => pc,
+            ExecError::StackOverflow { pc, .. } => pc,
+            ExecError::StackUnderflow { pc, .. } => pc,
+            ExecError::MemoryOutOfRange { pc, .. } => pc
+        }
+    }
+
+    /// Returns the raw opcode of the faulting instruction
+    pub fn opcode(&self) -> u16 {
+        match *self {
+            ExecError::UnknownOpCode { opcode, .. } => opcode,
+            ExecError::StackOverflow { opcode, .. } => opcode,
+            ExecError::StackUnderflow { opcode, .. } => opcode,
+            ExecError::MemoryOutOfRange { opcode, .. } => opcode
+        }
+    }
+}
+
+impl Display for ExecError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            ExecError::UnknownOpCode { pc, opcode } =>
+                write!(f, "Unknown opcode 0x{:04X} at 0x{:04X}", opcode, pc),
+            ExecError::StackOverflow { pc, opcode } =>
+                write!(f, "Stack overflow calling 0x{:04X} at 0x{:04X}", opcode, pc),
+            ExecError::StackUnderflow { pc, opcode } =>
+                write!(f, "Stack underflow returning 0x{:04X} at 0x{:04X}", opcode, pc),
+            ExecError::MemoryOutOfRange { pc, opcode, address } =>
+                write!(f, "Memory address 0x{:04X} out of range executing 0x{:04X} at 0x{:04X}",
+                    address, opcode, pc)
+        }
+    }
+}
+
+impl Error for ExecError {}
+
+/// A fault raised by an instruction handler.
+///
+/// Handlers only know which instruction they are executing, the program
+/// counter and raw opcode are attached by `State::step`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) enum Fault {
+    UnknownOpCode,
+    StackOverflow,
+    StackUnderflow,
+    MemoryOutOfRange(usize)
+}
+
+impl Fault {
+    /// Converts the fault into an `ExecError` for the instruction at pc
+    pub(crate) fn at(self, pc: u16, opcode: u16) -> ExecError {
+        match self {
+            Fault::UnknownOpCode => ExecError::UnknownOpCode { pc, opcode },
+            Fault::StackOverflow => ExecError::StackOverflow { pc, opcode },
+            Fault::StackUnderflow => ExecError::StackUnderflow { pc, opcode },
+            Fault::MemoryOutOfRange(address) => ExecError::MemoryOutOfRange { pc, opcode, address }
+        }
+    }
+}
@@ -1,10 +1,27 @@
 mod display;
 mod assembler;
+mod error;
+mod quirks;
+use crate::framebuffer::FrameBuffer;
+use crate::keypad::Keypad;
 use crate::memory::Memory;
-use crate::opcode::{OpCode, parser::parse_opcode};
-use assembler::assemble;
+use crate::random::{RandomSource, SeededRandom};
+use crate::opcode::{OpCode, parser::{is_long_opcode, parse_opcode, parse_long_opcode}};
+use assembler::execute;
 
-#[derive(Debug)]
+pub use self::error::ExecError;
+pub use self::quirks::Quirks;
+
+/// Width of the screen in low resolution mode
+pub const LORES_WIDTH: u32 = 64;
+/// Height of the screen in low resolution mode
+pub const LORES_HEIGHT: u32 = 32;
+/// Width of the screen in SUPER-CHIP high resolution mode
+pub const HIRES_WIDTH: u32 = 128;
+/// Height of the screen in SUPER-CHIP high resolution mode
+pub const HIRES_HEIGHT: u32 = 64;
+
+#[derive(Debug, Clone)]
 pub struct State {
     pub stack: [u16; 16],
     pub registers: [u8; 16],
@@ -13,26 +30,83 @@ pub struct State {
     pub pc: u16,
     pub stack_pointer: u16,
     pub i: u16,
-    pub draw_flag: bool,
     pub run_flag: bool,
-    pub clear_flag: bool,
     pub last_opcode: OpCode,
     pub opcode: Option<OpCode>,
     pub width: u32,
-    pub height: u32
+    pub height: u32,
+    pub hires: bool,
+    /// The SUPER-CHIP RPL user flags, XO-CHIP extends these to 16
+    pub rpl: [u8; 16],
+    /// The XO-CHIP bitplanes selected for drawing, each screen pixel
+    /// holds one bit per plane
+    pub plane: u8,
+    /// The XO-CHIP 1-bit audio pattern played while the sound timer is active
+    pub audio_pattern: [u8; 16],
+    /// The XO-CHIP playback rate of the audio pattern, 64 plays at 4000 bits per second
+    pub pitch: u8,
+    pub quirks: Quirks,
+    /// Set by the frontend at the start of each frame, a draw
+    /// waiting on the `display_wait` quirk will clear it.
+    pub vblank: bool,
+    /// The source of the numbers drawn by CXKK, seeded so runs can be repeated
+    pub random: Box<dyn RandomSource>,
+    /// The key FX0A saw held down, it completes once the key is released
+    pub key_wait: Option<u8>
 }
 
 impl Default for State {
     fn default() -> Self {
-        State::new(64, 32)
+        State::new(LORES_WIDTH, LORES_HEIGHT)
+    }
+}
+
+/// Reads the raw opcode at an address.
+/// 
+/// Fails if any byte of the opcode lies outside of memory.  Only the first two
+/// bytes of a long I load are returned.
+fn fetch_opcode(pc: u16, memory: &Memory) -> Result<u16, ExecError> {
+    let size = memory.size();
+    let address = usize::from(pc);
+
+    if address >= size {
+        return Err(ExecError::MemoryOutOfRange { pc, opcode: 0, address });
+    }
+
+    let high = u16::from(memory.read(pc)) << 8;
+    if address + 1 >= size {
+        return Err(ExecError::MemoryOutOfRange { pc, opcode: high, address: address + 1 });
+    }
+
+    let opcode = high | u16::from(memory.read(pc + 1));
+    if is_long_opcode((opcode >> 8) as u8, opcode as u8) && address + 3 >= size {
+        return Err(ExecError::MemoryOutOfRange { pc, opcode, address: size });
+    }
+
+    Ok(opcode)
+}
+
+/// Decodes the instruction at an address, which has already been checked to
+/// lie in memory by `fetch_opcode`.
+fn decode_opcode(pc: u16, memory: &Memory, opcode: u16) -> OpCode {
+    let (high, low) = ((opcode >> 8) as u8, opcode as u8);
+    if is_long_opcode(high, low) {
+        parse_long_opcode(high, low, memory.read(pc + 2), memory.read(pc + 3))
+    } else {
+        parse_opcode(high, low)
     }
 }
 
-fn get_opcode(state: &State, memory: &Memory) -> OpCode {
-    let pc = state.pc;
-    let high = memory.read(pc);
-    let low = memory.read(pc + 1);
-    parse_opcode(high, low)
+/// Reads and decodes the instruction at an address, through the decode cache
+pub(crate) fn fetch_decoded(pc: u16, memory: &mut Memory) -> Result<(u16, OpCode), ExecError> {
+    if let Some(cached) = memory.decoded(pc) {
+        return Ok(cached);
+    }
+
+    let raw = fetch_opcode(pc, memory)?;
+    let opcode = decode_opcode(pc, memory, raw);
+    memory.cache_decoded(pc, raw, opcode);
+    Ok((raw, opcode))
 }
 
 pub fn delay_timer(state: &State) -> u8 {
@@ -61,27 +135,283 @@ impl State {
             pc: 0x200,
             stack_pointer: 0,
             i: 0,
-            draw_flag: true,
             run_flag: true,
-            clear_flag: true,
             last_opcode: OpCode::Unknown(0),
             opcode: None,
             width: w,
-            height: h
+            height: h,
+            hires: false,
+            rpl: [0; 16],
+            plane: 0x1,
+            audio_pattern: [0; 16],
+            pitch: 64,
+            quirks: Default::default(),
+            vblank: false,
+            random: Box::new(SeededRandom::default()),
+            key_wait: None
+        }
+    }
+
+    /// Executes the instruction at the program counter, updating the state in place.
+    /// 
+    /// If the instruction cannot be executed the error describes the fault and
+    /// the instruction that caused it, the state is not modified.
+    /// 
+    /// When the resolution changes the screen is resized and cleared.  Once the
+    /// program has exited the state is left unchanged.
+    /// 
+    /// FX0A forgets the keypad edges of the key it completes on, so a key
+    /// pressed and released once only completes one wait.
+    /// 
+    /// Each instruction is decoded once and cached in memory by its address,
+    /// until memory under it is written to.
+    pub fn execute(&mut self, memory: &mut Memory, keypad: &mut Keypad,
+        screen: &mut FrameBuffer) -> Result<(), ExecError> {
+        if !self.run_flag {
+            return Ok(());
         }
+
+        let (raw, opcode) = match self.opcode {
+            Some(code) => (fetch_opcode(self.pc, memory)?, code),
+            None => fetch_decoded(self.pc, memory)?
+        };
+
+        self.execute_decoded(memory, keypad, screen, raw, opcode)
+    }
+
+    /// Executes an instruction already fetched and decoded from the program counter
+    pub(crate) fn execute_decoded(&mut self, memory: &mut Memory, keypad: &mut Keypad,
+        screen: &mut FrameBuffer, raw: u16, opcode: OpCode) -> Result<(), ExecError> {
+        let pc = self.pc;
+        execute(self, memory, keypad, screen, opcode)
+            .map_err(|fault| fault.at(pc, raw))?;
+
+        if screen.width() != self.width || screen.height() != self.height {
+            screen.resize(self.width, self.height);
+        }
+
+        Ok(())
+    }
+
+    /// Executes the instruction at the program counter and returns the new state.
+    /// 
+    /// Consumes the state, see `execute` to update it in place instead.
+    pub fn step(self, memory: &mut Memory, keypad: &mut Keypad, 
+        screen: &mut FrameBuffer) -> Result<State, ExecError> {
+        let mut state = self;
+        state.execute(memory, keypad, screen)?;
+        Ok(state)
+    }
+
+    /// Creates a blank screen of the current resolution
+    pub fn create_buffer(&self) -> FrameBuffer {
+        FrameBuffer::new(self.width, self.height)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(state: State, program: &[u8]) -> Result<State, ExecError> {
+        let mut memory = Memory::new();
+        memory.set_range(usize::from(state.pc), program);
+        let mut screen = state.create_buffer();
+        state.step(&mut memory, &mut Keypad::new(), &mut screen)
+    }
+
+    #[test]
+    fn it_should_report_unknown_opcodes() {
+        let state: State = Default::default();
+
+        let err = run(state, &[0x00, 0x00]).unwrap_err();
+
+        assert_eq!(ExecError::UnknownOpCode { pc: 0x200, opcode: 0x0000 }, err);
+    }
+
+    #[test]
+    fn it_should_report_stack_overflow() {
+        let state = State {
+            stack_pointer: 16,
+            ..Default::default()
+        };
+
+        let err = run(state, &[0x23, 0x00]).unwrap_err();
+
+        assert_eq!(ExecError::StackOverflow { pc: 0x200, opcode: 0x2300 }, err);
+    }
+
+    #[test]
+    fn it_should_report_stack_underflow() {
+        let state: State = Default::default();
+
+        let err = run(state, &[0x00, 0xEE]).unwrap_err();
+
+        assert_eq!(ExecError::StackUnderflow { pc: 0x200, opcode: 0x00EE }, err);
+    }
+
+    #[test]
+    fn it_should_resize_the_screen_when_resolution_changes() {
+        let state: State = Default::default();
+        let mut memory = Memory::new();
+        memory.set_range(0x200, &[0x00, 0xFF, 0x00, 0xFE]);
+        let mut screen = state.create_buffer();
+
+        let state = state.step(&mut memory, &mut Keypad::new(), &mut screen).unwrap();
+        assert_eq!((128, 64), (screen.width(), screen.height()));
+
+        state.step(&mut memory, &mut Keypad::new(), &mut screen).unwrap();
+        assert_eq!((64, 32), (screen.width(), screen.height()));
+    }
+
+    #[test]
+    fn it_should_not_step_once_exited() {
+        let state: State = Default::default();
+        let mut memory = Memory::new();
+        memory.set_range(0x200, &[0x00, 0xFD]);
+        let mut screen = state.create_buffer();
+
+        let state = state.step(&mut memory, &mut Keypad::new(), &mut screen).unwrap();
+        let state = state.step(&mut memory, &mut Keypad::new(), &mut screen).unwrap();
+
+        assert_eq!(0x202, state.pc);
+        assert!(!state.run_flag);
+    }
+
+    #[test]
+    fn it_should_report_fetching_past_the_end_of_memory() {
+        let state = State {
+            pc: 0xFFF,
+            ..Default::default()
+        };
+
+        let err = run(state, &[0x12]).unwrap_err();
+
+        assert_eq!(ExecError::MemoryOutOfRange { pc: 0xFFF, opcode: 0x1200, address: 0x1000 }, err);
+    }
+
+    #[test]
+    fn it_should_execute_long_instructions() {
+        let state: State = Default::default();
+
+        let state = run(state, &[0xF0, 0x00, 0xAB, 0xCD]).unwrap();
+
+        assert_eq!(0xABCD, state.i);
+        assert_eq!(0x204, state.pc);
     }
 
-    pub fn step(self, memory: &mut Memory, keys: &[u8], 
-        screen: &mut Vec<u8>) -> State {
-        let opcode = match self.opcode {
-            None => get_opcode(&self, memory),
-            Some(code) => code
+    #[test]
+    fn it_should_report_fetching_long_instructions_past_the_end_of_memory() {
+        let state = State {
+            pc: 0xFFE,
+            ..Default::default()
         };
 
-        assemble(self, memory, keys, &mut screen[..], opcode)
+        let err = run(state, &[0xF0, 0x00]).unwrap_err();
+
+        assert_eq!(ExecError::MemoryOutOfRange { pc: 0xFFE, opcode: 0xF000, address: 0x1000 }, err);
     }
 
-    pub fn create_buffer(&self) -> Vec<u8> {
-        vec![0x0u8; (self.width * self.height) as usize]
+    #[test]
+    fn it_should_report_writing_past_the_end_of_memory() {
+        let state = State {
+            i: 0xFFE,
+            ..Default::default()
+        };
+
+        let err = run(state, &[0xF0, 0x33]).unwrap_err();
+
+        assert_eq!(ExecError::MemoryOutOfRange { pc: 0x200, opcode: 0xF033, address: 0x1000 }, err);
+    }
+
+    #[test]
+    fn it_should_execute_in_place() {
+        let mut state: State = Default::default();
+        let mut memory = Memory::new();
+        memory.set_range(0x200, &[0x60, 0x7B, 0x22, 0x08]);
+        let mut screen = state.create_buffer();
+
+        state.execute(&mut memory, &mut Keypad::new(), &mut screen).unwrap();
+        state.execute(&mut memory, &mut Keypad::new(), &mut screen).unwrap();
+
+        assert_eq!(0x7B, state.registers[0]);
+        assert_eq!(0x208, state.pc);
+        assert_eq!(0x204, state.stack[0]);
+        assert_eq!(OpCode::CALL(0x208), state.last_opcode);
+    }
+
+    #[test]
+    fn it_should_leave_the_state_and_screen_unchanged_on_a_fault() {
+        let mut state = State {
+            i: 0xFFE,
+            vblank: true,
+            ..Default::default()
+        };
+        state.registers[0xF] = 0x7;
+        let mut memory = Memory::new();
+        memory.set_range(0x200, &[0xD0, 0x04]);
+        memory.set_range(0xFFE, &[0xFF, 0xFF]);
+        let mut screen = state.create_buffer();
+
+        let err = state.execute(&mut memory, &mut Keypad::new(), &mut screen).unwrap_err();
+
+        assert_eq!(ExecError::MemoryOutOfRange { pc: 0x200, opcode: 0xD004, address: 0x1000 }, err);
+        assert_eq!(0x200, state.pc);
+        assert_eq!(0x7, state.registers[0xF]);
+        assert!(state.vblank);
+        assert!(screen.to_bytes().iter().all(|p| *p == 0));
+    }
+
+    #[test]
+    fn it_should_run_code_rewritten_by_the_program() {
+        let mut state = State { pc: 0x208, ..Default::default() };
+        let mut memory = Memory::new();
+        // LD V0, 0x71; LD I, 0x208; LD [I], V0; JP 0x208; ADD V0, 1
+        memory.set_range(0x200, &[0x60, 0x71, 0xA2, 0x08, 0xF0, 0x55, 0x12, 0x08, 0x70, 0x01]);
+        let mut screen = state.create_buffer();
+
+        state.execute(&mut memory, &mut Keypad::new(), &mut screen).unwrap();
+        assert_eq!(1, state.registers[0]);
+
+        state.pc = 0x200;
+        for _ in 0..5 {
+            state.execute(&mut memory, &mut Keypad::new(), &mut screen).unwrap();
+        }
+
+        // the ADD V0, 1 was rewritten to ADD V1, 1
+        assert_eq!(0x71, state.registers[0]);
+        assert_eq!(1, state.registers[1]);
+    }
+
+    #[test]
+    fn it_should_run_code_rewritten_by_the_frontend() {
+        let mut state: State = Default::default();
+        let mut memory = Memory::new();
+        memory.set_range(0x200, &[0x70, 0x01]);
+        let mut screen = state.create_buffer();
+
+        state.execute(&mut memory, &mut Keypad::new(), &mut screen).unwrap();
+        memory.set(0x201, 0x05);
+        state.pc = 0x200;
+        state.execute(&mut memory, &mut Keypad::new(), &mut screen).unwrap();
+
+        assert_eq!(6, state.registers[0]);
+    }
+
+    #[test]
+    fn it_should_run_a_long_load_rewritten_in_its_last_byte() {
+        let mut state: State = Default::default();
+        let mut memory = Memory::new();
+        memory.set_range(0x200, &[0xF0, 0x00, 0x12, 0x34]);
+        let mut screen = state.create_buffer();
+
+        state.execute(&mut memory, &mut Keypad::new(), &mut screen).unwrap();
+        assert_eq!(0x1234, state.i);
+
+        memory.set(0x203, 0x56);
+        state.pc = 0x200;
+        state.execute(&mut memory, &mut Keypad::new(), &mut screen).unwrap();
+
+        assert_eq!(0x1256, state.i);
     }
 }
@@ -0,0 +1,73 @@
+//! Switches for instructions that behave differently between interpreters.
+
+/// Selects how ambiguous instructions are executed.
+///
+/// The default profile keeps the behaviour this library has always had, the
+/// `cosmac_vip` and `schip` profiles match the interpreters most roms were
+/// written against.
+///
+/// Example:
+///
+/// ```
+/// # use lib_chip::state::{State, Quirks};
+/// let state = State {
+///     quirks: Quirks::cosmac_vip(),
+///     ..Default::default()
+/// };
+/// # assert!(state.quirks.shift_uses_vy);
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct Quirks {
+    /// 8xy6 and 8xyE shift Vy and store the result in Vx, rather than shifting Vx in place.
+    pub shift_uses_vy: bool,
+    /// Fx55 and Fx65 leave I pointing at the address after the last register.
+    pub load_store_increments_i: bool,
+    /// 8xy1, 8xy2 and 8xy3 reset VF to 0.
+    pub logic_resets_vf: bool,
+    /// Bnnn jumps to nnn plus Vx, where x is the highest nibble of nnn, rather than V0.
+    pub jump_uses_vx: bool,
+    /// Sprites drawn over the edge of the screen are clipped rather than wrapped.
+    pub clip_sprites: bool,
+    /// Dxyn waits for the start of the next frame before drawing.
+    ///
+    /// The frontend signals a new frame by setting `State::vblank`.
+    pub display_wait: bool
+}
+
+impl Quirks {
+    /// The behaviour of the original COSMAC VIP interpreter
+    pub fn cosmac_vip() -> Quirks {
+        Quirks {
+            shift_uses_vy: true,
+            load_store_increments_i: true,
+            logic_resets_vf: true,
+            jump_uses_vx: false,
+            clip_sprites: true,
+            display_wait: true
+        }
+    }
+
+    /// The behaviour of the SUPER-CHIP 1.1 interpreter on the HP48
+    pub fn schip() -> Quirks {
+        Quirks {
+            shift_uses_vy: false,
+            load_store_increments_i: false,
+            logic_resets_vf: false,
+            jump_uses_vx: true,
+            clip_sprites: true,
+            display_wait: false
+        }
+    }
+
+    /// The behaviour of XO-CHIP, as implemented by Octo
+    pub fn xo_chip() -> Quirks {
+        Quirks {
+            shift_uses_vy: true,
+            load_store_increments_i: true,
+            logic_resets_vf: false,
+            jump_uses_vx: false,
+            clip_sprites: false,
+            display_wait: false
+        }
+    }
+}
@@ -0,0 +1,391 @@
+//! Records every instruction executed, with the changes it made.
+//!
+//! A tracer wraps `State::step`.  Each instruction that runs produces a
+//! record of its address, opcode and the registers, I, stack and memory it
+//! changed, which is written as a line of text or JSON.  Two traces of the
+//! same run can be diffed to find where two versions of the core part ways.
+//!
+//! Only the registers and the bytes of memory an instruction can write are
+//! kept to compare, so tracing costs little more than running.
+//!
+//! # Example:
+//!
+//! ```
+//! # use lib_chip::keypad::Keypad;
+//! # use lib_chip::framebuffer::FrameBuffer;
+//! # use lib_chip::memory::Memory;
+//! # use lib_chip::state::State;
+//! # use lib_chip::trace::{Format, Tracer};
+//! let mut memory = Memory::new();
+//! memory.set_range(0x200, &[0x6A, 0x05]);
+//! let mut screen = FrameBuffer::new(64, 32);
+//!
+//! let mut tracer = Tracer::new(Vec::new(), Format::Text);
+//! tracer.step(State::default(), &mut memory, &mut Keypad::new(), &mut screen)?;
+//!
+//! let text = String::from_utf8(tracer.into_inner()).unwrap();
+//! assert_eq!("0 0x200 6A05 LD VA, 0x05 VA=00->05\n", text);
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::ops::{Range, RangeInclusive};
+use crate::debugger::{memory_access, next_opcode};
+use crate::disasm::mnemonic;
+use crate::framebuffer::FrameBuffer;
+use crate::keypad::Keypad;
+use crate::memory::Memory;
+use crate::opcode::{OpCode, LoadOp, SkipOp, AddOp};
+use crate::opcode::encoder::encode_opcode;
+use crate::state::{ExecError, State};
+
+/// The kind of work an instruction does, used to filter a trace
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Category {
+    /// Jumps, calls, returns and exit
+    Flow,
+    /// Skips that compare registers
+    Skip,
+    /// Loads into registers and I
+    Load,
+    /// Arithmetic, logic and shifts
+    Arithmetic,
+    Random,
+    /// Reads and writes of memory through I and the RPL flags
+    Memory,
+    /// The delay and sound timers and XO-CHIP audio
+    Timer,
+    /// Skips and waits on the keypad
+    Key,
+    /// Drawing, clearing, scrolling and changes of resolution or plane
+    Display,
+    Unknown,
+}
+
+impl Category {
+    pub fn of(opcode: OpCode) -> Category {
+        match opcode {
+            OpCode::Unknown(_) => Category::Unknown,
+            OpCode::RET | OpCode::JP(_) | OpCode::CALL(_) | OpCode::EXIT => Category::Flow,
+            OpCode::SKIP(SkipOp::SKP(_)) | OpCode::SKIP(SkipOp::SKNP(_)) => Category::Key,
+            OpCode::SKIP(_) => Category::Skip,
+            OpCode::ADD(AddOp::ADDI(_)) => Category::Load,
+            OpCode::ADD(_) | OpCode::SUB(..) | OpCode::SUBN(..) | OpCode::OR(..)
+                | OpCode::AND(..) | OpCode::XOR(..) | OpCode::SHIFT(_) => Category::Arithmetic,
+            OpCode::RND(..) => Category::Random,
+            OpCode::CLS | OpCode::DRW(..) | OpCode::SCROLL(_) | OpCode::LOW
+                | OpCode::HIGH | OpCode::PLANE(_) => Category::Display,
+            OpCode::AUDIO => Category::Timer,
+            OpCode::LD(op) => match op {
+                LoadOp::LDKEY(_) => Category::Key,
+                LoadOp::LDVXDT(_) | LoadOp::LDDTVX(_) | LoadOp::LDSTVX(_)
+                    | LoadOp::LDPITCH(_) => Category::Timer,
+                LoadOp::LDB(_) | LoadOp::LDIV0X(_) | LoadOp::LDV0XI(_) | LoadOp::LDIVXY(..)
+                    | LoadOp::LDVXYI(..) | LoadOp::LDRVX(_) | LoadOp::LDVXR(_) => Category::Memory,
+                LoadOp::LD(..) | LoadOp::LDI(_) | LoadOp::LDIL(_) | LoadOp::LDXY(..)
+                    | LoadOp::LDF(_) | LoadOp::LDHF(_) => Category::Load
+            }
+        }
+    }
+}
+
+/// Selects the instructions that are traced, every instruction by default.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Filter {
+    /// Only trace instructions at these addresses
+    pub addresses: Option<RangeInclusive<u16>>,
+    /// Only trace instructions of these categories
+    pub categories: Option<Vec<Category>>,
+}
+
+impl Filter {
+    pub fn matches(&self, pc: u16, opcode: OpCode) -> bool {
+        let address = self.addresses.as_ref().map(|range| range.contains(&pc)).unwrap_or(true);
+        let category = self.categories.as_ref()
+            .map(|categories| categories.contains(&Category::of(opcode)))
+            .unwrap_or(true);
+        address && category
+    }
+}
+
+/// How records are written
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Format {
+    /// One line of text per instruction
+    Text,
+    /// One JSON object per line
+    JsonLines,
+}
+
+/// An instruction that was executed and what it changed
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    /// The number of instructions executed before this one
+    pub index: u64,
+    pub pc: u16,
+    /// The first 16 bits of the instruction
+    pub opcode: u16,
+    pub decoded: OpCode,
+    /// The registers that changed, with their old and new values
+    pub registers: Vec<(u8, u8, u8)>,
+    /// The old and new values of I, if it changed
+    pub i: Option<(u16, u16)>,
+    /// The addresses on the stack after the instruction, if it changed
+    pub stack: Option<Vec<u16>>,
+    /// The bytes of memory that changed, with their old and new values
+    pub memory: Vec<(usize, u8, u8)>,
+}
+
+/// The parts of the machine an instruction can change that are recorded
+struct Snapshot {
+    pc: u16,
+    registers: [u8; 16],
+    i: u16,
+    stack: [u16; 16],
+    stack_pointer: u16,
+    /// The bytes the instruction can write to and their values
+    writes: Range<usize>,
+    memory: Vec<u8>,
+}
+
+impl Snapshot {
+    fn before(state: &State, memory: &Memory) -> Snapshot {
+        let (_, len) = next_opcode(state, memory)
+            .map(|opcode| memory_access(state, opcode))
+            .unwrap_or((0, 0));
+        let start = usize::from(state.i).min(memory.size());
+        let writes = start..(start + len).min(memory.size());
+
+        Snapshot {
+            pc: state.pc,
+            registers: state.registers,
+            i: state.i,
+            stack: state.stack,
+            stack_pointer: state.stack_pointer,
+            memory: memory.read_all()[writes.clone()].to_vec(),
+            writes,
+        }
+    }
+}
+
+impl Record {
+    /// Compares the machine before and after an instruction
+    fn between(index: u64, before: &Snapshot, after: &State, memory: &Memory) -> Record {
+        let registers = (0..16u8)
+            .map(|v| (v, before.registers[usize::from(v)], after.registers[usize::from(v)]))
+            .filter(|(_, old, new)| old != new)
+            .collect();
+
+        let stack_changed = before.stack_pointer != after.stack_pointer || before.stack != after.stack;
+        let depth = usize::from(after.stack_pointer).min(after.stack.len());
+
+        let written = &memory.read_all()[before.writes.clone()];
+        let memory = before.memory.iter().zip(written.iter()).enumerate()
+            .filter(|(_, (old, new))| old != new)
+            .map(|(offset, (old, new))| (before.writes.start + offset, *old, *new))
+            .collect();
+
+        Record {
+            index,
+            pc: before.pc,
+            opcode: encode_opcode(after.last_opcode),
+            decoded: after.last_opcode,
+            registers,
+            i: if before.i != after.i { Some((before.i, after.i)) } else { None },
+            stack: if stack_changed { Some(after.stack[..depth].to_vec()) } else { None },
+            memory,
+        }
+    }
+
+    /// Formats the record as a line of text, without the line ending
+    pub fn to_text(&self) -> String {
+        let mut line = format!("{} 0x{:03X} {:04X} {}", self.index, self.pc, self.opcode, mnemonic(self.decoded));
+        for (v, old, new) in self.registers.iter() {
+            let _ = write!(line, " V{:X}={:02X}->{:02X}", v, old, new);
+        }
+        if let Some((old, new)) = self.i {
+            let _ = write!(line, " I={:03X}->{:03X}", old, new);
+        }
+        if let Some(stack) = &self.stack {
+            let addresses: Vec<String> = stack.iter().map(|a| format!("{:03X}", a)).collect();
+            let _ = write!(line, " stack=[{}]", addresses.join(","));
+        }
+        for (address, old, new) in self.memory.iter() {
+            let _ = write!(line, " [{:03X}]={:02X}->{:02X}", address, old, new);
+        }
+        line
+    }
+
+    /// Formats the record as a JSON object, without the line ending
+    pub fn to_json(&self) -> String {
+        let registers: Vec<String> = self.registers.iter()
+            .map(|(v, old, new)| format!("{{\"v\":{},\"old\":{},\"new\":{}}}", v, old, new))
+            .collect();
+        let memory: Vec<String> = self.memory.iter()
+            .map(|(address, old, new)| format!("{{\"address\":{},\"old\":{},\"new\":{}}}", address, old, new))
+            .collect();
+
+        let mut json = format!("{{\"index\":{},\"pc\":{},\"opcode\":{},\"instruction\":\"{}\",\"registers\":[{}]",
+            self.index, self.pc, self.opcode, mnemonic(self.decoded), registers.join(","));
+        if let Some((old, new)) = self.i {
+            let _ = write!(json, ",\"i\":{{\"old\":{},\"new\":{}}}", old, new);
+        }
+        if let Some(stack) = &self.stack {
+            let addresses: Vec<String> = stack.iter().map(|a| a.to_string()).collect();
+            let _ = write!(json, ",\"stack\":[{}]", addresses.join(","));
+        }
+        let _ = write!(json, ",\"memory\":[{}]}}", memory.join(","));
+        json
+    }
+}
+
+/// Steps states and writes a record of each instruction.
+///
+/// Errors writing the trace are kept until `take_error` is called, so a
+/// failing writer never stops the program.
+pub struct Tracer<W: Write> {
+    writer: W,
+    format: Format,
+    filter: Filter,
+    executed: u64,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> Tracer<W> {
+    pub fn new(writer: W, format: Format) -> Tracer<W> {
+        Tracer::with_filter(writer, format, Default::default())
+    }
+
+    pub fn with_filter(writer: W, format: Format, filter: Filter) -> Tracer<W> {
+        Tracer { writer, format, filter, executed: 0, error: None }
+    }
+
+    /// Executes an instruction like `State::execute`, tracing it if it matches the filter.
+    pub fn execute(&mut self, state: &mut State, memory: &mut Memory, keypad: &mut Keypad,
+        screen: &mut FrameBuffer) -> Result<(), ExecError> {
+        if !state.run_flag {
+            return Ok(());
+        }
+
+        let before = Snapshot::before(state, memory);
+        state.execute(memory, keypad, screen)?;
+
+        if self.filter.matches(before.pc, state.last_opcode) {
+            let record = Record::between(self.executed, &before, state, memory);
+            self.write(&record);
+        }
+        self.executed += 1;
+
+        Ok(())
+    }
+
+    /// Executes an instruction like `State::step`, tracing it if it matches the filter.
+    pub fn step(&mut self, state: State, memory: &mut Memory, keypad: &mut Keypad,
+        screen: &mut FrameBuffer) -> Result<State, ExecError> {
+        let mut state = state;
+        self.execute(&mut state, memory, keypad, screen)?;
+        Ok(state)
+    }
+
+    fn write(&mut self, record: &Record) {
+        if self.error.is_some() {
+            return;
+        }
+
+        let line = match self.format {
+            Format::Text => record.to_text(),
+            Format::JsonLines => record.to_json()
+        };
+        if let Err(err) = writeln!(self.writer, "{}", line) {
+            self.error = Some(err);
+        }
+    }
+
+    /// Returns the number of instructions executed, traced or not
+    pub fn executed(&self) -> u64 {
+        self.executed
+    }
+
+    /// Returns the first error writing the trace, if there was one
+    pub fn take_error(&mut self) -> Option<io::Error> {
+        self.error.take()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trace(program: &[u8], steps: usize, format: Format, filter: Filter) -> String {
+        let mut memory = Memory::new();
+        memory.set_range(0x200, program);
+        let mut state: State = Default::default();
+        let mut screen = state.create_buffer();
+        let mut tracer = Tracer::with_filter(Vec::new(), format, filter);
+
+        for _ in 0..steps {
+            state = tracer.step(state, &mut memory, &mut Keypad::new(), &mut screen).unwrap();
+        }
+        String::from_utf8(tracer.into_inner()).unwrap()
+    }
+
+    /// LD V0, 0x7B; LD I, 0x300; LD [I], V0; CALL 0x20A; ...; RET at 0x20A
+    const PROGRAM: [u8; 12] = [0x60, 0x7B, 0xA3, 0x00, 0xF0, 0x55, 0x22, 0x0A, 0x12, 0x08, 0x00, 0xEE];
+
+    #[test]
+    fn it_will_trace_changes_as_text() {
+        let text = trace(&PROGRAM, 5, Format::Text, Default::default());
+
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(vec![
+            "0 0x200 607B LD V0, 0x7B V0=00->7B",
+            "1 0x202 A300 LD I, 0x300 I=000->300",
+            "2 0x204 F055 LD [I], V0 [300]=00->7B",
+            "3 0x206 220A CALL 0x20A stack=[208]",
+            "4 0x20A 00EE RET stack=[]",
+        ], lines);
+    }
+
+    #[test]
+    fn it_will_trace_every_byte_an_instruction_writes() {
+        // LD V0, 0x7B; LD I, 0x300; LD B, V0; SAVE V0, V1
+        let program = [0x60, 0x7B, 0xA3, 0x00, 0xF0, 0x33, 0x50, 0x12];
+        let text = trace(&program, 4, Format::Text, Default::default());
+
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!("2 0x204 F033 LD B, V0 [300]=00->01 [301]=00->02 [302]=00->03", lines[2]);
+        assert_eq!("3 0x206 5012 SAVE V0, V1 [300]=01->7B [301]=02->00", lines[3]);
+    }
+
+    #[test]
+    fn it_will_trace_changes_as_json() {
+        let text = trace(&PROGRAM, 4, Format::JsonLines, Default::default());
+
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(r#"{"index":0,"pc":512,"opcode":24699,"instruction":"LD V0, 0x7B","registers":[{"v":0,"old":0,"new":123}],"memory":[]}"#, lines[0]);
+        assert_eq!(r#"{"index":3,"pc":518,"opcode":8714,"instruction":"CALL 0x20A","registers":[],"stack":[520],"memory":[]}"#, lines[3]);
+    }
+
+    #[test]
+    fn it_will_filter_by_address_and_category() {
+        let by_address = Filter { addresses: Some(0x202..=0x204), ..Default::default() };
+        let by_category = Filter { categories: Some(vec![Category::Flow]), ..Default::default() };
+
+        let text = trace(&PROGRAM, 5, Format::Text, by_address);
+        assert_eq!(vec!["1", "2"], text.lines().map(|l| &l[..1]).collect::<Vec<_>>());
+
+        let text = trace(&PROGRAM, 5, Format::Text, by_category);
+        assert_eq!(vec!["3", "4"], text.lines().map(|l| &l[..1]).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn it_will_categorise_opcodes() {
+        assert_eq!(Category::Key, Category::of(OpCode::LD(LoadOp::LDKEY(0x1))));
+        assert_eq!(Category::Memory, Category::of(OpCode::LD(LoadOp::LDIV0X(0x1))));
+        assert_eq!(Category::Display, Category::of(OpCode::DRW(0x1, 0x2, 0x3)));
+    }
+}
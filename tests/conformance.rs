@@ -0,0 +1,142 @@
+//! Runs test roms headlessly and compares their final screens with golden images.
+//!
+//! The roms in `tests/roms` are assembled from source with `common.asm`
+//! appended, and each golden image is the screen drawn with
+//! `export::to_ascii`.  The binary roms of Timendus' community test suite
+//! belong in `tests/roms/community`, see the README there.  Until they are
+//! vendored their test is ignored, run it with `cargo test -- --ignored`.
+//!
+//! Run with `UPDATE_GOLDEN=1` to write the golden images after an intended
+//! change to what a rom draws.
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use lib_chip::asm::assemble;
+use lib_chip::export::to_ascii;
+use lib_chip::machine::{Config, Machine};
+use lib_chip::rom::Rom;
+use lib_chip::state::Quirks;
+
+/// Keys held from a frame onwards, one bit per key
+type KeyScript = [(u32, u16)];
+
+fn roms_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("roms")
+}
+
+fn assemble_rom(name: &str) -> Rom {
+    let dir = roms_dir();
+    let source = fs::read_to_string(dir.join(format!("{}.asm", name))).unwrap();
+    let common = fs::read_to_string(dir.join("common.asm")).unwrap();
+    assemble(&format!("{}\n{}", source, common))
+        .unwrap_or_else(|error| panic!("{}.asm does not assemble: {}", name, error))
+}
+
+fn run(rom: &Rom, quirks: Quirks, frames: u32, keys: &KeyScript) -> Machine {
+    let mut machine = Machine::new(Config { quirks, ..Default::default() });
+    machine.load_rom(rom).unwrap();
+
+    for frame in 0..frames {
+        if let Some((_, pressed)) = keys.iter().rev().find(|(from, _)| *from <= frame) {
+            machine.keypad_mut().set_pressed(*pressed);
+        }
+        machine.run_frame().unwrap_or_else(|error| panic!("failed on frame {}: {:?}", frame, error));
+    }
+    machine
+}
+
+fn screen(machine: &Machine) -> String {
+    let screen = machine.screen();
+    to_ascii(&screen.to_bytes(), screen.width(), screen.height())
+}
+
+/// Compares a screen with its golden image, or writes the image when updating.
+fn assert_golden(path: PathBuf, actual: &str) {
+    if env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::write(&path, actual).unwrap();
+        return;
+    }
+
+    let expected = fs::read_to_string(&path)
+        .unwrap_or_else(|_| panic!("{} is missing, run with UPDATE_GOLDEN=1 to write it", path.display()));
+    assert!(expected == actual, "the screen does not match {}, it drew:\n{}", path.display(), actual);
+}
+
+/// Runs a rom that marks each check, asserting none failed and the screen is unchanged.
+fn assert_checks_pass(name: &str, quirks: Quirks, frames: u32, keys: &KeyScript) {
+    let machine = run(&assemble_rom(name), quirks, frames, keys);
+    let actual = screen(&machine);
+
+    assert_eq!(0, machine.state().registers[0xE], "{} failed checks, it drew:\n{}", name, actual);
+    assert_golden(roms_dir().join(format!("{}.txt", name)), &actual);
+}
+
+#[test]
+fn it_will_draw_the_logo() {
+    let machine = run(&assemble_rom("logo"), Quirks::default(), 200, &[]);
+    assert_golden(roms_dir().join("logo.txt"), &screen(&machine));
+}
+
+#[test]
+fn it_will_pass_the_opcode_checks() {
+    assert_checks_pass("opcodes", Quirks::default(), 200, &[]);
+}
+
+#[test]
+fn it_will_pass_the_flag_checks() {
+    assert_checks_pass("flags", Quirks::default(), 200, &[]);
+}
+
+#[test]
+fn it_will_pass_the_keypad_checks() {
+    let keys = [(5, 1 << 0xA), (7, 0), (10, 1 << 0x3), (12, 0), (20, 1 << 0x5)];
+    assert_checks_pass("keypad", Quirks::default(), 200, &keys);
+}
+
+#[test]
+fn it_will_show_the_quirks_of_each_profile() {
+    let rom = assemble_rom("quirks");
+    let profiles = [
+        ("default", Quirks::default()),
+        ("cosmac_vip", Quirks::cosmac_vip()),
+        ("schip", Quirks::schip()),
+        ("xo_chip", Quirks::xo_chip()),
+    ];
+
+    for (name, quirks) in profiles.iter() {
+        // the cosmac_vip profile draws once per frame
+        let machine = run(&rom, *quirks, 200, &[]);
+        assert_golden(roms_dir().join(format!("quirks_{}.txt", name)), &screen(&machine));
+    }
+}
+
+/// The community roms, the frames to run them for and the keys to press.
+///
+/// The quirks and keypad roms start with a menu, where key 1 picks the chip8
+/// platform or the first test.
+const COMMUNITY_ROMS: [(&str, u32, &KeyScript); 6] = [
+    ("1-chip8-logo", 60, &[]),
+    ("2-ibm-logo", 60, &[]),
+    ("3-corax+", 60, &[]),
+    ("4-flags", 60, &[]),
+    ("5-quirks", 300, &[(30, 1 << 0x1), (35, 0)]),
+    ("6-keypad", 120, &[(30, 1 << 0x1), (35, 0)]),
+];
+
+#[test]
+#[ignore = "the community roms are not vendored yet, see tests/roms/community/README.md"]
+fn it_will_match_the_community_roms() {
+    let dir = roms_dir().join("community");
+    let missing: Vec<&str> = COMMUNITY_ROMS.iter()
+        .map(|(name, _, _)| *name)
+        .filter(|name| !dir.join(format!("{}.ch8", name)).exists())
+        .collect();
+    assert!(missing.is_empty(), "{:?} are missing from {}, see the README there", missing, dir.display());
+
+    for (name, frames, keys) in COMMUNITY_ROMS.iter() {
+        let path = dir.join(format!("{}.ch8", name));
+        let rom = Rom::load(path.to_str().unwrap()).unwrap();
+        let machine = run(&rom, Quirks::cosmac_vip(), *frames, keys);
+        assert_golden(dir.join(format!("{}.txt", name)), &screen(&machine));
+    }
+}
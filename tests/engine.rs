@@ -0,0 +1,182 @@
+//! Checks the block engine changes the machine exactly as the interpreter does.
+//!
+//! Random programs are run side by side by both engines, comparing save
+//! states, clocks and sound after every frame.  The programs are made mostly
+//! of valid instructions whose jumps and I loads point into the program, so
+//! they run for a while and write over their own code.
+use lib_chip::audio::AudioConfig;
+use lib_chip::engine::{BlockEngine, Engine};
+use lib_chip::framebuffer::FrameBuffer;
+use lib_chip::keypad::Keypad;
+use lib_chip::machine::{Config, Machine};
+use lib_chip::memory::Memory;
+use lib_chip::rom::Rom;
+use lib_chip::savestate;
+use lib_chip::state::{ExecError, Quirks, State};
+
+const PROGRAMS: u64 = 200;
+const FRAMES: u32 = 60;
+/// The runs of up to 40 instructions given to the block engine directly
+const RUNS: u32 = 100;
+/// The instructions in each program
+const PROGRAM_LENGTH: u16 = 128;
+
+/// A xorshift generator, so the programs are the same every run
+struct Random(u64);
+
+impl Random {
+    fn new(seed: u64) -> Random {
+        Random(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: u64) -> u16 {
+        (self.next() % n) as u16
+    }
+
+    fn pick(&mut self, values: &[u16]) -> u16 {
+        values[usize::from(self.below(values.len() as u64))]
+    }
+}
+
+fn random_instruction(random: &mut Random) -> u16 {
+    let x = random.below(16) << 8;
+    let y = random.below(16) << 4;
+    let kk = random.below(256);
+    let target = 0x200 + 2 * random.below(u64::from(PROGRAM_LENGTH));
+    let data = 0x200 + random.below(0x200);
+
+    match random.below(32) {
+        0..=3 => 0x6000 | x | kk,
+        4..=7 => 0x7000 | x | kk,
+        8..=13 => 0x8000 | x | y | random.pick(&[0x0, 0x1, 0x2, 0x3, 0x4, 0x5, 0x6, 0x7, 0xE]),
+        14 => 0x3000 | x | kk,
+        15 => 0x4000 | x | kk,
+        16 => 0x5000 | x | y,
+        17 => 0x9000 | x | y,
+        18 | 19 => 0xA000 | data,
+        20 | 21 => 0x1000 | target,
+        22 => 0x2000 | target,
+        23 => 0x00EE,
+        24 => 0xC000 | x | kk,
+        25 => 0xD000 | x | y | random.below(16),
+        26 => x | random.pick(&[0xE09E, 0xE0A1]),
+        27 | 28 => 0xF000 | x | random.pick(&[0x07, 0x15, 0x18, 0x1E, 0x29, 0x33, 0x55, 0x65, 0x0A]),
+        29 => random.pick(&[0x00E0, 0x00FB, 0x00FC, 0x00FE, 0x00FF, 0x00C3, 0x00FD]),
+        30 => 0xB000 | target,
+        _ => random.next() as u16
+    }
+}
+
+fn random_program(random: &mut Random) -> Vec<u8> {
+    (0..PROGRAM_LENGTH)
+        .flat_map(|_| random_instruction(random).to_be_bytes().to_vec())
+        .collect()
+}
+
+fn random_quirks(random: &mut Random) -> Quirks {
+    match random.below(4) {
+        0 => Quirks::default(),
+        1 => Quirks::cosmac_vip(),
+        2 => Quirks::schip(),
+        _ => Quirks::xo_chip()
+    }
+}
+
+fn machine(rom: &Rom, config: Config, engine: Engine) -> Machine {
+    let mut machine = Machine::new(Config { engine, ..config });
+    machine.load_rom(rom).unwrap();
+    machine
+}
+
+/// Everything about the machine a program can change
+fn snapshot(machine: &mut Machine) -> (Vec<u8>, u64, u64, Vec<i16>) {
+    let samples = machine.beeper_mut().map(|beeper| beeper.take_samples()).unwrap_or_default();
+    let saved = savestate::save(machine.state(), machine.memory(), machine.screen());
+    (saved, machine.clock().instructions(), machine.clock().frames(), samples)
+}
+
+#[test]
+fn it_will_run_random_programs_as_the_interpreter_does() {
+    for seed in 0..PROGRAMS {
+        let mut random = Random::new(seed);
+        let rom = Rom::from_memory(random_program(&mut random));
+        let config = Config {
+            quirks: random_quirks(&mut random),
+            instructions_per_second: random.pick(&[97, 600, 1000, 3000]).into(),
+            audio: Some(AudioConfig { sample_rate: 4000, ..Default::default() }),
+            ..Default::default()
+        };
+        let mut interpreter = machine(&rom, config, Engine::Interpreter);
+        let mut blocks = machine(&rom, config, Engine::Blocks);
+
+        for frame in 0..FRAMES {
+            let keys = random.next() as u16 & random.next() as u16;
+            interpreter.keypad_mut().set_pressed(keys);
+            blocks.keypad_mut().set_pressed(keys);
+
+            let expected = interpreter.run_frame();
+            let actual = blocks.run_frame();
+            assert_eq!(expected, actual, "program {} differs on frame {}", seed, frame);
+            assert!(snapshot(&mut interpreter) == snapshot(&mut blocks),
+                "program {} differs after frame {}", seed, frame);
+
+            if expected.is_err() {
+                break;
+            }
+        }
+    }
+}
+
+/// Runs `count` instructions one at a time, as the block engine counts them
+fn interpret(state: &mut State, memory: &mut Memory, screen: &mut FrameBuffer, keypad: &mut Keypad,
+    count: u64) -> Result<u64, ExecError> {
+    for ran in 0..count {
+        if let Err(err) = state.execute(memory, keypad, screen) {
+            return if ran == 0 { Err(err) } else { Ok(ran) };
+        }
+    }
+    Ok(count)
+}
+
+#[test]
+fn it_will_run_random_programs_for_any_number_of_instructions() {
+    for seed in 0..PROGRAMS {
+        let mut random = Random::new(seed ^ 0xB10C);
+        let program = random_program(&mut random);
+        let mut keypad = Keypad::new();
+
+        let mut memory = Memory::new();
+        memory.set_range(0x200, &program);
+        let mut state: State = Default::default();
+        let mut screen = state.create_buffer();
+
+        let mut engine_memory = Memory::new();
+        engine_memory.set_range(0x200, &program);
+        let mut engine_state: State = Default::default();
+        let mut engine_screen = engine_state.create_buffer();
+        let mut engine = BlockEngine::new();
+
+        for run in 0..RUNS {
+            let limit = u64::from(random.below(40)) + 1;
+            let ran = engine.run(&mut engine_state, &mut engine_memory, &mut keypad, &mut engine_screen, limit);
+            // a failed run should fail on its first instruction
+            let count = *ran.as_ref().unwrap_or(&1);
+            let expected = interpret(&mut state, &mut memory, &mut screen, &mut keypad, count);
+
+            assert_eq!(expected, ran, "program {} differs on run {}", seed, run);
+            assert!(savestate::save(&state, &memory, &screen) == savestate::save(&engine_state, &engine_memory, &engine_screen),
+                "program {} differs after run {}", seed, run);
+
+            if ran.is_err() {
+                break;
+            }
+        }
+    }
+}
@@ -0,0 +1,33 @@
+; Shared by the conformance roms, which are assembled with this file appended.
+;
+; CALL check compares the result in V0 with the expected value in V1, drawing
+; a tick when they match and a cross when they do not.  VA and VB hold the
+; position of the next mark and VE counts the failures.
+check:
+    SE V0, V1
+    JP check_fail
+    LD I, check_tick
+    JP check_draw
+check_fail:
+    LD I, check_cross
+    ADD VE, 1
+check_draw:
+    DRW VA, VB, 4
+    ADD VA, 5
+    SE VA, 61
+    RET
+    LD VA, 1
+    ADD VB, 6
+    RET
+
+; CALL digit draws V0 with the built in font and moves along
+digit:
+    LD F, V0
+    DRW VA, VB, 5
+    ADD VA, 5
+    RET
+
+check_tick:
+    db 0b0001_0000, 0b0001_0000, 0b1010_0000, 0b0100_0000
+check_cross:
+    db 0b1010_0000, 0b0100_0000, 0b1010_0000, 0b0000_0000
@@ -0,0 +1,125 @@
+; Checks the result and VF of the arithmetic instructions, in the spirit of
+; the flags test.
+;
+; Each case draws two marks, the first for the result and the second for VF.
+    LD VA, 1
+    LD VB, 1
+    LD VE, 0
+
+; 8XY4 without and with a carry
+    LD V0, 0x10
+    LD V2, 0x20
+    ADD V0, V2
+    LD V3, VF
+    LD V1, 0x30
+    LD V4, 0
+    CALL check_both
+    LD V0, 0xFF
+    LD V2, 0x02
+    ADD V0, V2
+    LD V3, VF
+    LD V1, 0x01
+    LD V4, 1
+    CALL check_both
+
+; 8XY5 without a borrow, with equal values and with a borrow
+    LD V0, 0x30
+    LD V2, 0x10
+    SUB V0, V2
+    LD V3, VF
+    LD V1, 0x20
+    LD V4, 1
+    CALL check_both
+    LD V0, 0x30
+    LD V2, 0x30
+    SUB V0, V2
+    LD V3, VF
+    LD V1, 0x00
+    LD V4, 1
+    CALL check_both
+    LD V0, 0x10
+    LD V2, 0x30
+    SUB V0, V2
+    LD V3, VF
+    LD V1, 0xE0
+    LD V4, 0
+    CALL check_both
+
+; 8XY7 without and with a borrow
+    LD V0, 0x10
+    LD V2, 0x30
+    SUBN V0, V2
+    LD V3, VF
+    LD V1, 0x20
+    LD V4, 1
+    CALL check_both
+    LD V0, 0x30
+    LD V2, 0x10
+    SUBN V0, V2
+    LD V3, VF
+    LD V1, 0xE0
+    LD V4, 0
+    CALL check_both
+
+; 8XY6 shifting out a one and a zero
+    LD V0, 0x05
+    SHR V0
+    LD V3, VF
+    LD V1, 0x02
+    LD V4, 1
+    CALL check_both
+    LD V0, 0x04
+    SHR V0
+    LD V3, VF
+    LD V1, 0x02
+    LD V4, 0
+    CALL check_both
+
+; 8XYE shifting out a one and a zero
+    LD V0, 0x81
+    SHL V0
+    LD V3, VF
+    LD V1, 0x02
+    LD V4, 1
+    CALL check_both
+    LD V0, 0x41
+    SHL V0
+    LD V3, VF
+    LD V1, 0x82
+    LD V4, 0
+    CALL check_both
+
+; With VF as the destination the flag is written last
+    LD VF, 0xFF
+    LD V2, 0x02
+    ADD VF, V2
+    LD V0, VF
+    LD V1, 1
+    CALL check
+    LD VF, 0x10
+    LD V2, 0x30
+    SUB VF, V2
+    LD V0, VF
+    LD V1, 0
+    CALL check
+    LD VF, 0x02
+    SHR VF
+    LD V0, VF
+    LD V1, 0
+    CALL check
+    LD VF, 0x80
+    SHL VF
+    LD V0, VF
+    LD V1, 1
+    CALL check
+
+end:
+    JP end
+
+; Checks the result in V0 against V1, then the flag in V3 against V4
+check_both:
+    CALL check
+    LD V0, V3
+    LD V1, V4
+    CALL check
+    RET
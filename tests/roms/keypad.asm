@@ -0,0 +1,28 @@
+; Draws the keys typed, in the spirit of the keypad test.
+;
+; Waits for two keys with FX0A, drawing each as a digit once released, then
+; checks EXA1 skips while key 5 is up and EX9E skips once it is held.
+    LD VA, 1
+    LD VB, 1
+    LD VE, 0
+
+    LD V0, K
+    CALL digit
+    LD V0, K
+    CALL digit
+
+    LD V2, 5
+    LD V0, 1
+    SKNP V2
+    LD V0, 0
+    LD V1, 1
+    CALL check
+
+wait:
+    SKP V2
+    JP wait
+    LD V0, 1
+    CALL check
+
+end:
+    JP end
@@ -0,0 +1,37 @@
+; Draws a logo and the built in font, in the spirit of the IBM logo rom.
+;
+; Exercises CLS, LD I, LD F, DRW with sprites of different heights and wrapping
+; a row of digits across the screen.
+    CLS
+    LD VA, 1
+    LD VB, 1
+    LD I, logo_c
+    LD V2, 20
+    LD V3, 2
+    DRW V2, V3, 8
+    LD I, logo_8
+    LD V2, 30
+    DRW V2, V3, 8
+
+    LD VA, 1
+    LD VB, 14
+    LD V0, 0
+font:
+    CALL digit
+    SE VA, 41
+    JP next
+    LD VA, 1
+    ADD VB, 7
+next:
+    ADD V0, 1
+    SE V0, 16
+    JP font
+end:
+    JP end
+
+logo_c:
+    db 0b0111_1110, 0b1111_1111, 0b1100_0000, 0b1100_0000
+    db 0b1100_0000, 0b1100_0000, 0b1111_1111, 0b0111_1110
+logo_8:
+    db 0b0111_1110, 0b1100_0011, 0b1100_0011, 0b0111_1110
+    db 0b1100_0011, 0b1100_0011, 0b1100_0011, 0b0111_1110
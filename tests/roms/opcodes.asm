@@ -0,0 +1,168 @@
+; Checks each instruction, in the spirit of the corax+ opcode test.
+;
+; Every check draws a tick or a cross, see common.asm.
+    LD VA, 1
+    LD VB, 1
+    LD VE, 0
+    LD V2, 0x42
+    LD V3, 0x42
+    LD V4, 0x07
+
+; 3XNN skips when equal
+    LD V0, 1
+    SE V2, 0x42
+    LD V0, 0
+    LD V1, 1
+    CALL check
+; 4XNN skips when not equal
+    LD V0, 1
+    SNE V2, 0x41
+    LD V0, 0
+    CALL check
+; 5XY0
+    LD V0, 1
+    SE V2, V3
+    LD V0, 0
+    CALL check
+; 9XY0
+    LD V0, 1
+    SNE V2, V4
+    LD V0, 0
+    CALL check
+; 3XNN does not skip when different
+    LD V0, 0
+    SE V2, 0x41
+    LD V0, 1
+    CALL check
+
+; 7XNN wraps
+    LD V0, 0xFF
+    ADD V0, 2
+    LD V1, 0x01
+    CALL check
+; 8XY0
+    LD V0, V4
+    LD V1, 0x07
+    CALL check
+; 8XY1
+    LD V0, 0x0F
+    LD V5, 0xF0
+    OR V0, V5
+    LD V1, 0xFF
+    CALL check
+; 8XY2
+    LD V0, 0x3C
+    LD V5, 0x0F
+    AND V0, V5
+    LD V1, 0x0C
+    CALL check
+; 8XY3
+    LD V0, 0x3C
+    XOR V0, V5
+    LD V1, 0x33
+    CALL check
+; 8XY4
+    LD V0, 0x10
+    LD V5, 0x20
+    ADD V0, V5
+    LD V1, 0x30
+    CALL check
+; 8XY5
+    LD V0, 0x30
+    LD V5, 0x10
+    SUB V0, V5
+    LD V1, 0x20
+    CALL check
+; 8XY7
+    LD V0, 0x10
+    LD V5, 0x30
+    SUBN V0, V5
+    LD V1, 0x20
+    CALL check
+; 8XY6
+    LD V0, 0x0A
+    SHR V0
+    LD V1, 0x05
+    CALL check
+; 8XYE
+    LD V0, 0x05
+    SHL V0
+    LD V1, 0x0A
+    CALL check
+
+; ANNN and FX1E
+    LD I, data
+    LD V5, 1
+    ADD I, V5
+    LD V0, [I]
+    LD V1, 0x22
+    CALL check
+; FX55 and FX65
+    LD I, scratch
+    LD V0, 0x5A
+    LD [I], V0
+    LD V0, 0
+    LD I, scratch
+    LD V0, [I]
+    LD V1, 0x5A
+    CALL check
+; FX33 writes the hundreds, tens and units
+    LD V5, 254
+    LD I, scratch
+    LD B, V5
+    LD I, scratch
+    LD V2, [I]
+    LD V3, V1
+    LD V4, V2
+    LD V1, 2
+    CALL check
+    LD V0, V3
+    LD V1, 5
+    CALL check
+    LD V0, V4
+    LD V1, 4
+    CALL check
+
+; 2NNN and 00EE
+    LD V0, 0
+    CALL set_v0
+    LD V1, 1
+    CALL check
+; BNNN jumps to nnn plus V0
+    LD V0, 2
+    JP V0, table
+table:
+    JP jump_missed
+    JP jump_taken
+jump_missed:
+    LD V0, 0
+    JP jump_done
+jump_taken:
+    LD V0, 1
+jump_done:
+    CALL check
+; CXNN masks the random number
+    RND V0, 0
+    LD V1, 0
+    CALL check
+; DXYN reports a collision when it erases a pixel
+    LD I, data
+    LD V5, 60
+    LD V6, 28
+    DRW V5, V6, 1
+    DRW V5, V6, 1
+    LD V0, VF
+    LD V1, 1
+    CALL check
+
+end:
+    JP end
+
+set_v0:
+    LD V0, 1
+    RET
+
+data:
+    db 0x11, 0x22
+scratch:
+    db 0, 0, 0
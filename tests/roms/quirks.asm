@@ -0,0 +1,53 @@
+; Draws what each quirk did as a digit, in the spirit of the quirks test.
+;
+; From left to right: VF after OR, which is 0 when logic resets VF; the result
+; of shifting 0x10 right with Vy holding 0x04, which is 2 when shifts use Vy;
+; the second of two loads, which is 1 when loads increment I; the jump taken
+; by BNNN, which is 1 when it uses Vx.  A line drawn over the right edge is
+; either clipped or wrapped onto the left.
+    CLS
+    LD VA, 1
+    LD VB, 1
+
+    LD VF, 1
+    LD V2, 0x0F
+    OR V2, V2
+    LD V0, VF
+    CALL digit
+
+    LD V0, 0x10
+    LD V2, 0x04
+    SHR V0, V2
+    CALL digit
+
+    LD I, loads
+    LD V0, [I]
+    LD V0, [I]
+    CALL digit
+
+    LD V0, 0
+    LD V2, 2
+    JP V0, table
+table:
+    JP jump_v0
+    JP jump_vx
+jump_v0:
+    LD V0, 0
+    JP jump_done
+jump_vx:
+    LD V0, 1
+jump_done:
+    CALL digit
+
+    LD I, line
+    LD V2, 60
+    LD V3, 20
+    DRW V2, V3, 1
+
+end:
+    JP end
+
+loads:
+    db 0, 1
+line:
+    db 0xFF